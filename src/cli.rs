@@ -0,0 +1,422 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::models::{BOARDS_DIR, Board, Config, GeneralSeitting, LOGICAL_LAYOUT_DIR, LogicalLayout, resources, validate_config};
+use crate::utils::{OsRemapFormat, board_to_kle, cheat_sheet, decompile_firmware, diff_configs, firmware_identity, export_os_remap, import_keymap, import_logical_layout, kle_to_board, load_config, load_history, load_original_installer, merge_configs, write_cheat_sheet, write_config};
+
+const USAGE: &str = "\
+Usage: ku1255-firmware-modifier [COMMAND]
+
+Without a command the GUI is started.
+
+Commands:
+  flatten <config> [-o <output>]   Resolve a config that extends other configs
+                                   into one complete config (stdout by default)
+  diff <old> <new>                 List key, macro, media and setting changes
+  merge <base> <ours> <theirs> -o <output> [--force]
+                                   Combine two edits of a shared base config;
+                                   conflicting entries keep ours. A merge with
+                                   validation errors is only written with --force
+  import <keymap> -b <board> [-o <output>]
+                                   Convert a QMK keymap.c, VIA .json or Vial .vil
+                                   keymap for a board (stdout by default)
+  kle-export <config> [-o <output>]
+                                   Write the config's board and key labels as
+                                   keyboard-layout-editor.com JSON (stdout by default)
+  kle-import <kle.json> -b <new board> [-l <language>] [-o <board.cfg>]
+                                   Create a board definition from KLE JSON
+                                   (boards/<new board>.cfg in the user config
+                                   directory by default)
+  cheat-sheet <config> -o <sheet.svg|sheet.pdf>
+                                   Draw both layers, macro and media keys and the
+                                   Fn trigger as a printable SVG or PDF page
+  os-export <config> <xkb|karabiner|ahk> [-o <output>]
+                                   Write the Main layer and macros as an OS-level
+                                   remapping file (stdout by default)
+  decompile <firmware.bin|installer.exe> [-o <output>]
+                                   Recover the config of a firmware built by this
+                                   app or of the stock firmware (stdout by default)
+  fingerprint <firmware.bin|installer.exe>
+                                   Show the flash checksum and build fingerprint
+                                   and the recorded flashes of the same config
+  board-convert <board.cfg> [-o <board.toml>]
+                                   Convert a board to the structured format with
+                                   per-key positions (stdout by default)
+  check-boards [<board.cfg>...]    Check board definitions and list the problems
+                                   of each file (all of boards/ by default)
+  layout-import <symbols|layout.klc> [<variant>] [-l <base language>] [-o <layout.csv>]
+                                   Create a language from an XKB symbols or Windows
+                                   KLC file (logical_layouts/<name>.csv in the
+                                   user config directory by default)
+  paths                            Show where boards, languages, settings, configs
+                                   and build output are looked for and written
+  help                             Show this message";
+
+/// Run a command line tool and return the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "flatten" => flatten(&args[1..]),
+        "diff" => diff(&args[1..]),
+        "merge" => merge(&args[1..]),
+        "import" => import(&args[1..]),
+        "kle-export" => kle_export(&args[1..]),
+        "kle-import" => kle_import(&args[1..]),
+        "cheat-sheet" => cheat_sheet_command(&args[1..]),
+        "os-export" => os_export(&args[1..]),
+        "decompile" => decompile(&args[1..]),
+        "fingerprint" => fingerprint(&args[1..]),
+        "board-convert" => board_convert(&args[1..]),
+        "check-boards" => check_boards(&args[1..]),
+        "layout-import" => layout_import(&args[1..]),
+        "paths" => {
+            print!("{}", resources());
+            Ok(())
+        }
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("Unknown command '{}'\n\n{}", other, USAGE)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Positional arguments, the values of `-o`/`--output`, `-b`/`--board` and
+/// `-l`/`--layout`, and `-f`/`--force`.
+#[derive(Default)]
+struct Options<'a> {
+    positional: Vec<&'a str>,
+    output: Option<PathBuf>,
+    board: Option<&'a str>,
+    layout: Option<&'a str>,
+    force: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options<'_>, String> {
+    let mut options = Options::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let path = iter.next().ok_or_else(|| format!("{} needs a file path", arg))?;
+                options.output = Some(PathBuf::from(path));
+            }
+            "-b" | "--board" => {
+                options.board = Some(iter.next().ok_or_else(|| format!("{} needs a board name", arg))?);
+            }
+            "-l" | "--layout" => {
+                options.layout = Some(iter.next().ok_or_else(|| format!("{} needs a language name", arg))?);
+            }
+            "-f" | "--force" => options.force = true,
+            _ => options.positional.push(arg.as_str()),
+        }
+    }
+    Ok(options)
+}
+
+/// Write `config` to `output`, or print it as JSON when no output is given.
+fn write_or_print(config: &Config, output: Option<PathBuf>, general_setting: &GeneralSeitting) -> Result<(), String> {
+    match output {
+        Some(path) => write_config(&path, config, general_setting)
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e)),
+        None => {
+            let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+fn flatten(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input] = options.positional[..] else {
+        return Err(format!("flatten takes one config file\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let loaded = load_config(Path::new(input), &general_setting)
+        .map_err(|e| format!("Failed to load {}: {}", input, e))?;
+    eprint!("{}", loaded.report);
+    if loaded.report.has_errors() {
+        return Err(format!("{} was not flattened", input));
+    }
+    write_or_print(&loaded.config, options.output, &general_setting)
+}
+
+fn load(path: &str, general_setting: &GeneralSeitting) -> Result<Config, String> {
+    load_config(Path::new(path), general_setting)
+        .map(|loaded| loaded.config)
+        .map_err(|e| format!("Failed to load {}: {}", path, e))
+}
+
+fn diff(args: &[String]) -> Result<(), String> {
+    let [old, new] = args else {
+        return Err(format!("diff takes two config files\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let diff = diff_configs(&load(old, &general_setting)?, &load(new, &general_setting)?, &general_setting);
+    print!("{}", diff);
+    Ok(())
+}
+
+fn merge(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let (&[base, ours, theirs], Some(output)) = (&options.positional[..], options.output) else {
+        return Err(format!("merge takes three config files and -o <output>\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let merged = merge_configs(
+        &load(base, &general_setting)?,
+        &load(ours, &general_setting)?,
+        &load(theirs, &general_setting)?,
+        &general_setting,
+    );
+    eprint!("{}", merged.report);
+    if merged.report.has_errors() && !options.force {
+        return Err(format!(
+            "The merged config has errors and was not written; fix the inputs or pass --force to write {} anyway",
+            output.display()
+        ));
+    }
+    write_config(&output, &merged.config, &general_setting)
+        .map_err(|e| format!("Failed to save {}: {}", output.display(), e))?;
+    if merged.conflicts.is_empty() {
+        println!("Merged into {}", output.display());
+        return Ok(());
+    }
+    for conflict in &merged.conflicts {
+        eprintln!("Conflict: {}", conflict);
+    }
+    Err(format!(
+        "{} conflict(s); our values were kept in {}",
+        merged.conflicts.len(),
+        output.display()
+    ))
+}
+
+fn import(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let (&[input], Some(board_name)) = (&options.positional[..], options.board) else {
+        return Err(format!("import takes one keymap file and -b <board>\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let board = general_setting
+        .avail_boards
+        .iter()
+        .find(|b| b.board_name == board_name)
+        .ok_or_else(|| format!("Unknown board '{}'", board_name))?;
+    let imported = import_keymap(Path::new(input), board, &general_setting)?;
+    for note in &imported.notes {
+        eprintln!("Note: {}", note);
+    }
+    eprint!("{}", validate_config(&imported.config, &general_setting));
+    write_or_print(&imported.config, options.output, &general_setting)
+}
+
+fn logical_layout<'a>(general_setting: &'a GeneralSeitting, name: &str) -> Result<&'a LogicalLayout, String> {
+    general_setting
+        .avail_logical_layouts
+        .iter()
+        .find(|l| l.layout_name == name)
+        .ok_or_else(|| format!("Unknown language '{}'", name))
+}
+
+fn board<'a>(general_setting: &'a GeneralSeitting, name: &str) -> Result<&'a Board, String> {
+    general_setting
+        .avail_boards
+        .iter()
+        .find(|b| b.board_name == name)
+        .ok_or_else(|| format!("Unknown board '{}'", name))
+}
+
+fn kle_export(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input] = options.positional[..] else {
+        return Err(format!("kle-export takes one config file\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let config = load(input, &general_setting)?;
+    let board = board(&general_setting, &config.physical_layout_name)?;
+    let layout = logical_layout(&general_setting, &config.logical_layout_name)?;
+    let kle = board_to_kle(board, layout, &config.layer0, &config.layer1);
+    let json = serde_json::to_string_pretty(&kle).map_err(|e| e.to_string())?;
+    match options.output {
+        Some(path) => fs::write(&path, json).map_err(|e| format!("Failed to save {}: {}", path.display(), e)),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+fn kle_import(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let (&[input], Some(board_name)) = (&options.positional[..], options.board) else {
+        return Err(format!("kle-import takes one KLE file and -b <new board>\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let layout = logical_layout(&general_setting, options.layout.unwrap_or("US_English"))?;
+    let text = fs::read_to_string(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let kle = serde_json::from_str(&text).map_err(|e| format!("Invalid JSON in {}: {}", input, e))?;
+    let imported = kle_to_board(&kle, board_name, layout, &general_setting)?;
+    for note in &imported.notes {
+        eprintln!("Note: {}", note);
+    }
+    let output = options.output.unwrap_or_else(|| resources().user_config_dir(BOARDS_DIR).join(format!("{}.cfg", board_name)));
+    GeneralSeitting::save_board(&imported.board, &output)
+        .map_err(|e| format!("Failed to save {}: {}", output.display(), e))?;
+    println!("Board '{}' written to {}", board_name, output.display());
+    Ok(())
+}
+
+fn cheat_sheet_command(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let (&[input], Some(output)) = (&options.positional[..], options.output) else {
+        return Err(format!("cheat-sheet takes one config file and -o <output>\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let config = load(input, &general_setting)?;
+    let board = board(&general_setting, &config.physical_layout_name)?;
+    let layout = logical_layout(&general_setting, &config.logical_layout_name)?;
+    let sheet = cheat_sheet(&config, board, layout, &general_setting);
+    write_cheat_sheet(&output, &sheet).map_err(|e| format!("Failed to save {}: {}", output.display(), e))?;
+    println!("Cheat sheet written to {}", output.display());
+    Ok(())
+}
+
+fn os_export(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input, format] = options.positional[..] else {
+        return Err(format!("os-export takes one config file and a format\n\n{}", USAGE));
+    };
+    let format = OsRemapFormat::from_name(format).ok_or_else(|| format!("Unknown format '{}'\n\n{}", format, USAGE))?;
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let config = load(input, &general_setting)?;
+    let board = board(&general_setting, &config.physical_layout_name)?;
+    let remap = export_os_remap(format, &config, board, &general_setting);
+    for item in &remap.unsupported {
+        eprintln!("Not exported: {}", item);
+    }
+    match options.output {
+        Some(path) => fs::write(&path, remap.text).map_err(|e| format!("Failed to save {}: {}", path.display(), e)),
+        None => {
+            print!("{}", remap.text);
+            Ok(())
+        }
+    }
+}
+
+fn decompile(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input] = options.positional[..] else {
+        return Err(format!("decompile takes one firmware file\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let image = fs::read(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let decompiled = decompile_firmware(&load_original_installer()?, &image, &general_setting)?;
+    if decompiled.stock {
+        eprintln!("Note: {} is the stock firmware", input);
+    }
+    if let Some(fingerprint) = &decompiled.fingerprint {
+        eprintln!("Note: built by {}", fingerprint);
+    }
+    for note in &decompiled.notes {
+        eprintln!("Note: {}", note);
+    }
+    write_or_print(&decompiled.config, options.output, &general_setting)
+}
+
+fn fingerprint(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input] = options.positional[..] else {
+        return Err(format!("fingerprint takes one firmware file\n\n{}", USAGE));
+    };
+    let bytes = fs::read(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let (checksum, fingerprint) = firmware_identity(&bytes)?;
+    println!("Checksum: {:#06x}", checksum);
+    let Some(fingerprint) = fingerprint else {
+        println!("No fingerprint; not built by this app or built before fingerprints were added");
+        return Ok(());
+    };
+    println!("Built by: {}", fingerprint);
+    for entry in load_history()? {
+        if entry.config_hash.as_deref().is_some_and(|hash| fingerprint.matches_config_hash(hash)) {
+            let board = entry.config.as_ref().map_or("", |config| config.physical_layout_name.as_str());
+            println!("Flashed: {} {} {} ({})", entry.timestamp, entry.device, board, entry.result);
+        }
+    }
+    Ok(())
+}
+
+fn check_boards(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let diagnostics = if options.positional.is_empty() {
+        general_setting.board_diagnostics.clone()
+    } else {
+        let known_addresses = general_setting.known_addresses();
+        options
+            .positional
+            .iter()
+            .map(|path| GeneralSeitting::check_board(Path::new(path), &known_addresses, &general_setting.avail_logical_layouts).1)
+            .filter(|diagnostic| !diagnostic.report.is_empty())
+            .collect()
+    };
+    for diagnostic in &diagnostics {
+        eprint!("{}", diagnostic);
+    }
+    let failed = diagnostics.iter().filter(|d| d.report.has_errors()).count();
+    if failed > 0 {
+        return Err(format!("{} board file(s) have errors and are not loaded", failed));
+    }
+    if options.positional.is_empty() {
+        let dirs: Vec<String> = resources().dirs(BOARDS_DIR).iter().map(|dir| dir.display().to_string()).collect();
+        println!("{} board(s) loaded from {}", general_setting.avail_boards.len(), dirs.join(", "));
+    }
+    Ok(())
+}
+
+fn board_convert(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let [input] = options.positional[..] else {
+        return Err(format!("board-convert takes one board file\n\n{}", USAGE));
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let board = GeneralSeitting::load_board(Path::new(input)).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let text = GeneralSeitting::board_geometry_to_string(&board, &general_setting.avail_logical_layouts);
+    match options.output {
+        Some(path) => fs::write(&path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn layout_import(args: &[String]) -> Result<(), String> {
+    let options = parse_options(args)?;
+    let (input, variant) = match options.positional[..] {
+        [input] => (input, None),
+        [input, variant] => (input, Some(variant)),
+        _ => return Err(format!("layout-import takes one XKB symbols or KLC file and an optional variant\n\n{}", USAGE)),
+    };
+    let general_setting = GeneralSeitting::load_from_files().map_err(|e| e.to_string())?;
+    let base = logical_layout(&general_setting, options.layout.unwrap_or("US_English"))?;
+    let bytes = fs::read(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    let imported = import_logical_layout(&bytes, input, variant, base)?;
+    for note in &imported.notes {
+        eprintln!("Note: {}", note);
+    }
+    let output = options
+        .output
+        .unwrap_or_else(|| resources().user_config_dir(LOGICAL_LAYOUT_DIR).join(format!("{}.csv", imported.layout.layout_name)));
+    GeneralSeitting::save_logical_layout(&imported.layout, &output)
+        .map_err(|e| format!("Failed to save {}: {}", output.display(), e))?;
+    println!("Language '{}' written to {}", imported.layout.layout_name, output.display());
+    Ok(())
+}
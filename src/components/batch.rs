@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use dioxus::prelude::*;
+use crate::models::{Board, MacroKey};
+use crate::utils::{
+    BatchStatus, BuildSettings, DeviceOutcome, UsbDeviceInfo, list_keyboards, start_batch_install,
+};
+
+/// Build the current configuration once and flash it to several keyboards.
+#[component]
+pub fn BatchDialog(
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+    firmware_future: Resource<Vec<u8>>,
+    fn_id: Signal<u8>,
+    tp_sensitivity: Signal<u32>,
+    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+    media_key_map: Signal<BTreeMap<u8, u16>>,
+    enable_middle_click: Signal<bool>,
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout_name: ReadSignal<String>,
+    show_batch: Signal<bool>,
+) -> Element {
+    let mut devices: Signal<Vec<UsbDeviceInfo>> = use_signal(Vec::new);
+    let mut selected: Signal<Vec<bool>> = use_signal(Vec::new);
+    let mut all_matching = use_signal(|| true);
+    let mut message: Signal<Option<String>> = use_signal(|| None);
+    let batch_status: Signal<Option<BatchStatus>, SyncStorage> = use_signal_sync(|| None);
+
+    let mut refresh = move || match list_keyboards() {
+        Ok(found) => {
+            selected.set(vec![true; found.len()]);
+            devices.set(found);
+            message.set(None);
+        }
+        Err(err) => message.set(Some(err)),
+    };
+    use_hook(refresh);
+
+    let running = matches!(batch_status(), Some(BatchStatus { finished: false, .. }));
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                strong { class: "text-lg font-semibold", "Batch install" }
+                p {
+                    class: "text-sm",
+                    "Builds the current configuration once and flashes it to each keyboard in turn. Failed keyboards are reported and skipped."
+                }
+                label { class: "flex items-center gap-2 text-sm",
+                    input {
+                        r#type: "checkbox",
+                        checked: all_matching(),
+                        disabled: running,
+                        onchange: move |evt| all_matching.set(evt.checked()),
+                    }
+                    "All matching keyboards (scanned when the batch starts)"
+                }
+                if !all_matching() {
+                    div { class: "space-y-1 max-h-40 overflow-y-auto",
+                        if devices().is_empty() {
+                            p { class: "text-sm italic", "No keyboards found." }
+                        }
+                        for (i, device) in devices().into_iter().enumerate() {
+                            label { class: "flex items-center gap-2 text-sm font-mono",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: selected().get(i).copied().unwrap_or(false),
+                                    disabled: running,
+                                    onchange: move |evt| {
+                                        if let Some(flag) = selected.write().get_mut(i) {
+                                            *flag = evt.checked();
+                                        }
+                                    },
+                                }
+                                "{device}"
+                            }
+                        }
+                    }
+                }
+                if let Some(msg) = message() {
+                    p { class: "text-sm font-semibold text-red-600", { msg } }
+                }
+                if let Some(status) = batch_status() {
+                    div { class: "space-y-1",
+                        p { class: "text-sm font-semibold", "{status.stage}" }
+                        if let Some(err) = status.build_error.clone() {
+                            p { class: "text-sm text-red-600", { err } }
+                        }
+                        for note in status.build_notes.iter() {
+                            p { class: "text-sm text-orange-600", "{note}" }
+                        }
+                        for entry in status.entries.iter() {
+                            div {
+                                class: match entry.outcome {
+                                    DeviceOutcome::Verified { .. } => "text-xs font-mono text-green-700",
+                                    DeviceOutcome::Failed(_) => "text-xs font-mono text-red-600",
+                                    _ => "text-xs font-mono",
+                                },
+                                "{entry}"
+                            }
+                        }
+                        if status.finished {
+                            p { class: "text-sm",
+                                "Verified: {status.verified_count()}, failed: {status.failed_count()}"
+                            }
+                        }
+                        if let Some(path) = status.report_path.clone() {
+                            p { class: "text-xs", "Report written to {path}" }
+                        }
+                    }
+                }
+                if running {
+                    p { class: "font-bold text-red-600", "IN PROGRESS. DO NOT unplug any keyboard!" }
+                }
+                div { class: "flex gap-2 justify-end",
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600 disabled:opacity-50",
+                        disabled: running,
+                        onclick: move |_| refresh(),
+                        "Refresh"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600 disabled:opacity-50",
+                        disabled: running,
+                        onclick: move |_| {
+                            let settings = BuildSettings::from_signals(
+                                id_layout_l0,
+                                id_layout_l1,
+                                fn_id,
+                                tp_sensitivity,
+                                macro_key_map,
+                                media_key_map,
+                                enable_middle_click,
+                                selected_board,
+                                selected_logical_layout_name,
+                            );
+                            let targets = if all_matching() {
+                                None
+                            } else {
+                                Some(
+                                    devices()
+                                        .into_iter()
+                                        .zip(selected())
+                                        .filter_map(|(device, flag)| flag.then_some(device))
+                                        .collect(),
+                                )
+                            };
+                            match start_batch_install(firmware_future, settings, targets, batch_status) {
+                                Ok(()) => message.set(None),
+                                Err(err) => message.set(Some(err)),
+                            }
+                        },
+                        "Flash keyboards"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700 disabled:opacity-50",
+                        disabled: running,
+                        onclick: move |_| show_batch.set(false),
+                        "Close"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use dioxus::prelude::*;
+use std::sync::Arc;
+use rfd::FileDialog;
+use crate::models::{GeneralSeitting, BOARDS_DIR, resources};
+use crate::utils::{
+    BoardRecorder,
+    InstallStatus,
+    WIZARD_KEY_UNITS,
+    WizardMode,
+    diagnostic_settings,
+    start_firmware_install,
+    unit_width,
+};
+
+/// Guided creation of a board definition for a part number not in `boards/`:
+/// the user presses every key in turn and the matrix addresses are recorded
+/// row by row.
+#[component]
+pub fn BoardWizardDialog(
+    general_setting: Arc<GeneralSeitting>,
+    firmware_future: Resource<Vec<u8>>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    show_board_wizard: Signal<bool>,
+) -> Element {
+    let default_layout = general_setting.avail_logical_layouts
+        .first()
+        .map(|l| l.layout_name.clone())
+        .unwrap_or_default();
+    let mut board_name = use_signal(String::new);
+    let mut board_label = use_signal(String::new);
+    let mut layout_name = use_signal(|| default_layout);
+    let mut manual_address = use_signal(String::new);
+    let mut status: Signal<Option<String>> = use_signal(|| None);
+    let general_setting_recorder = general_setting.clone();
+    let mut recorder = use_signal(move || {
+        BoardRecorder::new(WizardMode::Stock, &general_setting_recorder).expect("stock mapping")
+    });
+
+    let general_setting_mode = general_setting.clone();
+    let set_mode = use_callback(move |mode: WizardMode| match BoardRecorder::new(mode, &general_setting_mode) {
+        Ok(new_recorder) => {
+            recorder.set(new_recorder);
+            status.set(None);
+        }
+        Err(e) => status.set(Some(e)),
+    });
+    let general_setting_flash = general_setting.clone();
+    let general_setting_save = general_setting.clone();
+    let mode = recorder().mode;
+    let key_name = |address: u8| {
+        general_setting.key_matrix.keys.get(&address).cloned().unwrap_or_default()
+    };
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-3xl w-full space-y-4 max-h-screen overflow-y-auto",
+                strong { class: "text-lg font-semibold", "New board" }
+                p { class: "text-sm",
+                    "Records the matrix address of every key of a keyboard whose part number is not listed yet. "
+                    "Press the keys row by row, left to right, and start a new row at the end of each row."
+                }
+                div { class: "grid grid-cols-3 gap-2 text-sm",
+                    label { "Part number"
+                        input {
+                            class: "w-full px-2 py-1 border border-gray-300 rounded",
+                            r#type: "text",
+                            placeholder: "0B47190",
+                            value: board_name,
+                            oninput: move |evt| board_name.set(evt.value().trim().to_string()),
+                        }
+                    }
+                    label { "Label"
+                        input {
+                            class: "w-full px-2 py-1 border border-gray-300 rounded",
+                            r#type: "text",
+                            placeholder: "US English",
+                            value: board_label,
+                            oninput: move |evt| board_label.set(evt.value()),
+                        }
+                    }
+                    label { "Default language"
+                        select {
+                            class: "w-full px-2 py-1 border border-gray-300 rounded",
+                            value: layout_name,
+                            onchange: move |evt| layout_name.set(evt.value()),
+                            for layout in general_setting.avail_logical_layouts.iter() {
+                                option { value: "{layout.layout_name}", "{layout.layout_label}" }
+                            }
+                        }
+                    }
+                }
+                div { class: "text-sm space-y-1",
+                    label { class: "flex items-center gap-2",
+                        input {
+                            r#type: "radio",
+                            checked: mode == WizardMode::Stock,
+                            onchange: move |_| set_mode.call(WizardMode::Stock),
+                        }
+                        "Stock firmware: keys are looked up in general_setting.csv; the Fn key and other keys that send nothing must be entered by address"
+                    }
+                    label { class: "flex items-center gap-2",
+                        input {
+                            r#type: "radio",
+                            checked: mode == WizardMode::Diagnostic,
+                            onchange: move |_| set_mode.call(WizardMode::Diagnostic),
+                        }
+                        "Diagnostic firmware: every matrix address sends its own key"
+                    }
+                    if mode == WizardMode::Diagnostic {
+                        div { class: "flex items-center gap-2",
+                            button {
+                                class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600",
+                                onclick: move |_| {
+                                    let result = diagnostic_settings(&general_setting_flash)
+                                        .and_then(|settings| start_firmware_install(firmware_future, settings, install_status));
+                                    if let Err(e) = result {
+                                        status.set(Some(e));
+                                    }
+                                },
+                                "Flash diagnostic firmware"
+                            }
+                            span { class: "text-xs",
+                                "Install your own configuration afterwards; the diagnostic keyboard is not usable for typing."
+                            }
+                        }
+                    }
+                }
+                div {
+                    class: "border-2 border-dashed border-gray-300 rounded p-4 text-sm text-center focus:border-blue-500 focus:outline-none",
+                    tabindex: 0,
+                    onkeydown: move |evt| {
+                        evt.prevent_default();
+                        let code = evt.code().to_string();
+                        match recorder.write().press(&code) {
+                            Ok(address) => status.set(Some(format!("{} recorded at address {:02X}", code, address))),
+                            Err(e) => status.set(Some(e)),
+                        }
+                    },
+                    "Click here, then press each key ({recorder().key_count()} recorded)"
+                }
+                if let Some(msg) = status() {
+                    p { class: "text-sm font-semibold", { msg } }
+                }
+                div { class: "flex flex-wrap items-center gap-2 text-sm",
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                        onclick: move |_| recorder.write().new_row(),
+                        "Next row"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                        onclick: move |_| recorder.write().undo(),
+                        "Undo"
+                    }
+                    label { "Width of last key"
+                        select {
+                            class: "ml-1 px-2 py-1 border border-gray-300 rounded",
+                            onchange: move |evt| {
+                                if let Ok(units) = evt.value().parse::<f64>() {
+                                    recorder.write().set_last_width(unit_width(units));
+                                }
+                            },
+                            for units in WIZARD_KEY_UNITS {
+                                option { value: "{units}", "{units}u" }
+                            }
+                        }
+                    }
+                    input {
+                        class: "w-16 px-2 py-1 border border-gray-300 rounded font-mono",
+                        r#type: "text",
+                        placeholder: "hex",
+                        value: manual_address,
+                        oninput: move |evt| manual_address.set(evt.value()),
+                    }
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                        onclick: move |_| {
+                            let result = u8::from_str_radix(manual_address().trim(), 16)
+                                .map_err(|_| format!("'{}' is not a hex address", manual_address()))
+                                .and_then(|address| recorder.write().add_address(address));
+                            match result {
+                                Ok(()) => {
+                                    manual_address.set(String::new());
+                                    status.set(None);
+                                }
+                                Err(e) => status.set(Some(e)),
+                            }
+                        },
+                        "Add address"
+                    }
+                }
+                div { class: "font-mono text-xs space-y-1 max-h-60 overflow-y-auto",
+                    for (i, row) in recorder().rows.iter().enumerate() {
+                        div { class: "flex flex-wrap gap-1",
+                            span { class: "w-6 text-gray-400", "{i + 1}" }
+                            for (address, width) in row.iter() {
+                                span {
+                                    class: "border border-gray-400 rounded px-1",
+                                    title: "{key_name(*address)}",
+                                    "{address:02X} ({width}px)"
+                                }
+                            }
+                        }
+                    }
+                }
+                div { class: "flex gap-2 justify-end",
+                    button {
+                        class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+                        disabled: board_name().is_empty() || recorder().key_count() == 0,
+                        onclick: move |_| {
+                            let Some(path) = FileDialog::new()
+                                .add_filter("Board geometry", &["toml"])
+                                .add_filter("Board config", &["cfg"])
+                                .set_directory(resources().user_config_dir(BOARDS_DIR))
+                                .set_file_name(format!("{}.toml", board_name()))
+                                .set_title("Save board")
+                                .save_file()
+                            else {
+                                return;
+                            };
+                            let board = recorder().to_board(&board_name(), &board_label(), &layout_name(), &general_setting_save);
+                            let saved = if path.extension().is_some_and(|e| e == "cfg") {
+                                GeneralSeitting::save_board(&board, &path)
+                            } else {
+                                GeneralSeitting::save_board_geometry(&board, &path, &general_setting_save.avail_logical_layouts)
+                            };
+                            match saved {
+                                Ok(()) => {
+                                    let (_, diagnostics) = GeneralSeitting::check_board(
+                                        &path,
+                                        &general_setting_save.known_addresses(),
+                                        &general_setting_save.avail_logical_layouts,
+                                    );
+                                    status.set(Some(if diagnostics.report.is_empty() {
+                                        format!("Saved to {}. Restart the app to select it.", path.display())
+                                    } else {
+                                        format!("Saved with problems: {}", diagnostics)
+                                    }));
+                                }
+                                Err(e) => status.set(Some(format!("Failed to save {}: {}", path.display(), e))),
+                            }
+                        },
+                        "Save board"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700",
+                        onclick: move |_| show_board_wizard.set(false),
+                        "Close"
+                    }
+                }
+            }
+        }
+    }
+}
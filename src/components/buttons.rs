@@ -1,11 +1,35 @@
 use dioxus::prelude::*;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 use rfd::FileDialog;
-use crate::models::{MacroKey, Board, LogicalLayout};
+use crate::models::{Config, MacroKey, Board, GeneralSeitting, LogicalLayout, BOARDS_DIR, CONFIGS_DIR, LOGICAL_LAYOUT_DIR, resources};
 use crate::utils::{
-    install_firmware_by_flashsn8,
+    BoardMatch,
+    BuildSettings,
+    InstallStatus,
+    OS_REMAP_FORMATS,
+    OsRemapFormat,
+    CONFIG_VERSION,
+    DecompileStatus,
+    KEYMAP_EXTENSION,
+    IMPORT_EXTENSIONS,
+    LAYOUT_IMPORT_EXTENSIONS,
+    LoadedConfig,
+    OverlayBase,
+    board_to_kle,
+    cheat_sheet,
+    export_os_remap,
+    kle_to_board,
+    import_keymap,
+    import_logical_layout,
+    start_decompile,
+    start_dry_run,
+    start_firmware_install,
     load_config,
     save_config,
+    write_cheat_sheet,
+    write_config_with_overlay,
 };
 
 #[component]
@@ -35,24 +59,28 @@ pub fn ButtonInstall(
     media_key_map: Signal<BTreeMap<u8, u16>>,
     enable_middle_click: Signal<bool>,
     selected_board: ReadSignal<Board>,
+    selected_logical_layout_name: ReadSignal<String>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
     error_msg: Signal<Option<String>>,
 ) -> Element {
     rsx! {
         button {
             class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600",
             onclick: move |_| {
-                install_firmware_by_flashsn8(
+                let settings = BuildSettings::from_signals(
                     id_layout_l0,
                     id_layout_l1,
-                    firmware_future,
                     fn_id,
                     tp_sensitivity,
                     macro_key_map,
                     media_key_map,
                     enable_middle_click,
                     selected_board,
-                    &mut error_msg,
+                    selected_logical_layout_name,
                 );
+                if let Err(err) = start_firmware_install(firmware_future, settings, install_status) {
+                    error_msg.set(Some(err));
+                }
             },
             "Install firmware"
         }
@@ -60,8 +88,146 @@ pub fn ButtonInstall(
 }
 
 
+#[component]
+pub fn ButtonDryRun(
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+    firmware_future: Resource<Vec<u8>>,
+    fn_id: Signal<u8>,
+    tp_sensitivity: Signal<u32>,
+    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+    media_key_map: Signal<BTreeMap<u8, u16>>,
+    enable_middle_click: Signal<bool>,
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout_name: ReadSignal<String>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                let settings = BuildSettings::from_signals(
+                    id_layout_l0,
+                    id_layout_l1,
+                    fn_id,
+                    tp_sensitivity,
+                    macro_key_map,
+                    media_key_map,
+                    enable_middle_click,
+                    selected_board,
+                    selected_logical_layout_name,
+                );
+                let save_path = FileDialog::new()
+                    .add_filter("Text files", &["txt"])
+                    .set_file_name("flash_transcript.txt")
+                    .set_title("Save flash transcript")
+                    .save_file();
+                let Some(path) = save_path else {
+                    println!("Cancel");
+                    return;
+                };
+                if let Err(err) = start_dry_run(firmware_future, settings, path, install_status) {
+                    error_msg.set(Some(err));
+                }
+            },
+            "Dry run"
+        }
+    }
+}
+
+#[component]
+pub fn ButtonRecover(show_recovery: Signal<bool>) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_recovery.set(true),
+            "Recover"
+        }
+    }
+}
+
+#[component]
+pub fn ButtonBoardWizard(show_board_wizard: Signal<bool>) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_board_wizard.set(true),
+            "New board"
+        }
+    }
+}
+
+/// Show where boards, languages, settings, configs and build output are
+/// looked for and written.
+#[component]
+pub fn ButtonPaths() -> Element {
+    let mut show_paths = use_signal(|| false);
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_paths.set(true),
+            "Folders"
+        }
+        if show_paths() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-3xl w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Folders" }
+                    p { class: "text-sm",
+                        "Files in the user config folder are used before the bundled ones: boards and languages are added to the bundled ones, and a file such as settings/url.txt replaces the bundled file of the same name."
+                    }
+                    pre { class: "text-xs whitespace-pre-wrap break-all", "{resources()}" }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| show_paths.set(false),
+                            "OK"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+pub fn ButtonBatch(show_batch: Signal<bool>) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_batch.set(true),
+            "Batch install"
+        }
+    }
+}
+
+#[component]
+pub fn ButtonHistory(show_history: Signal<bool>) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_history.set(true),
+            "History"
+        }
+    }
+}
+
+#[component]
+pub fn ButtonCompare(show_compare: Signal<bool>) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| show_compare.set(true),
+            "Compare"
+        }
+    }
+}
+
 #[component]
 pub fn ButtonLoad(
+    general_setting: Arc<GeneralSeitting>,
     selected_board_name: Signal<String>,
     selected_logical_layout_name: Signal<String>,
     id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
@@ -71,45 +237,48 @@ pub fn ButtonLoad(
     macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
     media_key_map: Signal<BTreeMap<u8, u16>>,
     enable_middle_click: Signal<bool>,
+    overlay_base: Signal<Option<OverlayBase>>,
+    error_msg: Signal<Option<String>>,
 ) -> Element {
+    // Applied config with warnings or an upgrade, kept for the notice below
+    let mut notice: Signal<Option<(PathBuf, LoadedConfig)>> = use_signal(|| None);
+
     rsx! {
         button {
             class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
             onclick: move |_| {
                 let file = FileDialog::new()
-                    .add_filter("Config files", &["json"])
-                    .set_directory(
-                        std::env::current_exe().unwrap().parent().unwrap().join("examples"),
-                    )
+                    .add_filter("Config files", &["json", KEYMAP_EXTENSION])
+                    .set_directory(resources().config_dialog_dir())
                     .set_title("Select key-remapping file")
                     .pick_file();
                 match file {
                     Some(path) => {
-                        match load_config(&path) {
-                            Ok(
-                                (
-                                    loaded_board_name,
-                                    loaded_logical_layout_name,
-                                    loaded_id_layout_l0,
-                                    loaded_id_layout_l1,
-                                    loaded_fn_id,
-                                    loaded_tp_sensitivity,
-                                    loaded_macro_key_map,
-                                    loaded_media_key_map,
-                                    loaded_enable_middle_click,
-                                ),
-                            ) => {
-                                selected_board_name.set(loaded_board_name);
-                                selected_logical_layout_name.set(loaded_logical_layout_name);
-                                id_layout_l0.set(loaded_id_layout_l0);
-                                id_layout_l1.set(loaded_id_layout_l1);
-                                fn_id.set(loaded_fn_id);
-                                tp_sensitivity.set(loaded_tp_sensitivity);
-                                macro_key_map.set(loaded_macro_key_map);
-                                media_key_map.set(loaded_media_key_map);
-                                enable_middle_click.set(loaded_enable_middle_click);
+                        match load_config(&path, &general_setting) {
+                            Ok(loaded) if loaded.report.has_errors() => {
+                                error_msg.set(Some(format!(
+                                    "{} was not loaded:\n{}",
+                                    path.display(),
+                                    loaded.report
+                                )));
+                            }
+                            Ok(loaded) => {
+                                let config = &loaded.config;
+                                selected_board_name.set(config.physical_layout_name.clone());
+                                selected_logical_layout_name.set(config.logical_layout_name.clone());
+                                id_layout_l0.set(config.layer0.clone());
+                                id_layout_l1.set(config.layer1.clone());
+                                fn_id.set(config.fn_id);
+                                tp_sensitivity.set(config.tp_sensitivity);
+                                macro_key_map.set(config.macro_key_map.clone());
+                                media_key_map.set(config.media_key_map.clone());
+                                enable_middle_click.set(config.enable_middle_click);
+                                overlay_base.set(loaded.overlay.clone());
+                                if loaded.was_upgraded() || !loaded.report.is_empty() {
+                                    notice.set(Some((path, loaded)));
+                                }
                             }
-                            Err(e) => eprintln!("Failed to load file: {}", e),
+                            Err(e) => error_msg.set(Some(format!("Failed to load {}: {}", path.display(), e))),
                         };
                     }
                     None => println!("file not selected"),
@@ -117,11 +286,240 @@ pub fn ButtonLoad(
             },
             "Load config"
         }
+        if let Some((path, loaded)) = notice() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-md w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Config loaded" }
+                    if !loaded.report.warnings.is_empty() {
+                        ul { class: "text-sm text-orange-600 list-disc pl-4",
+                            for warning in loaded.report.warnings.iter() {
+                                li { "{warning}" }
+                            }
+                        }
+                    }
+                    if loaded.was_upgraded() {
+                        p { class: "text-sm",
+                            "{path.display()} was written with config_version {loaded.original_version} and has been upgraded to {CONFIG_VERSION}. Save the upgraded file back?"
+                        }
+                    }
+                    div { class: "flex gap-2 justify-end",
+                        if loaded.was_upgraded() {
+                            button {
+                                class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+                                onclick: {
+                                    let path = path.clone();
+                                    let config = loaded.config.clone();
+                                    let overlay = loaded.overlay.clone();
+                                    let general_setting = general_setting.clone();
+                                    move |_| {
+                                        if let Err(e) = write_config_with_overlay(&path, &config, overlay.as_ref(), &general_setting) {
+                                            error_msg.set(Some(format!("Failed to save {}: {}", path.display(), e)));
+                                        }
+                                        notice.set(None);
+                                    }
+                                },
+                                "Save upgraded file"
+                            }
+                        }
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| notice.set(None),
+                            if loaded.was_upgraded() { "Keep original" } else { "OK" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Import a QMK, VIA or Vial keymap onto the selected board. Layers, macro
+/// keys and media keys are replaced; other settings are kept.
+#[component]
+pub fn ButtonImport(
+    general_setting: Arc<GeneralSeitting>,
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout_name: Signal<String>,
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+    media_key_map: Signal<BTreeMap<u8, u16>>,
+    overlay_base: Signal<Option<OverlayBase>>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    // Notes of the last import, shown until dismissed
+    let mut notes: Signal<Option<Vec<String>>> = use_signal(|| None);
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("QMK, VIA or Vial keymap", &IMPORT_EXTENSIONS)
+                    .set_title("Select keymap to import")
+                    .pick_file()
+                else {
+                    return;
+                };
+                match import_keymap(&path, &selected_board(), &general_setting) {
+                    Ok(imported) => {
+                        let config = imported.config;
+                        selected_logical_layout_name.set(config.logical_layout_name);
+                        id_layout_l0.set(config.layer0);
+                        id_layout_l1.set(config.layer1);
+                        macro_key_map.set(config.macro_key_map);
+                        media_key_map.set(config.media_key_map);
+                        overlay_base.set(None);
+                        notes.set(Some(imported.notes));
+                    }
+                    Err(e) => error_msg.set(Some(format!("Failed to import {}: {}", path.display(), e))),
+                }
+            },
+            "Import keymap"
+        }
+        if let Some(list) = notes() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Keymap imported" }
+                    if list.is_empty() {
+                        p { class: "text-sm", "Every key could be imported." }
+                    } else {
+                        p { class: "text-sm", "Some keys could not be imported as they were:" }
+                        ul { class: "text-sm text-orange-600 list-disc pl-4 max-h-80 overflow-y-auto",
+                            for note in list.iter() {
+                                li { "{note}" }
+                            }
+                        }
+                    }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| notes.set(None),
+                            "OK"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Recover the configuration of a firmware binary or installer built by this app,
+/// or of the stock firmware, and load it into the editor.
+#[component]
+pub fn ButtonDecompile(
+    general_setting: Arc<GeneralSeitting>,
+    firmware_future: Resource<Vec<u8>>,
+    selected_board_name: Signal<String>,
+    selected_logical_layout_name: Signal<String>,
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+    fn_id: Signal<u8>,
+    tp_sensitivity: Signal<u32>,
+    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+    media_key_map: Signal<BTreeMap<u8, u16>>,
+    enable_middle_click: Signal<bool>,
+    overlay_base: Signal<Option<OverlayBase>>,
+    detected_board: Signal<Option<BoardMatch>>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    // Background decompilation and, once finished, its result until dismissed
+    let mut decompile_status: Signal<Option<DecompileStatus>, SyncStorage> = use_signal_sync(|| None);
+
+    use_effect(move || match decompile_status() {
+        Some(DecompileStatus::Finished(_, decompiled)) => {
+            let config = &decompiled.config;
+            selected_board_name.set(config.physical_layout_name.clone());
+            selected_logical_layout_name.set(config.logical_layout_name.clone());
+            id_layout_l0.set(config.layer0.clone());
+            id_layout_l1.set(config.layer1.clone());
+            fn_id.set(config.fn_id);
+            tp_sensitivity.set(config.tp_sensitivity);
+            macro_key_map.set(config.macro_key_map.clone());
+            media_key_map.set(config.media_key_map.clone());
+            enable_middle_click.set(config.enable_middle_click);
+            overlay_base.set(None);
+            detected_board.set(decompiled.board_match.clone());
+        }
+        Some(DecompileStatus::Failed(msg)) => {
+            decompile_status.set(None);
+            error_msg.set(Some(msg));
+        }
+        _ => {}
+    });
+
+    let running = matches!(decompile_status(), Some(DecompileStatus::Running(_)));
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600 disabled:opacity-50",
+            disabled: running,
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("Firmware or installer", &["bin", "exe"])
+                    .set_title("Select firmware to decompile")
+                    .pick_file()
+                else {
+                    return;
+                };
+                if let Err(err) = start_decompile(firmware_future, path, general_setting.clone(), decompile_status) {
+                    error_msg.set(Some(err));
+                }
+            },
+            "Decompile firmware"
+        }
+        if let Some(DecompileStatus::Running(path)) = decompile_status() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Decompiling firmware" }
+                    p { class: "text-sm", "Locating the settings in {path.display()}..." }
+                }
+            }
+        }
+        if let Some(DecompileStatus::Finished(path, decompiled)) = decompile_status() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Firmware decompiled" }
+                    p { class: "text-sm",
+                        if decompiled.stock {
+                            "{path.display()} is the stock firmware. Its key map has been loaded."
+                        } else {
+                            "The configuration of {path.display()} has been loaded. Save it to keep a config file."
+                        }
+                    }
+                    if let Some(fingerprint) = &decompiled.fingerprint {
+                        p { class: "text-sm", "Built by {fingerprint}" }
+                    }
+                    if let Some(found) = &decompiled.board_match {
+                        p { class: "text-sm", "Keyboard: {found}" }
+                    }
+                    if !decompiled.notes.is_empty() {
+                        ul { class: "text-sm text-orange-600 list-disc pl-4",
+                            for note in decompiled.notes.iter() {
+                                li { "{note}" }
+                            }
+                        }
+                    }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| decompile_status.set(None),
+                            "OK"
+                        }
+                    }
+                }
+            }
+        }
     }
 }
 
 #[component]
 pub fn ButtonSave(
+    general_setting: Arc<GeneralSeitting>,
     selected_board: ReadSignal<Board>,
     selected_logical_layout: Memo<LogicalLayout>,
     id_layout_l0: ReadSignal<BTreeMap<u8, Option<u8>>>,
@@ -131,6 +529,7 @@ pub fn ButtonSave(
     macro_key_map: ReadSignal<BTreeMap<u8, MacroKey>>,
     media_key_map: ReadSignal<BTreeMap<u8, u16>>,
     enable_middle_click: ReadSignal<bool>,
+    overlay_base: ReadSignal<Option<OverlayBase>>,
 ) -> Element {
     rsx! {
         button {
@@ -138,16 +537,14 @@ pub fn ButtonSave(
             onclick: move |_| {
                 let save_path = FileDialog::new()
                     .add_filter("JSON files", &["json"])
-                    .set_directory(
-                        std::env::current_exe().unwrap().parent().unwrap().join("examples"),
-                    )
+                    .add_filter("Readable keymap", &[KEYMAP_EXTENSION])
+                    .set_directory(resources().user_data_dir(CONFIGS_DIR))
                     .set_file_name("config.json")
                     .set_title("Set config filepath")
                     .save_file();
                 match save_path {
                     Some(path) => {
-                        println!("Config file has been saved to: {}", path.display());
-                        let _ = save_config(
+                        let result = save_config(
                             &path,
                             &selected_board().board_name,
                             &selected_logical_layout().layout_name,
@@ -158,7 +555,13 @@ pub fn ButtonSave(
                             &macro_key_map(),
                             &media_key_map(),
                             enable_middle_click(),
+                            overlay_base().as_ref(),
+                            &general_setting,
                         );
+                        match result {
+                            Ok(()) => println!("Config file has been saved to: {}", path.display()),
+                            Err(e) => eprintln!("Failed to save {}: {}", path.display(), e),
+                        }
                     }
                     None => println!("Cancel"),
                 }
@@ -166,4 +569,346 @@ pub fn ButtonSave(
             "Save config"
         }
     }
-}
\ No newline at end of file
+}
+
+/// Shown while the loaded config extends a base. Flattening makes the next
+/// save write the complete config instead of only the overrides.
+#[component]
+pub fn ButtonFlatten(overlay_base: Signal<Option<OverlayBase>>) -> Element {
+    rsx! {
+        if let Some(overlay) = overlay_base() {
+            span {
+                class: "text-sm text-gray-200",
+                title: "{overlay.base_path.display()}",
+                "Extends {overlay.extend}"
+            }
+            button {
+                class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                onclick: move |_| overlay_base.set(None),
+                "Flatten"
+            }
+        }
+    }
+}
+
+/// Save the selected board with both layers' labels as keyboard-layout-editor.com JSON.
+#[component]
+pub fn ButtonExportKle(
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout: Memo<LogicalLayout>,
+    id_layout_l0: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("KLE JSON", &["json"])
+                    .set_file_name(format!("{}_kle.json", selected_board().board_name))
+                    .set_title("Export keyboard-layout-editor JSON")
+                    .save_file()
+                else {
+                    return;
+                };
+                let kle = board_to_kle(&selected_board(), &selected_logical_layout(), &id_layout_l0(), &id_layout_l1());
+                let result = serde_json::to_string_pretty(&kle)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+                match result {
+                    Ok(()) => println!("KLE layout has been saved to: {}", path.display()),
+                    Err(e) => error_msg.set(Some(format!("Failed to save {}: {}", path.display(), e))),
+                }
+            },
+            "Export KLE"
+        }
+    }
+}
+
+/// Create a board definition in `boards/` from keyboard-layout-editor.com JSON,
+/// matching legends with the selected language.
+#[component]
+pub fn ButtonImportKle(
+    general_setting: Arc<GeneralSeitting>,
+    selected_logical_layout: Memo<LogicalLayout>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    // Saved board file and notes of the last import
+    let mut result: Signal<Option<(PathBuf, Vec<String>)>> = use_signal(|| None);
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("KLE JSON", &["json"])
+                    .set_title("Select keyboard-layout-editor JSON")
+                    .pick_file()
+                else {
+                    return;
+                };
+                let board_name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                let imported = std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()))
+                    .and_then(|kle| kle_to_board(&kle, &board_name, &selected_logical_layout(), &general_setting));
+                let board_path = resources().user_config_dir(BOARDS_DIR).join(format!("{}.cfg", board_name));
+                let saved = imported.and_then(|imported| {
+                    GeneralSeitting::save_board(&imported.board, &board_path)
+                        .map(|()| imported.notes)
+                        .map_err(|e| e.to_string())
+                });
+                match saved {
+                    Ok(notes) => result.set(Some((board_path, notes))),
+                    Err(e) => error_msg.set(Some(format!("Failed to import {}: {}", path.display(), e))),
+                }
+            },
+            "Import KLE"
+        }
+        if let Some((board_path, notes)) = result() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Board created" }
+                    p { class: "text-sm",
+                        "The board has been saved to {board_path.display()}. Restart the app to select it."
+                    }
+                    if !notes.is_empty() {
+                        ul { class: "text-sm text-orange-600 list-disc pl-4 max-h-80 overflow-y-auto",
+                            for note in notes.iter() {
+                                li { "{note}" }
+                            }
+                        }
+                    }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| result.set(None),
+                            "OK"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Create a language from an XKB symbols or Windows KLC file.
+#[component]
+pub fn ButtonImportLayout(
+    selected_logical_layout: Memo<LogicalLayout>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    // Saved layout file and notes of the last import
+    let mut result: Signal<Option<(PathBuf, Vec<String>)>> = use_signal(|| None);
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("XKB symbols or KLC", &LAYOUT_IMPORT_EXTENSIONS)
+                    .add_filter("All files", &["*"])
+                    .set_title("Select XKB symbols or KLC file")
+                    .pick_file()
+                else {
+                    return;
+                };
+                let file_name = path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                let saved = std::fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|bytes| import_logical_layout(&bytes, &file_name, None, &selected_logical_layout()))
+                    .and_then(|imported| {
+                        let layout_path = resources().user_config_dir(LOGICAL_LAYOUT_DIR).join(format!("{}.csv", imported.layout.layout_name));
+                        GeneralSeitting::save_logical_layout(&imported.layout, &layout_path)
+                            .map(|()| (layout_path, imported.notes))
+                            .map_err(|e| e.to_string())
+                    });
+                match saved {
+                    Ok(saved) => result.set(Some(saved)),
+                    Err(e) => error_msg.set(Some(format!("Failed to import {}: {}", path.display(), e))),
+                }
+            },
+            "Import language"
+        }
+        if let Some((layout_path, notes)) = result() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Language created" }
+                    p { class: "text-sm",
+                        "The language has been saved to {layout_path.display()}. Keys the file does not define keep the labels of the selected language. Restart the app to select it."
+                    }
+                    if !notes.is_empty() {
+                        ul { class: "text-sm text-orange-600 list-disc pl-4 max-h-80 overflow-y-auto",
+                            for note in notes.iter() {
+                                li { "{note}" }
+                            }
+                        }
+                    }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                            onclick: move |_| result.set(None),
+                            "OK"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Save a printable SVG or PDF cheat sheet of the current configuration.
+#[component]
+pub fn ButtonCheatSheet(
+    general_setting: Arc<GeneralSeitting>,
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout: Memo<LogicalLayout>,
+    id_layout_l0: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    fn_id: ReadSignal<u8>,
+    tp_sensitivity: ReadSignal<u32>,
+    macro_key_map: ReadSignal<BTreeMap<u8, MacroKey>>,
+    media_key_map: ReadSignal<BTreeMap<u8, u16>>,
+    enable_middle_click: ReadSignal<bool>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                let Some(path) = FileDialog::new()
+                    .add_filter("SVG image", &["svg"])
+                    .add_filter("PDF document", &["pdf"])
+                    .set_file_name(format!("{}_cheat_sheet.svg", selected_board().board_name))
+                    .set_title("Save cheat sheet")
+                    .save_file()
+                else {
+                    return;
+                };
+                let config = Config {
+                    config_version: CONFIG_VERSION,
+                    physical_layout_name: selected_board().board_name,
+                    logical_layout_name: selected_logical_layout().layout_name,
+                    layer0: id_layout_l0(),
+                    layer1: id_layout_l1(),
+                    fn_id: fn_id(),
+                    tp_sensitivity: tp_sensitivity(),
+                    macro_key_map: macro_key_map(),
+                    media_key_map: media_key_map(),
+                    enable_middle_click: enable_middle_click(),
+                };
+                let sheet = cheat_sheet(&config, &selected_board(), &selected_logical_layout(), &general_setting);
+                match write_cheat_sheet(&path, &sheet) {
+                    Ok(()) => println!("Cheat sheet has been saved to: {}", path.display()),
+                    Err(e) => error_msg.set(Some(format!("Failed to save {}: {}", path.display(), e))),
+                }
+            },
+            "Cheat sheet"
+        }
+    }
+}
+
+/// Save the Main layer and macros as an xkb, Karabiner-Elements or AutoHotkey file
+/// and list what the OS cannot do.
+#[component]
+pub fn ButtonExportOs(
+    general_setting: Arc<GeneralSeitting>,
+    selected_board: ReadSignal<Board>,
+    selected_logical_layout_name: ReadSignal<String>,
+    id_layout_l0: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: ReadSignal<BTreeMap<u8, Option<u8>>>,
+    fn_id: ReadSignal<u8>,
+    tp_sensitivity: ReadSignal<u32>,
+    macro_key_map: ReadSignal<BTreeMap<u8, MacroKey>>,
+    media_key_map: ReadSignal<BTreeMap<u8, u16>>,
+    enable_middle_click: ReadSignal<bool>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    let mut show = use_signal(|| false);
+    // Saved file and the features it could not express
+    let mut result: Signal<Option<(PathBuf, Vec<String>)>> = use_signal(|| None);
+
+    let export = use_callback(move |format: OsRemapFormat| {
+        let Some(path) = FileDialog::new()
+            .add_filter(format.label(), &[format.extension()])
+            .set_file_name(format!("ku1255_remap.{}", format.extension()))
+            .set_title("Export OS remapping")
+            .save_file()
+        else {
+            return;
+        };
+        let config = Config {
+            config_version: CONFIG_VERSION,
+            physical_layout_name: selected_board().board_name,
+            logical_layout_name: selected_logical_layout_name(),
+            layer0: id_layout_l0(),
+            layer1: id_layout_l1(),
+            fn_id: fn_id(),
+            tp_sensitivity: tp_sensitivity(),
+            macro_key_map: macro_key_map(),
+            media_key_map: media_key_map(),
+            enable_middle_click: enable_middle_click(),
+        };
+        let remap = export_os_remap(format, &config, &selected_board(), &general_setting);
+        match std::fs::write(&path, remap.text) {
+            Ok(()) => result.set(Some((path, remap.unsupported))),
+            Err(e) => error_msg.set(Some(format!("Failed to save {}: {}", path.display(), e))),
+        }
+    });
+
+    rsx! {
+        button {
+            class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+            onclick: move |_| {
+                result.set(None);
+                show.set(true);
+            },
+            "Export OS remap"
+        }
+        if show() {
+            div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+                div {
+                    class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-lg w-full space-y-4",
+                    strong { class: "text-lg font-semibold", "Export OS remapping" }
+                    match result() {
+                        None => rsx! {
+                            p { class: "text-sm",
+                                "Remap keys in the operating system instead of the firmware. Only the Main layer, macro keys and media keys are exported."
+                            }
+                            div { class: "flex flex-col gap-2",
+                                for format in OS_REMAP_FORMATS {
+                                    button {
+                                        class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+                                        onclick: move |_| export.call(format),
+                                        "{format.label()}"
+                                    }
+                                }
+                            }
+                        },
+                        Some((path, unsupported)) => rsx! {
+                            p { class: "text-sm", "Saved to {path.display()}." }
+                            if !unsupported.is_empty() {
+                                p { class: "text-sm", "Not exported:" }
+                                ul { class: "text-sm text-orange-600 list-disc pl-4 max-h-80 overflow-y-auto",
+                                    for item in unsupported.iter() {
+                                        li { "{item}" }
+                                    }
+                                }
+                            }
+                        },
+                    }
+                    div { class: "flex justify-end",
+                        button {
+                            class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700",
+                            onclick: move |_| show.set(false),
+                            "Close"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
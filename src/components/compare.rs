@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use dioxus::prelude::*;
+use rfd::FileDialog;
+use crate::models::{Config, GeneralSeitting, MacroKey, resources};
+use crate::utils::{
+    CONFIG_VERSION, ConfigDiff, DIFF_SECTIONS, KEYMAP_EXTENSION, MergeResult, diff_configs, load_config, merge_configs,
+};
+
+/// What the dialog shows after comparing or merging.
+#[derive(Clone, PartialEq)]
+enum Outcome {
+    Diff(PathBuf, ConfigDiff),
+    /// Merge of the current configuration with the edits from `base` to `theirs`
+    Merge { base: PathBuf, theirs: PathBuf, merged: Box<MergeResult> },
+    Failed(String),
+}
+
+fn pick_config(title: &str) -> Option<PathBuf> {
+    FileDialog::new()
+        .add_filter("Config files", &["json", KEYMAP_EXTENSION])
+        .set_directory(resources().config_dialog_dir())
+        .set_title(title)
+        .pick_file()
+}
+
+fn load(path: &Path, general_setting: &GeneralSeitting) -> Result<Config, String> {
+    load_config(path, general_setting)
+        .map(|loaded| loaded.config)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))
+}
+
+/// Changes from a config file to the current configuration, by key name, and
+/// a three-way merge of someone else's edits into the current configuration.
+#[component]
+pub fn CompareDialog(
+    general_setting: Arc<GeneralSeitting>,
+    selected_board_name: Signal<String>,
+    selected_logical_layout_name: Signal<String>,
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+    fn_id: Signal<u8>,
+    tp_sensitivity: Signal<u32>,
+    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+    media_key_map: Signal<BTreeMap<u8, u16>>,
+    enable_middle_click: Signal<bool>,
+    show_compare: Signal<bool>,
+) -> Element {
+    let mut outcome: Signal<Option<Outcome>> = use_signal(|| None);
+
+    let current = move || Config {
+            config_version: CONFIG_VERSION,
+            physical_layout_name: selected_board_name(),
+            logical_layout_name: selected_logical_layout_name(),
+            layer0: id_layout_l0(),
+            layer1: id_layout_l1(),
+            fn_id: fn_id(),
+            tp_sensitivity: tp_sensitivity(),
+            macro_key_map: macro_key_map(),
+            media_key_map: media_key_map(),
+            enable_middle_click: enable_middle_click(),
+    };
+
+    let general_setting_compare = general_setting.clone();
+    let compare = move |_| {
+        let Some(path) = pick_config("Select config to compare with") else {
+            return;
+        };
+        outcome.set(Some(match load(&path, &general_setting_compare) {
+            Ok(config) => Outcome::Diff(path, diff_configs(&config, &current(), &general_setting_compare)),
+            Err(err) => Outcome::Failed(err),
+        }));
+    };
+
+    let general_setting_merge = general_setting.clone();
+    let merge = move |_| {
+        let Some(base) = pick_config("Select the shared base config") else {
+            return;
+        };
+        let Some(theirs) = pick_config("Select the edited config to merge in") else {
+            return;
+        };
+        let loaded = load(&base, &general_setting_merge).and_then(|b| Ok((b, load(&theirs, &general_setting_merge)?)));
+        outcome.set(Some(match loaded {
+            Ok((base_config, theirs_config)) => {
+                let merged = merge_configs(&base_config, &current(), &theirs_config, &general_setting_merge);
+                Outcome::Merge { base, theirs, merged: Box::new(merged) }
+            }
+            Err(err) => Outcome::Failed(err),
+        }));
+    };
+
+    let mut apply = move |config: Config| {
+        selected_board_name.set(config.physical_layout_name);
+        selected_logical_layout_name.set(config.logical_layout_name);
+        id_layout_l0.set(config.layer0);
+        id_layout_l1.set(config.layer1);
+        fn_id.set(config.fn_id);
+        tp_sensitivity.set(config.tp_sensitivity);
+        macro_key_map.set(config.macro_key_map);
+        media_key_map.set(config.media_key_map);
+        enable_middle_click.set(config.enable_middle_click);
+        show_compare.set(false);
+    };
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-30",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-2xl w-full space-y-4",
+                strong { class: "text-lg font-semibold", "Compare and merge configs" }
+                match outcome() {
+                    None => rsx! {
+                        p { class: "text-sm", "Pick a config file to list its differences from the current configuration." }
+                        p { class: "text-sm",
+                            "To merge, pick the config both sides started from and the other side's edited config. Their edits are applied on top of the current configuration."
+                        }
+                    },
+                    Some(Outcome::Failed(err)) => rsx! {
+                        p { class: "text-sm text-red-600", { err } }
+                    },
+                    Some(Outcome::Merge { base, theirs, merged }) => rsx! {
+                        p { class: "text-sm",
+                            "Edits from {base.display()} to {theirs.display()}, merged into the current configuration:"
+                        }
+                        if merged.conflicts.is_empty() {
+                            p { class: "text-sm italic", "No conflicts." }
+                        } else {
+                            p { class: "text-sm", "Changed on both sides; the current values are kept:" }
+                            ul { class: "max-h-48 overflow-y-auto text-xs text-orange-600 list-disc pl-4",
+                                for conflict in merged.conflicts.iter() {
+                                    li { "{conflict}" }
+                                }
+                            }
+                        }
+                        if !merged.report.is_empty() {
+                            ul { class: "text-xs list-disc pl-4",
+                                for error in merged.report.errors.iter() {
+                                    li { class: "text-red-600", "{error}" }
+                                }
+                                for warning in merged.report.warnings.iter() {
+                                    li { class: "text-orange-600", "{warning}" }
+                                }
+                            }
+                        }
+                        if merged.report.has_errors() {
+                            p { class: "text-sm text-red-600", "The merge has errors and cannot be applied." }
+                        } else {
+                            div { class: "flex justify-end",
+                                button {
+                                    class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600",
+                                    onclick: {
+                                        let config = merged.config.clone();
+                                        move |_| apply(config.clone())
+                                    },
+                                    "Apply merge"
+                                }
+                            }
+                        }
+                    },
+                    Some(Outcome::Diff(path, diff)) => rsx! {
+                        p { class: "text-sm", "Changes from {path.display()} to the current configuration:" }
+                        if diff.is_empty() {
+                            p { class: "text-sm italic", "No differences." }
+                        }
+                        div { class: "max-h-96 overflow-y-auto space-y-2",
+                            for section in DIFF_SECTIONS {
+                                if !diff.section(section).is_empty() {
+                                    div {
+                                        strong { class: "text-sm", "{section}" }
+                                        table { class: "w-full text-xs",
+                                            tbody {
+                                                for change in diff.section(section) {
+                                                    tr { class: "border-t",
+                                                        td { class: "pr-2 w-1/3", "{change.item}" }
+                                                        td { class: "pr-2 w-1/3 text-red-600",
+                                                            { change.old.clone().unwrap_or_default() }
+                                                        }
+                                                        td { class: "w-1/3 text-green-700",
+                                                            { change.new.clone().unwrap_or_default() }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+                div { class: "flex gap-2 justify-end",
+                    button {
+                        class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+                        onclick: compare,
+                        "Compare with file..."
+                    }
+                    button {
+                        class: "px-4 py-2 bg-green-500 text-white rounded shadow hover:bg-green-600",
+                        onclick: merge,
+                        "Merge edits..."
+                    }
+                    button {
+                        class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700",
+                        onclick: move |_| show_compare.set(false),
+                        "Close"
+                    }
+                }
+            }
+        }
+    }
+}
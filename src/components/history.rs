@@ -0,0 +1,117 @@
+use dioxus::prelude::*;
+use crate::utils::{HistoryEntry, InstallStatus, history_image_identity, load_history, start_history_reflash};
+
+/// Journal of earlier flashes, newest first. Any entry, or the stock
+/// firmware, can be flashed again from here.
+#[component]
+pub fn HistoryDialog(
+    firmware_future: Resource<Vec<u8>>,
+    show_history: Signal<bool>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    let history: Signal<Result<Vec<HistoryEntry>, String>> = use_signal(load_history);
+
+    let mut reflash = move |entry: Option<HistoryEntry>| {
+        match start_history_reflash(firmware_future, entry, install_status) {
+            Ok(()) => show_history.set(false),
+            Err(err) => error_msg.set(Some(err)),
+        }
+    };
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-30",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-4xl w-full space-y-4",
+                strong { class: "text-lg font-semibold", "Flash history" }
+                match history() {
+                    Err(err) => rsx! { p { class: "text-sm text-red-600", { err } } },
+                    Ok(entries) if entries.is_empty() => rsx! {
+                        p { class: "text-sm italic", "No keyboard has been flashed yet." }
+                    },
+                    Ok(entries) => rsx! {
+                        div { class: "max-h-96 overflow-y-auto",
+                            table { class: "w-full text-xs",
+                                thead {
+                                    tr { class: "text-left",
+                                        th { "Time" }
+                                        th { "Device" }
+                                        th { "Firmware" }
+                                        th { "Image" }
+                                        th { "Checksum" }
+                                        th { "Fingerprint" }
+                                        th { "Result" }
+                                        th {}
+                                    }
+                                }
+                                tbody {
+                                    for (entry, identity) in entries.into_iter().rev().map(|entry| {
+                                        let identity = history_image_identity(&entry);
+                                        (entry, identity)
+                                    }) {
+                                        tr { class: "border-t",
+                                            td { class: "pr-2 whitespace-nowrap", "{entry.timestamp}" }
+                                            td { class: "pr-2 font-mono", "{entry.device}" }
+                                            td { class: "pr-2",
+                                                if entry.is_stock() {
+                                                    "Stock"
+                                                } else if let Some(config) = &entry.config {
+                                                    "{config.physical_layout_name}"
+                                                }
+                                            }
+                                            td { class: "pr-2 font-mono", title: "{entry.image_hash}",
+                                                { entry.image_hash.chars().take(12).collect::<String>() }
+                                            }
+                                            match &identity {
+                                                Ok((checksum, fingerprint)) => rsx! {
+                                                    td { class: "pr-2 font-mono", "{checksum:#06x}" }
+                                                    td { class: "pr-2",
+                                                        if let Some(fingerprint) = fingerprint {
+                                                            "{fingerprint}"
+                                                        } else {
+                                                            "-"
+                                                        }
+                                                    }
+                                                },
+                                                Err(err) => rsx! {
+                                                    td { class: "pr-2 text-red-600", colspan: 2, title: "{err}", "Image missing" }
+                                                },
+                                            }
+                                            td {
+                                                class: if entry.verified { "pr-2 text-green-700" } else { "pr-2 text-red-600" },
+                                                title: "{entry.result}",
+                                                if entry.verified { "✓ Verified" } else { "✗ Failed" }
+                                            }
+                                            td {
+                                                button {
+                                                    class: "px-2 py-1 bg-blue-500 text-white rounded shadow hover:bg-blue-600",
+                                                    onclick: {
+                                                        let entry = entry.clone();
+                                                        move |_| reflash(Some(entry.clone()))
+                                                    },
+                                                    "Re-flash"
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                }
+                div { class: "flex gap-2 justify-end",
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600",
+                        onclick: move |_| reflash(None),
+                        "Flash stock firmware"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700",
+                        onclick: move |_| show_history.set(false),
+                        "Close"
+                    }
+                }
+            }
+        }
+    }
+}
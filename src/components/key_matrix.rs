@@ -0,0 +1,110 @@
+use dioxus::prelude::*;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use crate::models::{Board, GeneralSeitting, LogicalLayout};
+use crate::components::Popup;
+use crate::utils::board_keymask;
+
+const MATRIX_ROWS: u8 = 16;
+const MATRIX_COLUMNS: u8 = 8;
+
+/// Electrical scan matrix of the selected board. Each cell shows its address,
+/// the key wired there in `keymatrix.csv`, whether the board populates it and
+/// the key on each layer; clicking a layer's key assigns it like the keyboard view.
+#[component]
+pub fn KeyMatrixView(
+    general_setting: Arc<GeneralSeitting>,
+    board: Board,
+    logical_layout: LogicalLayout,
+    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
+    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+) -> Element {
+
+    let mut selected_address_l0 = use_signal(|| None as Option<u8>);
+    let mut selected_address_l1 = use_signal(|| None as Option<u8>);
+
+    let key_matrix = &general_setting.key_matrix;
+    let keymask = board_keymask(&board);
+    let pin = |pins: &Vec<String>, i: u8| pins.get(i as usize).cloned().unwrap_or_else(|| format!("{:X}", i));
+    let key_name = |id: Option<u8>| match id {
+        Some(id) => logical_layout
+            .map_key_label
+            .get(&id)
+            .map(|l| l.default.clone())
+            .filter(|l| !l.is_empty())
+            .or_else(|| general_setting.avail_hid_usage_names.get(&id).cloned())
+            .unwrap_or_else(|| format!("{:02X}", id)),
+        None => "-".to_string(),
+    };
+
+    rsx! {
+        div { class: "overflow-x-auto",
+            table { class: "text-[10px] leading-tight border-separate border-spacing-1",
+                thead {
+                    tr {
+                        th {}
+                        for col in 0..MATRIX_COLUMNS {
+                            th { class: "font-mono text-gray-300", { pin(&key_matrix.column_pins, col) } }
+                        }
+                    }
+                }
+                tbody {
+                    for row in 0..MATRIX_ROWS {
+                        tr {
+                            th { class: "font-mono text-gray-300 pr-2 text-right", { pin(&key_matrix.row_pins, row) } }
+                            for col in 0..MATRIX_COLUMNS {
+                                {
+                                    let address = (row << 4) | col;
+                                    let populated = keymask[row as usize] & (1 << col) != 0;
+                                    let wired = key_matrix.keys.get(&address).cloned().unwrap_or_default();
+                                    rsx! {
+                                        td {
+                                            class: if populated {
+                                                "w-24 border border-sky-300 rounded p-1 align-top"
+                                            } else {
+                                                "w-24 border border-gray-600 rounded p-1 align-top text-gray-500"
+                                            },
+                                            title: if populated { "Populated on {board.board_name}" } else { "Not populated on {board.board_name}" },
+                                            div { class: "flex justify-between",
+                                                span { class: "font-mono font-bold", "{address:02X}" }
+                                                span { class: "text-gray-400", "{wired}" }
+                                            }
+                                            if populated {
+                                                button {
+                                                    class: "block w-full text-left hover:bg-gray-600",
+                                                    onclick: move |_| selected_address_l0.set(Some(address)),
+                                                    "Main: {key_name(id_layout_l0().get(&address).copied().flatten())}"
+                                                }
+                                                button {
+                                                    class: "block w-full text-left hover:bg-gray-600",
+                                                    onclick: move |_| selected_address_l1.set(Some(address)),
+                                                    "2nd: {key_name(id_layout_l1().get(&address).copied().flatten())}"
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Popup {
+                general_setting: general_setting.clone(),
+                layer_number: 0,
+                selected_address: selected_address_l0,
+                id_layout_l0,
+                id_layout_l1,
+                map_key_label: logical_layout.map_key_label.clone(),
+            }
+            Popup {
+                general_setting: general_setting.clone(),
+                layer_number: 1,
+                selected_address: selected_address_l1,
+                id_layout_l0,
+                id_layout_l1,
+                map_key_label: logical_layout.map_key_label.clone(),
+            }
+        }
+    }
+}
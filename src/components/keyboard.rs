@@ -1,7 +1,7 @@
 use dioxus::prelude::*;
 use std::sync::Arc;
 use std::collections::BTreeMap;
-use crate::models::{Board, GeneralSeitting, KeyLabel, LogicalLayout};
+use crate::models::{Board, GeneralSeitting, KeyGeometry, KeyLabel, KeyShape, LogicalLayout, KEY_HEIGHT};
 use crate::components::Popup;
 
 #[component]
@@ -22,140 +22,64 @@ pub fn Keyboard(
             div { class: "text-xl font-bold",
                 {if layer_number == 0 { "Main Layer" } else { "2nd Layer" }}
             }
-            div { class: "space-y-1",
+            div {
+                class: "relative",
+                style: format!(
+                    "width: {}px; height: {}px;",
+                    board.keys.iter().map(|k| k.x + k.w).max().unwrap_or(0),
+                    board.keys.iter().map(|k| k.y + k.h).max().unwrap_or(0),
+                ),
                 {
-                    let al = board.map_address.clone();
-                    let wl = board.map_widths.clone();
-                    al.into_iter().zip(wl.into_iter()).map(|row| rsx! {
-                        div { class: "flex space-x-1",
-                            {
-                                row.0
-                                    .into_iter()
-                                    .zip(row.1.into_iter())
-                                    .map(|(add_opt, width)| {
-                                        let width_str = width.to_string();
-                                        let (id_opt, id_opt_org) = match add_opt {
-                                            Some(address) => {
-                                                (
-                                                    id_layout().get(&address).copied(),
-                                                    if layer_number == 0 {
-                                                        board.initial_id_map.get(&address).copied()
-                                                    } else {
-                                                        id_layout_l0().get(&address).copied()
-                                                    },
-                                                )
-                                            }
-                                            None => (None, None),
-                                        };
-                                        let key_label = match &id_opt {
-                                            Some(Some(kid)) => {
-                                                match logical_layout.map_key_label.get(&kid) {
-                                                    Some(kl) => kl.clone(),
-                                                    None => KeyLabel::new(),
+                    board.keys.clone().into_iter().filter_map(|key| {
+                        let address = key.address;
+                        let kid = id_layout().get(&address).copied()?;
+                        let kid_org = if layer_number == 0 {
+                            board.initial_id_map.get(&address).copied()
+                        } else {
+                            id_layout_l0().get(&address).copied()
+                        };
+                        let key_label = match kid {
+                            Some(kid) => logical_layout.map_key_label.get(&kid).cloned().unwrap_or_else(KeyLabel::new),
+                            None => KeyLabel::new(),
+                        };
+                        let key_default = key_label.default.clone();
+                        let key_shifted = key_label.shifted.clone();
+                        let has_altgr = key_label.has_altgr();
+                        let (border_color, text_color) = key_colors(kid, kid_org);
+                        Some(rsx! {
+                            for (i, (left, top, width, height, sides)) in key_outline(&key).into_iter().enumerate() {
+                                button {
+                                    style: format!("left: {}px; top: {}px; width: {}px; height: {}px;", left, top, width, height),
+                                    class: format!(
+                                        "absolute {} {} {} text-xs flex flex-col items-center justify-center text-center break-words whitespace-normal text-[10px] leading-tight hover:bg-gray-600",
+                                        sides,
+                                        border_color,
+                                        if i == 0 { "px-2 py-1" } else { "" },
+                                    ),
+                                    title: "{key.label}",
+                                    onclick: move |_| selected_address.set(Some(address)),
+                                    if i == 0 && has_altgr {
+                                        div { class: "flex w-full items-center justify-between gap-1",
+                                            div { class: "flex flex-col items-start",
+                                                if !key_shifted.is_empty() {
+                                                    span { class: "text-gray-500 text-[10px] {dead_class(key_label.dead[1])}", "{key_shifted}" }
                                                 }
+                                                span { class: "{text_color} {dead_class(key_label.dead[0])}", "{key_default}" }
                                             }
-                                            _ => KeyLabel::new(),
-                                        };
-                                        let key_default = key_label.default.clone();
-                                        let key_shifted = key_label.shifted.clone();
-                                        if let Some(kid) = id_opt {
-                                            let border_color = match kid {
-                                                None => "border-gray-500",
-                                                Some(0..213) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "border-sky-300"
-                                                    }
-                                                }
-                                                Some(213..224) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "border-violet-300"
-                                                    }
-                                                }
-                                                Some(224..231) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "border-sky-300"
-                                                    }
-                                                }
-                                                Some(231) => "border-rose-300",
-                                                _ => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "border-green-300"
-                                                    }
-                                                }
-                                            };
-                                            let text_color = match kid {
-                                                None => "text-gray-500",
-                                                Some(0..213) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "text-sky-300"
-                                                    }
-                                                }
-                                                Some(213..224) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "text-violet-300"
-                                                    }
-                                                }
-                                                Some(224..231) => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "text-sky-300"
-                                                    }
-                                                }
-                                                Some(231) => "text-rose-300",
-                                                _ => {
-                                                    if kid == id_opt_org.unwrap_or(Some(0)) {
-                                                        ""
-                                                    } else {
-                                                        "text-green-300"
-                                                    }
-                                                }
-                                            };
-                                            rsx! {
-                                                button {
-                                                    style: format!("width: {}px;", width_str),
-                                                    class: format!(
-                                                        "border {} px-2 py-1 h-10 text-xs flex flex-col items-center justify-center text-center break-words whitespace-normal text-[10px] leading-tight hover:bg-gray-600",
-                                                        border_color,
-                                                    ),
-                                                    onclick: move |_| selected_address.set(add_opt),
-                                                    {
-                                                        if key_shifted != "" {
-                                                            rsx! {
-                                                                span { class: format!("text-gray-500 text-[10px]"), "{key_shifted}" }
-                                                                span { class: format!("{}", text_color), "{key_default}" }
-                                                            }
-                                                        } else {
-                                                            rsx! {
-                                                                span { class: format!("{}", text_color), "{key_default}" }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        } else {
-                                            rsx! {
-                                                button {
-                                                    style: format!("width: {}px;", width_str),
-                                                    class: format!("invisible border px-2 py-1 h-10 flex flex-col items-center justify-center"),
-                                                }
+                                            div { class: "flex flex-col items-end text-emerald-300",
+                                                span { class: "{dead_class(key_label.dead[3])}", "{key_label.altgr_shifted}" }
+                                                span { class: "{dead_class(key_label.dead[2])}", "{key_label.altgr}" }
                                             }
                                         }
-                                    })
+                                    } else if i == 0 {
+                                        if key_shifted != "" {
+                                            span { class: "text-gray-500 text-[10px] {dead_class(key_label.dead[1])}", "{key_shifted}" }
+                                        }
+                                        span { class: "{text_color} {dead_class(key_label.dead[0])}", "{key_default}" }
+                                    }
+                                }
                             }
-                        }
+                        })
                     })
                 }
             }
@@ -170,3 +94,35 @@ pub fn Keyboard(
         }
     }
 }
+
+/// Dead keys are underlined with dots.
+fn dead_class(dead: bool) -> &'static str {
+    if dead { "underline decoration-dotted" } else { "" }
+}
+
+/// Border and text colour of a key: remapped keys are coloured by the kind
+/// of key they now send, unmapped keys are grey.
+fn key_colors(kid: Option<u8>, kid_org: Option<Option<u8>>) -> (&'static str, &'static str) {
+    let unchanged = kid == kid_org.unwrap_or(Some(0));
+    match kid {
+        None => ("border-gray-500", "text-gray-500"),
+        Some(231) => ("border-rose-300", "text-rose-300"),
+        _ if unchanged => ("", ""),
+        Some(0..213) | Some(224..231) => ("border-sky-300", "text-sky-300"),
+        Some(213..224) => ("border-violet-300", "text-violet-300"),
+        _ => ("border-green-300", "text-green-300"),
+    }
+}
+
+/// Boxes drawing a key as (left, top, width, height, border sides). An ISO
+/// Enter is an upper box, a lower box and the edge of the notch between them.
+fn key_outline(key: &KeyGeometry) -> Vec<(u16, u16, u16, u16, &'static str)> {
+    match key.shape {
+        KeyShape::IsoEnter => vec![
+            (key.x, key.y, key.w, KEY_HEIGHT, "border border-b-0"),
+            (key.x + key.notch, key.y + KEY_HEIGHT, key.w - key.notch, key.h - KEY_HEIGHT, "border border-t-0"),
+            (key.x, key.y + KEY_HEIGHT - 1, key.notch, 1, "border-b"),
+        ],
+        KeyShape::Rect => vec![(key.x, key.y, key.w, key.h, "border")],
+    }
+}
@@ -8,7 +8,7 @@ pub fn ErrorMessage(msg: String, error_msg: Signal<Option<String>>) -> Element {
                 class: "bg-red-100 border border-red-400 text-red-700 px-6 py-4 rounded-xl shadow-lg max-w-md w-full relative",
                 strong { class: "text-lg font-semibold", "Error" }
                 p {
-                    class: "mt-2",
+                    class: "mt-2 whitespace-pre-line",
                     { msg }
                 }
                 button {
@@ -6,12 +6,26 @@ mod popup;
 mod messages;
 mod macro_key;
 mod media_key;
+mod recovery;
+mod progress;
+mod batch;
+mod history;
+mod compare;
+mod key_matrix;
+mod board_wizard;
 
 pub use keyboard::Keyboard;
 pub use selects::{SelectBoard, SelectLogicalLayout, SelectFnID};
 pub use sliders::SliderTPSensitivity;
-pub use buttons::{ButtonCopyLayer, ButtonInstall, ButtonLoad, ButtonSave};
+pub use buttons::{ButtonBatch, ButtonBoardWizard, ButtonCheatSheet, ButtonCompare, ButtonCopyLayer, ButtonDecompile, ButtonDryRun, ButtonExportKle, ButtonExportOs, ButtonFlatten, ButtonHistory, ButtonImport, ButtonImportKle, ButtonImportLayout, ButtonInstall, ButtonLoad, ButtonPaths, ButtonRecover, ButtonSave};
 pub use popup::Popup;
 pub use messages::ErrorMessage;
 pub use macro_key::MacroKeySetting;
-pub use media_key::MediaKeySetting;
\ No newline at end of file
+pub use media_key::MediaKeySetting;
+pub use recovery::RecoveryDialog;
+pub use progress::InstallProgress;
+pub use batch::BatchDialog;
+pub use history::HistoryDialog;
+pub use compare::CompareDialog;
+pub use key_matrix::KeyMatrixView;
+pub use board_wizard::BoardWizardDialog;
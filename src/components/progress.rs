@@ -0,0 +1,70 @@
+use dioxus::prelude::*;
+use crate::utils::InstallStatus;
+
+/// Progress view for a background build-and-flash.
+/// Failures are handed over to `error_msg`.
+#[component]
+pub fn InstallProgress(
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    error_msg: Signal<Option<String>>,
+) -> Element {
+    use_effect(move || {
+        if let Some(InstallStatus::Failed(msg)) = install_status() {
+            install_status.set(None);
+            error_msg.set(Some(msg));
+        }
+    });
+
+    let Some(status) = install_status() else {
+        return rsx! {};
+    };
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-40",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-md w-full space-y-4",
+                strong { class: "text-lg font-semibold", "Installing firmware" }
+                match status {
+                    InstallStatus::Running(progress) => rsx! {
+                        if progress.do_not_unplug {
+                            p { class: "font-bold text-red-600", "IN PROGRESS. DO NOT unplug the keyboard!" }
+                        } else {
+                            p { class: "font-bold", "Make sure that the keyboard is plugged in." }
+                        }
+                        p { class: "text-sm", "{progress.stage}" }
+                        if progress.erase_total_bytes > 0 {
+                            div { class: "text-xs", "Erased {progress.erased_bytes} / {progress.erase_total_bytes} bytes" }
+                            progress {
+                                class: "w-full",
+                                max: progress.erase_total_bytes as f64,
+                                value: progress.erased_bytes as f64,
+                            }
+                        }
+                        if progress.program_total_bytes > 0 {
+                            div { class: "text-xs", "Programmed {progress.programmed_bytes} / {progress.program_total_bytes} bytes" }
+                            progress {
+                                class: "w-full",
+                                max: progress.program_total_bytes as f64,
+                                value: progress.programmed_bytes as f64,
+                            }
+                        }
+                        if progress.timeout_retries > 0 {
+                            div { class: "text-xs text-orange-600", "USB timeouts retried: {progress.timeout_retries}" }
+                        }
+                    },
+                    InstallStatus::Finished(msg) => rsx! {
+                        p { class: "font-bold text-green-700", "✓ {msg}" }
+                        div { class: "flex justify-end",
+                            button {
+                                class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600",
+                                onclick: move |_| install_status.set(None),
+                                "Close"
+                            }
+                        }
+                    },
+                    InstallStatus::Failed(_) => rsx! {},
+                }
+            }
+        }
+    }
+}
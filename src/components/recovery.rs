@@ -0,0 +1,87 @@
+use dioxus::prelude::*;
+use std::sync::Arc;
+use crate::models::GeneralSeitting;
+use crate::utils::{
+    BoardMatch,
+    DeviceState,
+    RecoveryStatus,
+    start_keyboard_detection,
+    start_keyboard_recovery,
+};
+
+/// Guided recovery for keyboards left in flasher mode by an interrupted flash.
+/// Works from the official installer only; the current keymap is not used.
+/// When the firmware on the keyboard is in the flash history, its board is
+/// selected.
+#[component]
+pub fn RecoveryDialog(
+    general_setting: Arc<GeneralSeitting>,
+    firmware_future: Resource<Vec<u8>>,
+    selected_board_name: Signal<String>,
+    selected_logical_layout_name: Signal<String>,
+    detected_board: Signal<Option<BoardMatch>>,
+    show_recovery: Signal<bool>,
+) -> Element {
+    let mut recovery_status: Signal<Option<RecoveryStatus>, SyncStorage> = use_signal_sync(|| None);
+
+    use_effect(move || {
+        if let Some(RecoveryStatus::Detected { board: Some(found), .. }) = recovery_status() {
+            selected_board_name.set(found.board_name.clone());
+            selected_logical_layout_name.set(found.default_logical_layout_name.clone());
+            detected_board.set(Some(found));
+        }
+    });
+
+    let running = matches!(recovery_status(), Some(RecoveryStatus::Running(_)));
+    let stuck = matches!(recovery_status(), Some(RecoveryStatus::Detected { state: DeviceState::Flasher { .. }, .. }));
+
+    rsx! {
+        div { class: "fixed inset-0 flex items-center justify-center bg-black bg-opacity-50 z-50",
+            div {
+                class: "bg-white text-gray-700 px-6 py-4 rounded-xl shadow-lg max-w-md w-full space-y-4",
+                strong { class: "text-lg font-semibold", "Recover keyboard" }
+                p {
+                    class: "text-sm",
+                    "Restores the stock Lenovo firmware on a keyboard that is stuck in flasher mode after an interrupted installation."
+                }
+                match recovery_status() {
+                    Some(RecoveryStatus::Running(msg) | RecoveryStatus::Finished(msg) | RecoveryStatus::Failed(msg))
+                    | Some(RecoveryStatus::Detected { message: msg, .. }) => rsx! {
+                        p { class: "text-sm font-semibold", { msg } }
+                    },
+                    None => rsx! {},
+                }
+                div { class: "flex gap-2 justify-end",
+                    button {
+                        class: "px-4 py-2 bg-gray-500 text-white rounded shadow hover:bg-gray-600 disabled:opacity-50",
+                        disabled: running,
+                        onclick: {
+                            let general_setting = general_setting.clone();
+                            move |_| {
+                                if let Err(err) = start_keyboard_detection(firmware_future, general_setting.clone(), recovery_status) {
+                                    recovery_status.set(Some(RecoveryStatus::Failed(err)));
+                                }
+                            }
+                        },
+                        "Detect"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-blue-500 text-white rounded shadow hover:bg-blue-600 disabled:opacity-50",
+                        disabled: !stuck,
+                        onclick: move |_| {
+                            if let Err(err) = start_keyboard_recovery(firmware_future, recovery_status) {
+                                recovery_status.set(Some(RecoveryStatus::Failed(err)));
+                            }
+                        },
+                        "Restore stock firmware"
+                    }
+                    button {
+                        class: "px-4 py-2 bg-red-600 text-white rounded shadow hover:bg-red-700",
+                        onclick: move |_| show_recovery.set(false),
+                        "Close"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use std::path::Path;
 use std::sync::Arc;
 use std::collections::BTreeMap;
 
+mod cli;
 mod components;
 mod models;
 mod utils;
@@ -10,44 +10,73 @@ use dioxus::prelude::*;
 use components::{
     SelectBoard,
     SelectLogicalLayout,
+    ButtonBatch,
+    ButtonBoardWizard,
+    ButtonCheatSheet,
+    ButtonCompare,
     ButtonCopyLayer,
+    ButtonDecompile,
+    ButtonDryRun,
+    ButtonExportKle,
+    ButtonExportOs,
+    ButtonFlatten,
+    ButtonHistory,
+    ButtonImport,
+    ButtonImportKle,
+    ButtonImportLayout,
+    ButtonPaths,
     ButtonInstall,
     ButtonLoad,
+    ButtonRecover,
     ButtonSave,
     ErrorMessage,
     Keyboard,
+    KeyMatrixView,
     SliderTPSensitivity,
     SelectFnID,
     MacroKeySetting,
     MediaKeySetting,
+    RecoveryDialog,
+    InstallProgress,
+    BatchDialog,
+    BoardWizardDialog,
+    HistoryDialog,
+    CompareDialog,
 };
 
 use models::{
     Board, LogicalLayout, GeneralSeitting, MacroKey, 
     default_fn_id, default_tp_sensitivity, default_macro_key_map, default_media_key_map, default_enable_middle_click
 };
-use utils::{load_url, load_or_download_firmware};
+use utils::{load_or_download_firmware, watch_connected_board, BoardMatch, InstallStatus, OverlayBase};
 
 // Assets
 const FAVICON: Asset = asset!("/public/favicon.ico");
 const MAIN_CSS: Asset = asset!("/public/styling/main.css");
 const TAILWIND_CSS: Asset = asset!("/public/tailwind.css");
 
-// Constants
-const EXE_URL_SETTING_PATH: &str = "settings/url.txt";
-
 
 fn main() {
+    // Any arguments select a command line tool instead of the GUI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
     dioxus::launch(App);
 }
 
 #[component]
 fn App() -> Element {
 
-    let general_setting = GeneralSeitting::load_from_files().unwrap();
+    let loaded = GeneralSeitting::load_from_files().map_err(|e| e.to_string());
+    if let Ok(general_setting) = &loaded {
+        for diagnostic in &general_setting.board_diagnostics {
+            eprint!("{}", diagnostic);
+        }
+    }
 
     // Firmware to be patched
-    let exe_url = load_url(Path::new(EXE_URL_SETTING_PATH)).unwrap();
+    let exe_url = loaded.as_ref().map(|g| g.official_firmware_url.clone()).unwrap_or_default();
     let firmware_future = use_resource({move || {
         let exe_url_cloned = exe_url.clone();
         async move {
@@ -59,7 +88,15 @@ fn App() -> Element {
         document::Link { rel: "icon", href: FAVICON }
         document::Link { rel: "stylesheet", href: MAIN_CSS }
         document::Link { rel: "stylesheet", href: TAILWIND_CSS }
-        MainWindow { general_setting, firmware_future }
+        match loaded {
+            Ok(general_setting) => rsx! { MainWindow { general_setting, firmware_future } },
+            Err(e) => rsx! {
+                div { class: "p-6 space-y-2 text-white",
+                    strong { class: "text-lg font-semibold", "The app data could not be loaded" }
+                    pre { class: "text-sm whitespace-pre-wrap", "{e}" }
+                }
+            },
+        }
     }
 }
 
@@ -72,20 +109,29 @@ pub fn MainWindow(
     // General setting 
     let general_setting = Arc::new(general_setting);
 
-    // Error message
-    let error_msg: Signal<Option<String>> = use_signal(|| None);
+    // Error message, initially the problems found in board definitions
+    let error_msg: Signal<Option<String>> = use_signal(|| {
+        let diagnostics = &general_setting.board_diagnostics;
+        (!diagnostics.is_empty()).then(|| {
+            let details: String = diagnostics.iter().map(|d| d.to_string()).collect();
+            format!("Problems in board definitions:\n{}", details)
+        })
+    });
 
     // Board variables
     let avail_board_cloned = general_setting.avail_boards.clone();
-    let selected_board_name = use_signal(|| general_setting.avail_boards.get(0).unwrap().board_name.clone() );
+    let mut selected_board_name = use_signal(|| general_setting.avail_boards.get(0).unwrap().board_name.clone() );
     let selected_board: Memo<Board> = use_memo(move || {
         avail_board_cloned.iter().find(|b| b.board_name == selected_board_name())
             .unwrap_or(avail_board_cloned.get(0).unwrap()).clone()
     });
     
+    // Board inferred from a decompiled firmware or a detected keyboard
+    let mut detected_board: Signal<Option<BoardMatch>> = use_signal(|| None);
+
     // Logical layout variables
     let logical_layouts_cloned = general_setting.avail_logical_layouts.clone();
-    let selected_logical_layout_name = use_signal(|| { selected_board().default_logical_layout_name });
+    let mut selected_logical_layout_name = use_signal(|| { selected_board().default_logical_layout_name });
     let selected_logical_layout: Memo<LogicalLayout>  = use_memo(move || {
         logical_layouts_cloned.iter().find(|l| l.layout_name == selected_logical_layout_name())
             .unwrap_or(logical_layouts_cloned.get(0).unwrap()).clone()
@@ -104,10 +150,95 @@ pub fn MainWindow(
     let media_key_map: Signal<BTreeMap<u8, u16>> = use_signal(default_media_key_map);
     let mut enable_middle_click: Signal<bool> = use_signal(default_enable_middle_click);
 
+    // Base of the loaded config when it extends another file
+    let overlay_base: Signal<Option<OverlayBase>> = use_signal(|| None);
+
+    // Recovery, batch install, history, compare and new board dialogs
+    let show_recovery = use_signal(|| false);
+    let show_batch = use_signal(|| false);
+    let show_history = use_signal(|| false);
+    let show_compare = use_signal(|| false);
+    let show_board_wizard = use_signal(|| false);
+
+    // Background build and flash
+    let install_status: Signal<Option<InstallStatus>, SyncStorage> = use_signal_sync(|| None);
+
+    // Board of a keyboard plugged in while the app runs, pre-selected once found
+    let connected_board: Signal<Option<BoardMatch>, SyncStorage> = use_signal_sync(|| None);
+    let general_setting_cloned = general_setting.clone();
+    let mut watching = use_signal(|| false);
+    use_effect(move || {
+        if let Some(original_binary) = firmware_future.read().clone()
+            && !*watching.peek()
+        {
+            watching.set(true);
+            watch_connected_board(original_binary, general_setting_cloned.clone(), install_status, connected_board);
+        }
+    });
+    use_effect(move || {
+        if let Some(found) = connected_board() {
+            selected_board_name.set(found.board_name.clone());
+            selected_logical_layout_name.set(found.default_logical_layout_name.clone());
+            detected_board.set(Some(found));
+        }
+    });
+
     rsx! {
         if let Some(msg) = error_msg() {
             ErrorMessage { msg, error_msg }
         }
+        if show_recovery() {
+            RecoveryDialog {
+                general_setting: general_setting.clone(),
+                firmware_future,
+                selected_board_name,
+                selected_logical_layout_name,
+                detected_board,
+                show_recovery,
+            }
+        }
+        if show_batch() {
+            BatchDialog {
+                id_layout_l0,
+                id_layout_l1,
+                firmware_future,
+                fn_id,
+                tp_sensitivity,
+                macro_key_map,
+                media_key_map,
+                enable_middle_click,
+                selected_board,
+                selected_logical_layout_name,
+                show_batch,
+            }
+        }
+        if show_history() {
+            HistoryDialog { firmware_future, show_history, install_status, error_msg }
+        }
+        if show_compare() {
+            CompareDialog {
+                general_setting: general_setting.clone(),
+                selected_board_name,
+                selected_logical_layout_name,
+                id_layout_l0,
+                id_layout_l1,
+                fn_id,
+                tp_sensitivity,
+                macro_key_map,
+                media_key_map,
+                enable_middle_click,
+                show_compare,
+            }
+        }
+        if show_board_wizard() {
+            BoardWizardDialog {
+                general_setting: general_setting.clone(),
+                firmware_future,
+                install_status,
+                show_board_wizard,
+            }
+        }
+        InstallProgress { install_status, error_msg }
 
         div { class: "min-h-screen bg-gray-600 text-slate-100",
             div { class: "mx-auto w-full p-4 space-y-4",
@@ -127,10 +258,14 @@ pub fn MainWindow(
                             selected_logical_layout_name,
                             selected_logical_layout,
                         }
+                        if let Some(warning) = detected_board().and_then(|found| found.mismatch_warning(&selected_board_name())) {
+                            span { class: "text-sm text-orange-300", "⚠ {warning}" }
+                        }
                     }
                     div { class: "flex items-center gap-2 ml-auto",
                         ButtonCopyLayer { id_layout_l0, id_layout_l1 }
                         ButtonLoad {
+                            general_setting: general_setting.clone(),
                             selected_board_name,
                             selected_logical_layout_name,
                             id_layout_l0,
@@ -140,8 +275,38 @@ pub fn MainWindow(
                             macro_key_map,
                             media_key_map,
                             enable_middle_click,
+                            overlay_base,
+                            error_msg,
+                        }
+                        ButtonImport {
+                            general_setting: general_setting.clone(),
+                            selected_board,
+                            selected_logical_layout_name,
+                            id_layout_l0,
+                            id_layout_l1,
+                            macro_key_map,
+                            media_key_map,
+                            overlay_base,
+                            error_msg,
+                        }
+                        ButtonDecompile {
+                            general_setting: general_setting.clone(),
+                            firmware_future,
+                            selected_board_name,
+                            selected_logical_layout_name,
+                            id_layout_l0,
+                            id_layout_l1,
+                            fn_id,
+                            tp_sensitivity,
+                            macro_key_map,
+                            media_key_map,
+                            enable_middle_click,
+                            overlay_base,
+                            detected_board,
+                            error_msg,
                         }
                         ButtonSave {
+                            general_setting: general_setting.clone(),
                             selected_board,
                             selected_logical_layout,
                             id_layout_l0,
@@ -151,6 +316,67 @@ pub fn MainWindow(
                             macro_key_map,
                             media_key_map,
                             enable_middle_click,
+                            overlay_base,
+                        }
+                        ButtonFlatten { overlay_base }
+                        ButtonCompare { show_compare }
+                        ButtonExportKle {
+                            selected_board,
+                            selected_logical_layout,
+                            id_layout_l0,
+                            id_layout_l1,
+                            error_msg,
+                        }
+                        ButtonImportKle {
+                            general_setting: general_setting.clone(),
+                            selected_logical_layout,
+                            error_msg,
+                        }
+                        ButtonImportLayout { selected_logical_layout, error_msg }
+                        ButtonBoardWizard { show_board_wizard }
+                        ButtonPaths {}
+                        ButtonCheatSheet {
+                            general_setting: general_setting.clone(),
+                            selected_board,
+                            selected_logical_layout,
+                            id_layout_l0,
+                            id_layout_l1,
+                            fn_id,
+                            tp_sensitivity,
+                            macro_key_map,
+                            media_key_map,
+                            enable_middle_click,
+                            error_msg,
+                        }
+                        ButtonExportOs {
+                            general_setting: general_setting.clone(),
+                            selected_board,
+                            selected_logical_layout_name,
+                            id_layout_l0,
+                            id_layout_l1,
+                            fn_id,
+                            tp_sensitivity,
+                            macro_key_map,
+                            media_key_map,
+                            enable_middle_click,
+                            error_msg,
+                        }
+                        ButtonRecover { show_recovery }
+                        ButtonBatch { show_batch }
+                        ButtonHistory { show_history }
+                        ButtonDryRun {
+                            id_layout_l0,
+                            id_layout_l1,
+                            firmware_future,
+                            fn_id,
+                            tp_sensitivity,
+                            macro_key_map,
+                            media_key_map,
+                            enable_middle_click,
+                            selected_board,
+                            selected_logical_layout_name,
+                            install_status,
+                            error_msg,
                         }
                         ButtonInstall {
                             id_layout_l0,
@@ -162,6 +388,8 @@ pub fn MainWindow(
                             media_key_map,
                             enable_middle_click,
                             selected_board,
+                            selected_logical_layout_name,
+                            install_status,
                             error_msg,
                         }
                     }
@@ -240,9 +468,16 @@ pub fn MainWindow(
                         }
                     }
                 }
-                        // div { class: "w-full bg-gray-800 p-4 rounded shadow flex flex-wrap items-end gap-4",
-            //     h2 { class: "text-xl font-bold text-center py-2", "Key matrix" },
-            // }
+                div { class: "w-full bg-black p-4 rounded",
+                    h2 { class: "text-xl font-bold text-center py-2", "Key matrix" }
+                    KeyMatrixView {
+                        general_setting: general_setting.clone(),
+                        board: selected_board().clone(),
+                        logical_layout: selected_logical_layout().clone(),
+                        id_layout_l0,
+                        id_layout_l1,
+                    }
+                }
             }
         }
     }
@@ -1,16 +1,22 @@
-use std::{collections::BTreeMap};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 
-use crate::models::{Board, KeyLabel, LogicalLayout};
+use serde::{Serialize, Deserialize};
+
+use crate::models::{
+    Board, BoardDiagnostics, KeyGeometry, KeyLabel, KeyMatrix, KeyShape, LogicalLayout, DEAD_KEY_PREFIX, KEY_GAP,
+    KEY_HEIGHT, Resources, resources, validate_board_file,
+};
 
 // Constants
 const GENERAL_SETTING_PATH: &str = "settings/general_setting.csv";
 const MEDIA_KEY_USAGE_NAMES_PATH: &str = "settings/media_key_usage_names.csv";
-const BOARDS_DIR:  &str = "boards";
-const LOGICAL_LAYOUT_DIR:  &str = "logical_layouts";
+pub const BOARDS_DIR:  &str = "boards";
+pub const LOGICAL_LAYOUT_DIR:  &str = "logical_layouts";
 const EXE_URL_SETTING_PATH: &str = "settings/url.txt";
+const KEY_MATRIX_PATH: &str = "settings/keymatrix.csv";
 
 #[derive(PartialEq, Clone)]
 pub struct GeneralSeitting {
@@ -20,6 +26,136 @@ pub struct GeneralSeitting {
     pub avail_boards: Vec<Board>,
     pub avail_logical_layouts: Vec<LogicalLayout>,
     pub official_firmware_url: String,
+    pub key_matrix: KeyMatrix,
+    /// Board files with problems; files with errors are not in `avail_boards`
+    pub board_diagnostics: Vec<BoardDiagnostics>,
+}
+
+/// Sections of a board `.cfg` file as written, before any checks.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct BoardFile {
+    pub board_name: String,
+    pub board_label: String,
+    pub default_logical_layout_name: String,
+    pub map_ids: Vec<Vec<Option<u8>>>,
+    pub map_address: Vec<Vec<Option<u8>>>,
+    pub map_widths: Vec<Vec<u16>>,
+    /// Tokens that are neither a hex byte nor a width
+    pub invalid_tokens: Vec<String>,
+    /// Key positions of a `.toml` board; derived from the rows for `.cfg` boards
+    pub keys: Vec<KeyGeometry>,
+}
+
+/// Board file in the structured `.toml` format.
+#[derive(Serialize, Deserialize)]
+struct BoardGeometryFile {
+    board_name: String,
+    #[serde(default)]
+    board_label: String,
+    #[serde(default)]
+    default_logical_layout_name: String,
+    keys: Vec<KeyGeometry>,
+}
+
+/// Key positions of a `.cfg` board: cells are laid out left to right,
+/// `KEY_GAP` apart, one row every `KEY_HEIGHT + KEY_GAP`.
+pub fn keys_from_rows(
+    map_address: &[Vec<Option<u8>>],
+    map_widths: &[Vec<u16>],
+    initial_id_map: &BTreeMap<u8, Option<u8>>,
+) -> Vec<KeyGeometry> {
+    let mut keys = Vec::new();
+    for (row, (addresses, widths)) in map_address.iter().zip(map_widths).enumerate() {
+        let mut x = 0;
+        for (address, width) in addresses.iter().zip(widths) {
+            if let Some(address) = address {
+                keys.push(KeyGeometry {
+                    x,
+                    y: row as u16 * (KEY_HEIGHT + KEY_GAP),
+                    w: *width,
+                    h: KEY_HEIGHT,
+                    shape: KeyShape::Rect,
+                    notch: 0,
+                    label: String::new(),
+                    address: *address,
+                    id: initial_id_map.get(address).copied().flatten(),
+                });
+            }
+            x += width + KEY_GAP;
+        }
+    }
+    keys
+}
+
+/// Board file with `keys` and key ID, address and width rows approximating
+/// them: one row per distinct `y`, with empty cells for horizontal gaps.
+pub fn rows_from_keys(keys: Vec<KeyGeometry>) -> BoardFile {
+    let mut rows: BTreeMap<u16, Vec<&KeyGeometry>> = BTreeMap::new();
+    for key in &keys {
+        rows.entry(key.y).or_default().push(key);
+    }
+    let (mut map_ids, mut map_address, mut map_widths) = (Vec::new(), Vec::new(), Vec::new());
+    for mut row in rows.into_values() {
+        row.sort_by_key(|key| key.x);
+        let (mut ids, mut addresses, mut widths) = (Vec::new(), Vec::new(), Vec::new());
+        let mut x = 0;
+        for key in row {
+            if key.x > x {
+                ids.push(None);
+                addresses.push(None);
+                widths.push((key.x - x).saturating_sub(KEY_GAP));
+            }
+            ids.push(key.id);
+            addresses.push(Some(key.address));
+            widths.push(key.w);
+            x = key.x + key.w + KEY_GAP;
+        }
+        map_ids.push(ids);
+        map_address.push(addresses);
+        map_widths.push(widths);
+    }
+    BoardFile { map_ids, map_address, map_widths, keys, ..BoardFile::default() }
+}
+
+/// Legend of the key with `id` as printed on the keycap, e.g. "Enter", "Q"
+/// or "1 !".
+pub fn position_label(logical_layout: Option<&LogicalLayout>, id: Option<u8>) -> String {
+    let Some(label) = id.and_then(|id| logical_layout?.map_key_label.get(&id)) else {
+        return String::new();
+    };
+    let mut chars = label.default.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_alphabetic() => c.to_uppercase().collect(),
+        _ if label.shifted.is_empty() => label.default.clone(),
+        _ => format!("{} {}", label.default, label.shifted),
+    }
+}
+
+impl BoardFile {
+    fn into_board(self) -> Board {
+        let initial_id_map: BTreeMap<u8, Option<u8>> = self.map_address
+            .iter().flatten()
+            .zip(self.map_ids.iter().flatten())
+            .filter_map(|(addr_opt, id_opt)| {
+                addr_opt.map(|addr| (addr, *id_opt))
+            })
+            .collect();
+        let keys = if self.keys.is_empty() {
+            keys_from_rows(&self.map_address, &self.map_widths, &initial_id_map)
+        } else {
+            self.keys
+        };
+
+        Board {
+            board_name: self.board_name,
+            board_label: self.board_label,
+            default_logical_layout_name: self.default_logical_layout_name,
+            map_widths: self.map_widths,
+            map_address: self.map_address,
+            initial_id_map,
+            keys,
+        }
+    }
 }
 
 
@@ -29,19 +165,42 @@ impl GeneralSeitting {
         self.avail_media_key_usage_names.get(&media_key_id).unwrap().to_string()
     }
 
+    /// Load the settings, boards and languages found by [`resources`].
+    /// Errors name the search path, as a missing file usually means the
+    /// bundled data was not found.
     pub fn load_from_files() -> io::Result<GeneralSeitting> {
-        
-        let general_setting_path = Path::new(GENERAL_SETTING_PATH);
-        let media_key_setting_path = Path::new(MEDIA_KEY_USAGE_NAMES_PATH);
-        let boards_dir_path = Path::new(BOARDS_DIR);
-        let logical_layouts_dir_path = Path::new(LOGICAL_LAYOUT_DIR);
-        let official_firmware_url_path = Path::new(EXE_URL_SETTING_PATH);
-
-        let (id_map, usage_names) = GeneralSeitting::load_general_settings(general_setting_path)?;
-        let media_key_usage_names = GeneralSeitting::load_media_key_settings(media_key_setting_path)?;
-        let avail_boards = GeneralSeitting::load_boards(boards_dir_path)?;
-        let avail_logical_layouts = GeneralSeitting::load_logical_layouts(logical_layouts_dir_path, general_setting_path)?;
-        let official_firmware_url = GeneralSeitting::load_url(official_firmware_url_path)?;
+        let resources = resources();
+        GeneralSeitting::load_from(resources)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}\n\nSearch path:\n{}", e, resources)))
+    }
+
+    /// Load from the files found by `resources`.
+    pub fn load_from(resources: &Resources) -> io::Result<GeneralSeitting> {
+
+        let general_setting_path = resources.find(GENERAL_SETTING_PATH);
+        let media_key_setting_path = resources.find(MEDIA_KEY_USAGE_NAMES_PATH);
+        let boards_dirs = resources.dirs(BOARDS_DIR);
+        let logical_layouts_dirs = resources.dirs(LOGICAL_LAYOUT_DIR);
+        let official_firmware_url_path = resources.find(EXE_URL_SETTING_PATH);
+
+        let (id_map, usage_names) = GeneralSeitting::load_general_settings(&general_setting_path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", general_setting_path.display(), e)))?;
+        let media_key_usage_names = GeneralSeitting::load_media_key_settings(&media_key_setting_path)?;
+        let avail_logical_layouts = GeneralSeitting::load_logical_layouts(&logical_layouts_dirs, &general_setting_path)?;
+        let key_matrix_path = resources.find(KEY_MATRIX_PATH);
+        let key_matrix = GeneralSeitting::load_key_matrix(&key_matrix_path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", key_matrix_path.display(), e)))?;
+        let known_addresses = id_map.keys().chain(key_matrix.keys.keys()).copied().collect();
+        let (avail_boards, board_diagnostics) =
+            GeneralSeitting::load_boards(&boards_dirs, &known_addresses, &avail_logical_layouts)?;
+        if avail_boards.is_empty() {
+            let details: String = board_diagnostics.iter().map(|d| d.to_string()).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No usable board definition in {}\n{}", BOARDS_DIR, details),
+            ));
+        }
+        let official_firmware_url = GeneralSeitting::load_url(&official_firmware_url_path)?;
 
         Ok(GeneralSeitting {
             initial_id_map: id_map,
@@ -50,6 +209,8 @@ impl GeneralSeitting {
             avail_media_key_usage_names: media_key_usage_names,
             avail_hid_usage_names: usage_names,
             official_firmware_url: official_firmware_url,
+            key_matrix,
+            board_diagnostics,
         })
 
     }
@@ -110,6 +271,24 @@ impl GeneralSeitting {
     }
 
     pub fn load_board(board_config_path: &Path) -> io::Result<Board> {
+        let board_file = GeneralSeitting::read_board_file(board_config_path)?;
+        if let Some(token) = board_file.invalid_tokens.iter().find(|t| t.starts_with("[key_width]")) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, token.clone()));
+        }
+        if board_file.map_address.len() != board_file.map_widths.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Row count mismatch between key addresses and widths",
+            ));
+        }
+        Ok(board_file.into_board())
+    }
+
+    /// Read the sections of a board `.cfg` or `.toml` file without checking them.
+    pub fn read_board_file(board_config_path: &Path) -> io::Result<BoardFile> {
+        if board_config_path.extension().is_some_and(|ext| ext == "toml") {
+            return GeneralSeitting::read_board_geometry_file(board_config_path);
+        }
         let file = File::open(board_config_path)?;
         let reader = BufReader::new(file);
 
@@ -124,12 +303,7 @@ impl GeneralSeitting {
         }
 
         let mut section = Section::None;
-        let mut board_name = "".to_string();
-        let mut board_label = "".to_string();
-        let mut default_logical_layout_name = "".to_string();
-        let mut map_ids: Vec<Vec<Option<u8>>> = Vec::new();
-        let mut map_address: Vec<Vec<Option<u8>>> = Vec::new();
-        let mut map_widths: Vec<Vec<u16>> = Vec::new();
+        let mut board_file = BoardFile::default();
 
         for line in reader.lines() {
             let line = line?;
@@ -168,56 +342,64 @@ impl GeneralSeitting {
             }
 
             let tokens: Vec<&str> = trimmed.split(',').map(|s| s.trim()).collect();
+            let invalid_tokens = &mut board_file.invalid_tokens;
+            let mut hex_row = |section_name: &str, row_number: usize| {
+                tokens
+                    .iter()
+                    .map(|s| {
+                        if s.is_empty() {
+                            None
+                        } else {
+                            let byte = u8::from_str_radix(s, 16).ok();
+                            if byte.is_none() {
+                                invalid_tokens.push(format!(
+                                    "{} row {}: '{}' is not a hex byte", section_name, row_number, s
+                                ));
+                            }
+                            byte
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            };
 
             match section {
                 Section::Name => {
                     if let Some(s) = tokens.get(0).copied() {
-                        board_name = s.to_string();
+                        board_file.board_name = s.to_string();
                     }
                 }
                 Section::Label => {
                     if let Some(s) = tokens.get(0).copied() {
-                        board_label = s.to_string();
+                        board_file.board_label = s.to_string();
                     }
                 }
                 Section::DefaultLogicalLayout => {
                     if let Some(s) = tokens.get(0).copied() {
-                        default_logical_layout_name = s.to_string();
+                        board_file.default_logical_layout_name = s.to_string();
                     }
                 }
                 Section::KeyId => {
-                    let row = tokens
-                        .into_iter()
-                        .map(|s| {
-                            if s.is_empty() {
-                                None
-                            } else {
-                                u8::from_str_radix(s, 16).ok()
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                    map_ids.push(row);
+                    let row = hex_row("[key_id]", board_file.map_ids.len() + 1);
+                    board_file.map_ids.push(row);
                 }
                 Section::KeyAddress => {
+                    let row = hex_row("[key_address]", board_file.map_address.len() + 1);
+                    board_file.map_address.push(row);
+                }
+                Section::Width => {
+                    let row_number = board_file.map_widths.len() + 1;
                     let row = tokens
-                        .into_iter()
+                        .iter()
                         .map(|s| {
-                            if s.is_empty() {
-                                None
-                            } else {
-                                u8::from_str_radix(s, 16).ok()
-                            }
+                            s.parse::<u16>().unwrap_or_else(|_| {
+                                board_file.invalid_tokens.push(format!(
+                                    "[key_width] row {}: '{}' is not a width", row_number, s
+                                ));
+                                0
+                            })
                         })
                         .collect::<Vec<_>>();
-                    map_address.push(row);
-                }
-                Section::Width => {
-                    let row = tokens
-                        .into_iter()
-                        .map(|s| s.parse::<u16>())
-                        .collect::<Result<Vec<_>, _>>()
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-                    map_widths.push(row);
+                    board_file.map_widths.push(row);
                 }
                 Section::None => {
                     // Ignore
@@ -225,53 +407,206 @@ impl GeneralSeitting {
             }
         }
 
-        if map_address.len() != map_widths.len() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Row count mismatch between key addresses and widths",
-            ));
-        }
+        Ok(board_file)
+    }
 
-        // let address2id = GeneralSeitting::load_address2id(general_config_path)?;
+    fn read_board_geometry_file(board_path: &Path) -> io::Result<BoardFile> {
+        let text = std::fs::read_to_string(board_path)?;
+        let file: BoardGeometryFile = toml::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(BoardFile {
+            board_name: file.board_name,
+            board_label: file.board_label,
+            default_logical_layout_name: file.default_logical_layout_name,
+            ..rows_from_keys(file.keys)
+        })
+    }
 
-        let initial_id_map: BTreeMap<u8, Option<u8>> = map_address
-            .iter().flatten()
-            .zip(map_ids.iter().flatten())
-            .filter_map(|(addr_opt, id_opt)| {
-                addr_opt.map(|addr| (addr, *id_opt))
+    /// Write `board` in the structured `.toml` format read by [`GeneralSeitting::load_board`].
+    pub fn save_board_geometry(
+        board: &Board,
+        board_path: &Path,
+        logical_layouts: &[LogicalLayout],
+    ) -> io::Result<()> {
+        std::fs::write(board_path, GeneralSeitting::board_geometry_to_string(board, logical_layouts))
+    }
+
+    /// `board` in the structured `.toml` format, one key per line with hex
+    /// addresses and IDs. Unlabelled keys are labelled with the legend printed
+    /// on them in the board's default logical layout.
+    pub fn board_geometry_to_string(board: &Board, logical_layouts: &[LogicalLayout]) -> String {
+        let quote = |text: &str| toml::Value::String(text.to_string()).to_string();
+        let logical_layout = logical_layouts.iter().find(|l| l.layout_name == board.default_logical_layout_name);
+        let keys = board.keys
+            .iter()
+            .map(|key| {
+                let label = if key.label.is_empty() {
+                    position_label(logical_layout, key.id)
+                } else {
+                    key.label.clone()
+                };
+                let mut line = format!("  {{ x = {}, y = {}, w = {}, h = {}", key.x, key.y, key.w, key.h);
+                if key.shape == KeyShape::IsoEnter {
+                    line += &format!(", shape = \"iso_enter\", notch = {}", key.notch);
+                }
+                line += &format!(", address = 0x{:02X}", key.address);
+                if let Some(id) = key.id {
+                    line += &format!(", id = 0x{:02X}", id);
+                }
+                if !label.is_empty() {
+                    line += &format!(", label = {}", quote(&label));
+                }
+                line + " },"
             })
-            .collect();
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "board_name = {}\nboard_label = {}\ndefault_logical_layout_name = {}\n\n\
+             # x, y, w, h in px: 1u = 48, keys 4 apart, rows 44 apart.\n\
+             # shape = \"iso_enter\" is two rows high with `notch` px cut from the left of the lower row.\n\
+             keys = [\n{}\n]\n",
+            quote(&board.board_name),
+            quote(&board.board_label),
+            quote(&board.default_logical_layout_name),
+            keys,
+        )
+    }
 
-        Ok(Board {
-            board_name,
-            board_label,
-            default_logical_layout_name,
-            map_widths,
-            map_address,
-            initial_id_map,
-        })
+    /// Load a board file and check it strictly. The board is only returned
+    /// when the report has no errors.
+    pub fn check_board(
+        board_config_path: &Path,
+        known_addresses: &BTreeSet<u8>,
+        logical_layouts: &[LogicalLayout],
+    ) -> (Option<Board>, BoardDiagnostics) {
+        let (board, report) = match GeneralSeitting::read_board_file(board_config_path) {
+            Ok(board_file) => {
+                let report = validate_board_file(&board_file, known_addresses, logical_layouts);
+                let board = (!report.has_errors()).then(|| board_file.into_board());
+                (board, report)
+            }
+            Err(e) => {
+                let mut report = crate::models::ValidationReport::default();
+                report.errors.push(format!("Cannot read file: {}", e));
+                (None, report)
+            }
+        };
+        (board, BoardDiagnostics { path: board_config_path.to_path_buf(), report })
     }
 
-    pub fn load_boards(dir: &Path) -> io::Result<Vec<Board>> {
-        let mut cfg_files = Vec::new();
-        let mut cfgs = Vec::new();
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext_found) = path.extension() {
-                    if ext_found == "cfg" {
-                        cfg_files.push(path);
-                    }
+    /// Matrix addresses named in `general_setting.csv` or `keymatrix.csv`.
+    pub fn known_addresses(&self) -> BTreeSet<u8> {
+        self.initial_id_map.keys().chain(self.key_matrix.keys.keys()).copied().collect()
+    }
+
+    /// Write `board` in the `.cfg` format read by [`GeneralSeitting::load_board`].
+    pub fn save_board(board: &Board, board_config_path: &Path) -> io::Result<()> {
+        let hex_rows = |cell: &dyn Fn(u8) -> Option<u8>| {
+            board.map_address
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|a| a.and_then(cell).map_or("  ".to_string(), |v| format!("{:02X}", v)))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let widths = board.map_widths
+            .iter()
+            .map(|row| row.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(","))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = format!(
+            "[board_name]\n{}\n\n[board_label]\n{}\n\n[default_logical_layout_name]\n{}\n\n\
+             [key_id]\n{}\n\n[key_address]\n{}\n\n[key_width]\n{}\n",
+            board.board_name,
+            board.board_label,
+            board.default_logical_layout_name,
+            hex_rows(&|a| board.initial_id_map.get(&a).copied().flatten()),
+            hex_rows(&Some),
+            widths,
+        );
+        std::fs::write(board_config_path, text)
+    }
+
+    /// Files with one of `extensions` in `dirs`, in name order. A file shadows
+    /// files of the same name in later directories.
+    fn layered_files(dirs: &[PathBuf], extensions: &[&str]) -> io::Result<Vec<PathBuf>> {
+        let mut files: BTreeMap<std::ffi::OsString, PathBuf> = BTreeMap::new();
+        for dir in dirs {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_file()
+                    && path.extension().is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+                    && let Some(file_name) = path.file_name()
+                {
+                    files.entry(file_name.to_os_string()).or_insert(path);
                 }
             }
         }
+        Ok(files.into_values().collect())
+    }
+
+    /// Boards that pass [`GeneralSeitting::check_board`], and the diagnostics of
+    /// every file with problems. Files are read in name order from all of
+    /// `dirs`; a file shadows files of the same name in later directories, and
+    /// a `.toml` file replaces the `.cfg` file it was converted from.
+    pub fn load_boards(
+        dirs: &[PathBuf],
+        known_addresses: &BTreeSet<u8>,
+        logical_layouts: &[LogicalLayout],
+    ) -> io::Result<(Vec<Board>, Vec<BoardDiagnostics>)> {
+        let mut cfgs: Vec<Board> = Vec::new();
+        let mut diagnostics = Vec::new();
+        let board_files = GeneralSeitting::layered_files(dirs, &["cfg", "toml"])?;
+        let has_extension = |path: &Path, extension: &str| path.extension().is_some_and(|ext| ext == extension);
+        let converted: BTreeSet<_> = board_files
+            .iter()
+            .filter(|path| has_extension(path, "toml"))
+            .filter_map(|path| path.file_stem())
+            .collect();
+        let cfg_files = board_files
+            .iter()
+            .filter(|path| !(has_extension(path, "cfg") && path.file_stem().is_some_and(|stem| converted.contains(stem))));
         for cfg_filepath in cfg_files {
-            if let Ok(cfg) = GeneralSeitting::load_board(&cfg_filepath) {
-                cfgs.push(cfg);
-            };
+            let (board, mut diagnostic) = GeneralSeitting::check_board(cfg_filepath, known_addresses, logical_layouts);
+            if let Some(board) = board {
+                if cfgs.iter().any(|b| b.board_name == board.board_name) {
+                    diagnostic.report.errors.push(format!(
+                        "Board name '{}' is already used by another file", board.board_name
+                    ));
+                } else {
+                    cfgs.push(board);
+                }
+            }
+            if !diagnostic.report.is_empty() {
+                diagnostics.push(diagnostic);
+            }
         }
-        Ok(cfgs)
+        Ok((cfgs, diagnostics))
+    }
+
+    /// Read `keymatrix.csv`: one row per row pin, one column per column pin,
+    /// `(none)` where no key is wired.
+    pub fn load_key_matrix(key_matrix_path: &Path) -> io::Result<KeyMatrix> {
+        let file = File::open(key_matrix_path)?;
+        let mut rdr = csv::Reader::from_reader(BufReader::new(file));
+        let column_pins = rdr.headers()?.iter().skip(1).map(|s| s.trim().to_string()).collect();
+        let mut row_pins = Vec::new();
+        let mut keys = BTreeMap::new();
+        for (row, record) in rdr.records().enumerate() {
+            let record = record?;
+            row_pins.push(record.get(0).unwrap_or("").trim().to_string());
+            for (col, key) in record.iter().skip(1).enumerate() {
+                let key = key.trim();
+                if !key.is_empty() && key != "(none)" {
+                    keys.insert(((row as u8) << 4) | col as u8, key.to_string());
+                }
+            }
+        }
+        Ok(KeyMatrix { row_pins, column_pins, keys })
     }
 
     pub fn load_usage_names(general_config_path: &Path) -> io::Result<BTreeMap<u8, String>> {
@@ -292,12 +627,23 @@ impl GeneralSeitting {
         Ok(usage_name_map)
     }
 
+    /// Load a logical layout CSV. Columns are found by their header: `ID`,
+    /// `default` and `shifted`, and optionally `altgr` and `altgr_shifted`.
+    /// A cell starting with [`DEAD_KEY_PREFIX`] is a dead key.
     pub fn load_logical_layout(logical_layout_path: &Path, general_config_path: &Path) -> io::Result<LogicalLayout> {
         let file = File::open(logical_layout_path)?;
-        let mut rdr = csv::Reader::from_reader(BufReader::new(file));
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(BufReader::new(file));
         let mut map_key_label = BTreeMap::new();
 
         let usage_names = GeneralSeitting::load_usage_names(general_config_path)?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let levels = [
+            column("default").or(Some(1)),
+            column("shifted").or(Some(2)),
+            column("altgr"),
+            column("altgr_shifted"),
+        ];
 
         for record in rdr.records() {
             let record = record?;
@@ -307,11 +653,20 @@ impl GeneralSeitting {
             }
 
             let id = u8::from_str_radix(id_str, 16).unwrap_or(0);
-            let default = record.get(1).unwrap_or("").trim().to_string();
-            let shifted = record.get(2).unwrap_or("").trim().to_string();
-
-            let usage_name = usage_names.get(&id).map_or("", |v| v).to_string();
-            map_key_label.insert(id, KeyLabel{ usage_name, default, shifted } );
+            let mut label = KeyLabel::new();
+            label.usage_name = usage_names.get(&id).map_or("", |v| v).to_string();
+            let cells = [&mut label.default, &mut label.shifted, &mut label.altgr, &mut label.altgr_shifted];
+            for ((cell, level), dead) in cells.into_iter().zip(levels).zip(label.dead.iter_mut()) {
+                let text = level.and_then(|i| record.get(i)).unwrap_or("").trim();
+                match text.strip_prefix(DEAD_KEY_PREFIX) {
+                    Some(symbol) => {
+                        *cell = symbol.to_string();
+                        *dead = true;
+                    }
+                    None => *cell = text.to_string(),
+                }
+            }
+            map_key_label.insert(id, label);
         }
 
         let basename = logical_layout_path.file_stem().unwrap().to_str().unwrap();
@@ -323,20 +678,9 @@ impl GeneralSeitting {
         })
     }
 
-    pub fn load_logical_layouts(dir: &Path, general_config_path: &Path) -> io::Result<Vec<LogicalLayout>> {
-        let mut cfg_files = Vec::new();
+    pub fn load_logical_layouts(dirs: &[PathBuf], general_config_path: &Path) -> io::Result<Vec<LogicalLayout>> {
         let mut cfgs = Vec::new();
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(ext_found) = path.extension() {
-                    if ext_found == "csv" {
-                        cfg_files.push(path);
-                    }
-                }
-            }
-        }
+        let cfg_files = GeneralSeitting::layered_files(dirs, &["csv"])?;
         for cfg_filepath in cfg_files {
             if let Ok(cfg) = GeneralSeitting::load_logical_layout(&cfg_filepath, general_config_path) {
                 cfgs.push(cfg);
@@ -345,6 +689,29 @@ impl GeneralSeitting {
         Ok(cfgs)
     }
 
+    /// Write `logical_layout` in the CSV format read by [`GeneralSeitting::load_logical_layout`],
+    /// one row per key ID. The AltGr columns are only written when used.
+    pub fn save_logical_layout(logical_layout: &LogicalLayout, path: &Path) -> io::Result<()> {
+        let altgr = logical_layout.map_key_label.values().any(|label| label.has_altgr());
+        let mut writer = csv::Writer::from_path(path)?;
+        let mut header = vec!["ID", "default", "shifted"];
+        if altgr {
+            header.extend(["altgr", "altgr_shifted"]);
+        }
+        writer.write_record(&header)?;
+        let empty = KeyLabel::new();
+        for id in 0..=255u8 {
+            let label = logical_layout.map_key_label.get(&id).unwrap_or(&empty);
+            let levels = [&label.default, &label.shifted, &label.altgr, &label.altgr_shifted];
+            let mut record = vec![format!("{:02X}", id)];
+            for (text, dead) in levels.into_iter().zip(label.dead).take(header.len() - 1) {
+                record.push(if dead { format!("{}{}", DEAD_KEY_PREFIX, text) } else { text.clone() });
+            }
+            writer.write_record(&record)?;
+        }
+        writer.flush()
+    }
+
     pub fn load_url(filepath: &Path) -> io::Result<String> {
         let file = File::open(filepath)?;
         let mut reader = BufReader::new(file);
@@ -356,3 +723,63 @@ impl GeneralSeitting {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn board_geometry_round_trip() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        assert_eq!(general_setting.avail_boards.len(), 4);
+        let dir = tempfile::tempdir().unwrap();
+        for board in &general_setting.avail_boards {
+            let path = dir.path().join(format!("{}.toml", board.board_name));
+            GeneralSeitting::save_board_geometry(board, &path, &general_setting.avail_logical_layouts).unwrap();
+            let saved = GeneralSeitting::load_board(&path).unwrap();
+            // Labels are added and neighbouring gaps in the rows merged, so only the key outlines are compared
+            let outlines = |b: &Board| b.keys.iter().map(|k| (k.x, k.y, k.w, k.h, k.shape, k.notch, k.address, k.id)).collect::<Vec<_>>();
+            assert_eq!(outlines(&saved), outlines(board), "{}", board.board_name);
+            assert_eq!(saved.initial_id_map, board.initial_id_map, "{}", board.board_name);
+        }
+        // The structured file replaces the .cfg, which has no ISO Enter
+        let iso = general_setting.avail_boards.iter().find(|b| b.board_name == "0B47221").unwrap();
+        assert!(iso.keys.iter().any(|k| k.shape == KeyShape::IsoEnter));
+        let rows = GeneralSeitting::load_board(&resources().find("boards/0B47221.cfg")).unwrap();
+        assert!(rows.keys.iter().all(|k| k.shape == KeyShape::Rect));
+        assert_eq!(rows.initial_id_map, iso.initial_id_map);
+    }
+    #[test]
+    fn user_boards_are_layered_over_bundled_boards() {
+        let root = tempfile::tempdir().unwrap();
+        let resources = Resources::isolated(root.path());
+        let boards_dir = resources.user_config_dir(BOARDS_DIR);
+        let bundled = std::fs::read_to_string(resources.find("boards/0B47190.cfg")).unwrap();
+        std::fs::write(boards_dir.join("MyBoard.cfg"), bundled.replacen("0B47190\n", "MyBoard\n", 1)).unwrap();
+        // Shadows the bundled file of the same name
+        std::fs::write(boards_dir.join("45N2211.cfg"), bundled.replacen("0B47190\n", "45N2211\n", 1)).unwrap();
+        let general_setting = GeneralSeitting::load_from(&resources).unwrap();
+        let names: Vec<&str> = general_setting.avail_boards.iter().map(|b| b.board_name.as_str()).collect();
+        assert_eq!(names, ["0B47190", "0B47208", "0B47221", "45N2211", "MyBoard"]);
+        let shadowed = general_setting.avail_boards.iter().find(|b| b.board_name == "45N2211").unwrap();
+        assert_eq!(shadowed.board_label, "0B47190 (84 keys - ANSI)");
+        assert_eq!(general_setting.board_diagnostics, vec![]);
+    }
+
+    #[test]
+    fn loads_altgr_levels_and_dead_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("altgr.csv");
+        std::fs::write(&path, "ID,default,shifted,altgr,altgr_shifted\n04,a,A,á,Á\n2F,[,{,dead:¨,dead:^\n05,b,B\n").unwrap();
+        let layout = GeneralSeitting::load_logical_layout(&path, &resources().find(GENERAL_SETTING_PATH)).unwrap();
+        let a = &layout.map_key_label[&0x04];
+        assert_eq!((a.altgr.as_str(), a.altgr_shifted.as_str()), ("á", "Á"));
+        let bracket = &layout.map_key_label[&0x2F];
+        assert_eq!((bracket.altgr.as_str(), bracket.altgr_shifted.as_str()), ("¨", "^"));
+        assert_eq!(bracket.dead, [false, false, true, true]);
+        assert!(!layout.map_key_label[&0x05].has_altgr());
+
+        let uk = GeneralSeitting::load_logical_layout(&resources().find("logical_layouts/UK_English.csv"), &resources().find(GENERAL_SETTING_PATH)).unwrap();
+        assert_eq!(uk.map_key_label[&0x21].altgr, "€");
+    }
+}
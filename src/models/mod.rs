@@ -4,6 +4,12 @@ use serde::{Serialize, Deserialize};
 mod general_setting;
 pub use general_setting::*;
 
+mod validation;
+pub use validation::*;
+
+mod resources;
+pub use resources::{BUILD_DIR, CONFIGS_DIR, Resources, resources};
+
 // Default values
 const DEFAULT_TP_SENSITIVITY: u32 = 1;
 const DEFAULT_FN_ID: u8 = 0xaf;
@@ -13,11 +19,16 @@ const MACRO_KEY_TRIGGER_IDS: [u8;24] = [
     0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7,
     0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF,
 ];
+pub const MOD_KEY_ID: u8 = 0xE7;
 const MEDIA_KEY_TRIGGER_IDS: [u8;11] = [
     0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xDB, 0xDC,
     0xDD, 0xDE, 0xDF,
 ];
 
+// Key geometry in px: keys are 40 high and 4 apart, rows 44 apart
+pub const KEY_HEIGHT: u16 = 40;
+pub const KEY_GAP: u16 = 4;
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct Board {
     pub board_name: String,
@@ -26,6 +37,61 @@ pub struct Board {
     pub map_widths: Vec<Vec<u16>>, 
     pub map_address: Vec<Vec<Option<u8>>>,
     pub initial_id_map: BTreeMap<u8, Option<u8>>,
+    /// Position of every key, as drawn by `Keyboard`
+    pub keys: Vec<KeyGeometry>,
+}
+
+impl Board {
+    /// Keys to draw: `keys`, or the rows laid out for a board built without them.
+    pub fn key_geometry(&self) -> Vec<KeyGeometry> {
+        if self.keys.is_empty() {
+            keys_from_rows(&self.map_address, &self.map_widths, &self.initial_id_map)
+        } else {
+            self.keys.clone()
+        }
+    }
+}
+
+/// Outline of a keycap.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyShape {
+    #[default]
+    Rect,
+    /// Two rows high, with `notch` px cut from the left of the lower row
+    IsoEnter,
+}
+
+pub fn default_key_height() -> u16 { KEY_HEIGHT }
+
+/// Position and size of one key in px (1u = 48).
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeyGeometry {
+    pub x: u16,
+    pub y: u16,
+    pub w: u16,
+    #[serde(default = "default_key_height")]
+    pub h: u16,
+    #[serde(default)]
+    pub shape: KeyShape,
+    #[serde(default)]
+    pub notch: u16,
+    /// Name of the position, e.g. "Enter"; only for people reading the file
+    #[serde(default)]
+    pub label: String,
+    pub address: u8,
+    /// Original key ID
+    pub id: Option<u8>,
+}
+
+/// Electrical key matrix from `keymatrix.csv`. A key address is
+/// `row << 4 | column`, indexing `row_pins` and `column_pins`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct KeyMatrix {
+    pub row_pins: Vec<String>,
+    pub column_pins: Vec<String>,
+    /// Key name at each wired address
+    pub keys: BTreeMap<u8, String>,
 }
 
 /*
@@ -61,11 +127,20 @@ impl LogicalLayout {
 }
 */
 
+/// Marks a dead key in a logical layout cell, e.g. `dead:^`
+pub const DEAD_KEY_PREFIX: &str = "dead:";
+
 #[derive(Clone, PartialEq)]
 pub struct KeyLabel {
     pub usage_name: String,
     pub default: String,
     pub shifted: String, 
+    /// Third level, typed with AltGr
+    pub altgr: String,
+    /// Fourth level, typed with AltGr and Shift
+    pub altgr_shifted: String,
+    /// Whether each level (default, shifted, altgr, altgr_shifted) is a dead key
+    pub dead: [bool; 4],
 }
 
 impl KeyLabel {
@@ -74,8 +149,15 @@ impl KeyLabel {
             usage_name: String::new(),
             default: String::new(),
             shifted: String::new(),
+            altgr: String::new(),
+            altgr_shifted: String::new(),
+            dead: [false; 4],
         }
     }
+
+    pub fn has_altgr(&self) -> bool {
+        !self.altgr.is_empty() || !self.altgr_shifted.is_empty()
+    }
 }
 
 pub fn default_fn_id() -> u8 { DEFAULT_FN_ID }
@@ -99,7 +181,7 @@ pub fn default_media_key_map() -> BTreeMap<u8, u16> {
 
 pub fn default_enable_middle_click() -> bool { false }
 
-#[derive(Serialize, Deserialize, )]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Config {
     pub config_version: u32,
     pub physical_layout_name: String,
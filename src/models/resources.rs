@@ -0,0 +1,215 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const APP_DIR_NAME: &str = "ku1255-firmware-modifier";
+/// Present in every directory holding the bundled data
+const BUNDLE_MARKER: &str = "settings";
+
+pub const EXAMPLES_DIR: &str = "examples";
+/// Keymap configs saved by the user
+pub const CONFIGS_DIR: &str = "configs";
+/// Downloaded installer and firmware build output
+pub const BUILD_DIR: &str = "firmware";
+
+/// Where the app looks for its files: the read-only data shipped with the
+/// app, layered under per-user directories.
+///
+/// Files such as `settings/url.txt` or `template/diff.json` are looked up in
+/// the user config directory first, so a copy there overrides the bundled one.
+/// Directories such as `boards/` are merged, user files shadowing bundled
+/// files of the same name.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Resources {
+    /// Directories with the bundled `boards/`, `settings/`, ..., most preferred first
+    pub bundled: Vec<PathBuf>,
+    /// User boards, languages and overridden settings
+    pub config_dir: PathBuf,
+    /// User configs and the flash history
+    pub data_dir: PathBuf,
+    /// Downloaded installer and build output
+    pub cache_dir: PathBuf,
+}
+
+/// Resources of this run, found on first use. Tests use the source tree and
+/// empty per-user directories, independent of the user's own files.
+pub fn resources() -> &'static Resources {
+    static RESOURCES: OnceLock<Resources> = OnceLock::new();
+    RESOURCES.get_or_init(|| {
+        if cfg!(test) {
+            // Statics are never dropped, so the directory is kept explicitly
+            let root = tempfile::Builder::new().prefix("ku1255-test-").tempdir().unwrap().keep();
+            Resources::isolated(&root)
+        } else {
+            Resources::detect()
+        }
+    })
+}
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+    env::var_os(var).map(PathBuf::from).filter(|dir| dir.is_absolute())
+}
+
+/// Per-user (config, data, cache) base directories of the platform.
+fn user_base_dirs() -> Option<(PathBuf, PathBuf, PathBuf)> {
+    if cfg!(target_os = "windows") {
+        let roaming = env_dir("APPDATA")?;
+        let local = env_dir("LOCALAPPDATA").unwrap_or_else(|| roaming.clone());
+        Some((roaming.clone(), roaming, local))
+    } else if cfg!(target_os = "macos") {
+        let home = env_dir("HOME")?;
+        let support = home.join("Library/Application Support");
+        Some((support.clone(), support, home.join("Library/Caches")))
+    } else {
+        let home = env_dir("HOME");
+        let xdg = |var: &str, fallback: &str| env_dir(var).or_else(|| home.as_ref().map(|h| h.join(fallback)));
+        Some((xdg("XDG_CONFIG_HOME", ".config")?, xdg("XDG_DATA_HOME", ".local/share")?, xdg("XDG_CACHE_HOME", ".cache")?))
+    }
+}
+
+impl Resources {
+    /// Bundled data next to the executable, in the working directory or, for
+    /// development builds, in the source tree; per-user directories of the
+    /// platform (XDG on Linux).
+    pub fn detect() -> Resources {
+        let mut candidates = Vec::new();
+        if let Ok(exe) = env::current_exe()
+            && let Some(dir) = exe.parent()
+        {
+            candidates.push(dir.to_path_buf());
+        }
+        if let Ok(dir) = env::current_dir() {
+            candidates.push(dir);
+        }
+        if cfg!(debug_assertions) {
+            candidates.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        }
+        let mut bundled: Vec<PathBuf> = Vec::new();
+        for dir in candidates {
+            let dir = dir.canonicalize().unwrap_or(dir);
+            if dir.join(BUNDLE_MARKER).is_dir() && !bundled.contains(&dir) {
+                bundled.push(dir);
+            }
+        }
+
+        let (config, data, cache) = user_base_dirs().unwrap_or_else(|| {
+            let temp = env::temp_dir();
+            (temp.clone(), temp.clone(), temp)
+        });
+        Resources {
+            bundled,
+            config_dir: config.join(APP_DIR_NAME),
+            data_dir: data.join(APP_DIR_NAME),
+            cache_dir: cache.join(APP_DIR_NAME),
+        }
+    }
+
+    /// Bundled data of the source tree with per-user directories under `root`.
+    pub fn isolated(root: &Path) -> Resources {
+        Resources {
+            bundled: vec![PathBuf::from(env!("CARGO_MANIFEST_DIR"))],
+            config_dir: root.join("config"),
+            data_dir: root.join("data"),
+            cache_dir: root.join("cache"),
+        }
+    }
+
+    /// First existing `relative` path, user config before bundled data. When
+    /// there is none, the path it would have in the bundled data, for messages.
+    pub fn find(&self, relative: &str) -> PathBuf {
+        let mut roots = std::iter::once(&self.config_dir).chain(&self.bundled);
+        roots
+            .clone()
+            .map(|root| root.join(relative))
+            .find(|path| path.exists())
+            .unwrap_or_else(|| roots.nth(1).map_or_else(|| PathBuf::from(relative), |root| root.join(relative)))
+    }
+
+    /// Existing `relative` directories, user config before bundled data.
+    pub fn dirs(&self, relative: &str) -> Vec<PathBuf> {
+        std::iter::once(&self.config_dir)
+            .chain(&self.bundled)
+            .map(|root| root.join(relative))
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+    /// Writable `relative` directory under `base`, created when missing.
+    fn user_dir(base: &Path, relative: &str) -> PathBuf {
+        let dir = base.join(relative);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            eprintln!("Failed to create {}: {}", dir.display(), e);
+        }
+        dir
+    }
+
+    /// User directory for new files of a merged directory such as `boards/`.
+    pub fn user_config_dir(&self, relative: &str) -> PathBuf {
+        Resources::user_dir(&self.config_dir, relative)
+    }
+
+    pub fn user_data_dir(&self, relative: &str) -> PathBuf {
+        Resources::user_dir(&self.data_dir, relative)
+    }
+
+    pub fn build_dir(&self) -> PathBuf {
+        Resources::user_dir(&self.cache_dir, BUILD_DIR)
+    }
+
+    /// Where file dialogs for configs open: the user's configs once there
+    /// are any, the bundled examples before.
+    pub fn config_dialog_dir(&self) -> PathBuf {
+        let configs = self.user_data_dir(CONFIGS_DIR);
+        let has_configs = fs::read_dir(&configs).is_ok_and(|mut entries| entries.next().is_some());
+        match self.dirs(EXAMPLES_DIR).into_iter().next() {
+            Some(examples) if !has_configs => examples,
+            _ => configs,
+        }
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "User config (boards, languages, settings overrides): {}", self.config_dir.display())?;
+        if self.bundled.is_empty() {
+            writeln!(f, "Bundled data: not found next to the executable or in the working directory")?;
+        }
+        for dir in &self.bundled {
+            writeln!(f, "Bundled data: {}", dir.display())?;
+        }
+        writeln!(f, "User data (configs, flash history): {}", self.data_dir.display())?;
+        writeln!(f, "Cache (downloaded installer, build output): {}", self.cache_dir.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_user_files_over_bundled_data() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let resources = Resources { bundled: vec![root.join("bundle")], ..Resources::isolated(root) };
+        for dir in ["bundle/settings", "bundle/boards", "bundle/examples", "config/settings"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("bundle/settings/url.txt"), "bundled").unwrap();
+        fs::write(root.join("bundle/settings/other.txt"), "bundled").unwrap();
+        fs::write(root.join("config/settings/url.txt"), "user").unwrap();
+
+        assert_eq!(resources.find("settings/url.txt"), root.join("config/settings/url.txt"));
+        assert_eq!(resources.find("settings/other.txt"), root.join("bundle/settings/other.txt"));
+        assert_eq!(resources.find("settings/missing.txt"), root.join("bundle/settings/missing.txt"));
+        assert_eq!(resources.dirs("boards"), vec![root.join("bundle/boards")]);
+        assert_eq!(resources.user_config_dir("boards"), root.join("config/boards"));
+        assert_eq!(resources.dirs("boards"), vec![root.join("config/boards"), root.join("bundle/boards")]);
+
+        // Dialogs open in the examples until the user has saved a config
+        assert_eq!(resources.config_dialog_dir(), root.join("bundle/examples"));
+        fs::write(root.join("data/configs/mine.json"), "{}").unwrap();
+        assert_eq!(resources.config_dialog_dir(), root.join("data/configs"));
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::models::{
+    BoardFile, Config, GeneralSeitting, KeyShape, LogicalLayout, KEY_HEIGHT, MACRO_KEY_TRIGGER_IDS, MEDIA_KEY_TRIGGER_IDS, MOD_KEY_ID,
+};
+
+/// Problems found in a config. Errors make the config unusable;
+/// warnings are shown but the config is still applied.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty() && self.warnings.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for e in &self.errors {
+            writeln!(f, "Error: {}", e)?;
+        }
+        for w in &self.warnings {
+            writeln!(f, "Warning: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check `config` against the boards, usage names and trigger tables in `general_setting`.
+pub fn validate_config(config: &Config, general_setting: &GeneralSeitting) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let usage_names = &general_setting.avail_hid_usage_names;
+    let has_usage_name = |id: u8| usage_names.get(&id).is_some_and(|name| !name.is_empty());
+
+    let board = general_setting
+        .avail_boards
+        .iter()
+        .find(|b| b.board_name == config.physical_layout_name);
+    if board.is_none() {
+        report.errors.push(format!("Unknown board '{}'", config.physical_layout_name));
+    }
+    if !general_setting
+        .avail_logical_layouts
+        .iter()
+        .any(|l| l.layout_name == config.logical_layout_name)
+    {
+        report.warnings.push(format!(
+            "Unknown language '{}'; key labels fall back to the board default",
+            config.logical_layout_name
+        ));
+    }
+
+    for (layer_name, layer) in [("layer0", &config.layer0), ("layer1", &config.layer1)] {
+        if let Some(board) = board {
+            let addresses: BTreeSet<u8> = board.map_address.iter().flatten().flatten().copied().collect();
+            // Saved configs carry every matrix address, so only unknown addresses
+            // and remaps of keys the board does not have are reported
+            for (address, id) in layer.iter().filter(|(a, _)| !addresses.contains(a)) {
+                match general_setting.initial_id_map.get(address) {
+                    None => report.errors.push(format!(
+                        "{}: address {:#04x} does not exist on board '{}'",
+                        layer_name, address, board.board_name
+                    )),
+                    Some(default) if default != id => report.warnings.push(format!(
+                        "{}: address {:#04x} is remapped but does not exist on board '{}'",
+                        layer_name, address, board.board_name
+                    )),
+                    Some(_) => {}
+                }
+            }
+            let missing = addresses.iter().filter(|a| !layer.contains_key(a)).count();
+            if missing > 0 {
+                report.warnings.push(format!(
+                    "{}: {} key(s) of board '{}' are not mapped",
+                    layer_name, missing, board.board_name
+                ));
+            }
+        }
+        for (address, id) in layer {
+            if let Some(id) = id.filter(|id| !has_usage_name(*id)) {
+                report.errors.push(format!(
+                    "{}: key ID {:#04x} at address {:#04x} has no usage name",
+                    layer_name, id, address
+                ));
+            }
+        }
+    }
+
+    for (address, id) in &config.layer0 {
+        if *id == Some(MOD_KEY_ID) && config.layer1.get(address) != Some(&Some(MOD_KEY_ID)) {
+            report.warnings.push(format!(
+                "The 'Mod' key at address {:#04x} must be at the same position on both layers to install",
+                address
+            ));
+        }
+    }
+
+    if !has_usage_name(config.fn_id) {
+        report.errors.push(format!("fn_id {:#04x} has no usage name", config.fn_id));
+    }
+    if !(1..=5).contains(&config.tp_sensitivity) {
+        report.errors.push(format!("tp_sensitivity {} is outside 1..=5", config.tp_sensitivity));
+    }
+
+    for (trigger, macro_key) in &config.macro_key_map {
+        if !MACRO_KEY_TRIGGER_IDS.contains(trigger) {
+            report.errors.push(format!("Macro trigger {:#04x} is not a macro key ID", trigger));
+        }
+        if macro_key.key_id != 0 && !has_usage_name(macro_key.key_id) {
+            report.errors.push(format!(
+                "Macro {:#04x}: key ID {:#04x} has no usage name",
+                trigger, macro_key.key_id
+            ));
+        }
+    }
+    for (trigger, usage) in &config.media_key_map {
+        if !MEDIA_KEY_TRIGGER_IDS.contains(trigger) {
+            report.errors.push(format!("Media trigger {:#04x} is not a media key ID", trigger));
+        }
+        if !general_setting.avail_media_key_usage_names.contains_key(usage) {
+            report.errors.push(format!(
+                "Media {:#04x}: usage {:#06x} is not in media_key_usage_names.csv",
+                trigger, usage
+            ));
+        }
+    }
+
+    report
+}
+
+/// Problems found in one board definition file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoardDiagnostics {
+    pub path: PathBuf,
+    pub report: ValidationReport,
+}
+
+impl fmt::Display for BoardDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:", self.path.display())?;
+        for line in self.report.to_string().lines() {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a board file: the key ID, address and width grids must have the same
+/// shape, every address must be a known matrix address used only once and every
+/// key must have a positive width.
+pub fn validate_board_file(
+    board_file: &BoardFile,
+    known_addresses: &BTreeSet<u8>,
+    logical_layouts: &[LogicalLayout],
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    report.errors.extend(board_file.invalid_tokens.iter().cloned());
+
+    if board_file.board_name.is_empty() {
+        report.errors.push("[board_name] is missing".into());
+    }
+    if board_file.map_address.is_empty() {
+        report.errors.push("[key_address] is missing".into());
+    }
+
+    let row_lengths = |rows: &[Vec<Option<u8>>]| rows.iter().map(Vec::len).collect::<Vec<_>>();
+    let address_lengths = row_lengths(&board_file.map_address);
+    let width_lengths = board_file.map_widths.iter().map(Vec::len).collect::<Vec<_>>();
+    for (section, lengths) in [("[key_id]", row_lengths(&board_file.map_ids)), ("[key_width]", width_lengths)] {
+        if lengths.len() != address_lengths.len() {
+            report.errors.push(format!(
+                "{} has {} rows but [key_address] has {}",
+                section, lengths.len(), address_lengths.len()
+            ));
+            continue;
+        }
+        for (i, (length, address_length)) in lengths.iter().zip(&address_lengths).enumerate() {
+            if length != address_length {
+                report.errors.push(format!(
+                    "{} row {} has {} keys but [key_address] has {}",
+                    section, i + 1, length, address_length
+                ));
+            }
+        }
+    }
+
+    // Blank cells with width 0 are spacers; keys need a positive width
+    for (i, (widths, addresses)) in board_file.map_widths.iter().zip(&board_file.map_address).enumerate() {
+        for (width, address) in widths.iter().zip(addresses) {
+            if let (0, Some(address)) = (width, address) {
+                report.errors.push(format!("[key_width] row {}: key {:02X} has width 0", i + 1, address));
+            }
+        }
+    }
+
+    for key in &board_file.keys {
+        if key.w == 0 || key.h == 0 {
+            report.errors.push(format!("Key {:02X} has size {}x{}", key.address, key.w, key.h));
+        }
+        if key.shape == KeyShape::IsoEnter && (key.notch >= key.w || key.h <= KEY_HEIGHT) {
+            report.errors.push(format!(
+                "Key {:02X}: an ISO Enter must be more than one row high and wider than its notch",
+                key.address
+            ));
+        }
+    }
+
+    let mut rows_of_address: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+    for (i, row) in board_file.map_address.iter().enumerate() {
+        for address in row.iter().flatten() {
+            rows_of_address.entry(*address).or_default().push(i + 1);
+        }
+    }
+    for (address, rows) in &rows_of_address {
+        if rows.len() > 1 {
+            report.errors.push(format!("Address {:02X} is used {} times (rows {:?})", address, rows.len(), rows));
+        }
+        if !known_addresses.contains(address) {
+            report.errors.push(format!(
+                "Address {:02X} (row {}) is in neither general_setting.csv nor keymatrix.csv",
+                address, rows[0]
+            ));
+        }
+    }
+
+    if !logical_layouts.iter().any(|l| l.layout_name == board_file.default_logical_layout_name) {
+        report.warnings.push(format!(
+            "Default logical layout '{}' does not exist in logical_layouts",
+            board_file.default_logical_layout_name
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        Board, KeyGeometry, MacroKey, default_enable_middle_click, default_fn_id, default_macro_key_map, default_media_key_map,
+        default_tp_sensitivity,
+    };
+
+    /// Checks that `report` has exactly one error or warning per expected part
+    /// of its message.
+    fn assert_report(report: &ValidationReport, errors: &[&str], warnings: &[&str]) {
+        for (found, expected, kind) in [(&report.errors, errors, "errors"), (&report.warnings, warnings, "warnings")] {
+            assert_eq!(found.len(), expected.len(), "{} {:?}: {}", kind, expected, report);
+            for part in expected {
+                assert!(found.iter().any(|message| message.contains(part)), "'{}' not in {}", part, report);
+            }
+        }
+    }
+
+    fn config_for(board: &Board) -> Config {
+        Config {
+            config_version: crate::utils::CONFIG_VERSION,
+            physical_layout_name: board.board_name.clone(),
+            logical_layout_name: board.default_logical_layout_name.clone(),
+            layer0: board.initial_id_map.clone(),
+            layer1: board.initial_id_map.clone(),
+            fn_id: default_fn_id(),
+            tp_sensitivity: default_tp_sensitivity(),
+            macro_key_map: default_macro_key_map(),
+            media_key_map: default_media_key_map(),
+            enable_middle_click: default_enable_middle_click(),
+        }
+    }
+
+    #[test]
+    fn reports_each_config_problem() {
+        let mut general_setting = GeneralSeitting::load_from_files().unwrap();
+        // A board without every matrix address of the KU-1255
+        let (board, absent) = general_setting
+            .avail_boards
+            .iter()
+            .find_map(|board| {
+                let absent = general_setting.initial_id_map.keys().find(|a| !board.initial_id_map.contains_key(*a))?;
+                Some((board, *absent))
+            })
+            .unwrap();
+        let valid = config_for(board);
+        assert_report(&validate_config(&valid, &general_setting), &[], &[]);
+
+        let (&address, _) = board.initial_id_map.iter().find(|(_, id)| id.is_some()).unwrap();
+        // Every ID has a usage name, so drop the name of one the config does not use.
+        // 0 means no key in macros and is skipped
+        let unnamed = (1..=u8::MAX)
+            .find(|id| !board.initial_id_map.values().any(|used| *used == Some(*id)) && *id != valid.fn_id)
+            .unwrap();
+        general_setting.avail_hid_usage_names.remove(&unnamed);
+        let unused_address = (0..=u8::MAX).find(|a| !general_setting.initial_id_map.contains_key(a)).unwrap();
+        let trigger = *default_macro_key_map().keys().next().unwrap();
+        let media_trigger = *default_media_key_map().keys().next().unwrap();
+        let not_a_trigger = 0x04;
+
+        type Edit = Box<dyn Fn(&mut Config)>;
+        let cases: Vec<(Edit, &[&str], &[&str])> = vec![
+            (Box::new(|c| c.physical_layout_name = "nonexistent".into()), &["Unknown board"], &[]),
+            (Box::new(|c| c.logical_layout_name = "Klingon".into()), &[], &["Unknown language"]),
+            (Box::new(move |c| { c.layer0.insert(unused_address, None); }), &["does not exist on board"], &[]),
+            (Box::new(move |c| { c.layer0.insert(absent, Some(0x04)); }), &[], &["is remapped but does not exist"]),
+            (Box::new(move |c| { c.layer1.remove(&address); }), &[], &["layer1: 1 key(s)"]),
+            (Box::new(move |c| { c.layer0.insert(address, Some(unnamed)); }), &["has no usage name"], &[]),
+            (Box::new(move |c| { c.layer0.insert(address, Some(MOD_KEY_ID)); }), &[], &["'Mod' key"]),
+            (Box::new(move |c| c.fn_id = unnamed), &["fn_id"], &[]),
+            (Box::new(|c| c.tp_sensitivity = 0), &["outside 1..=5"], &[]),
+            (Box::new(move |c| { c.macro_key_map.insert(not_a_trigger, MacroKey::new()); }), &["not a macro key ID"], &[]),
+            (
+                Box::new(move |c| c.macro_key_map.get_mut(&trigger).unwrap().key_id = unnamed),
+                &["Macro"],
+                &[],
+            ),
+            (Box::new(move |c| { c.media_key_map.insert(not_a_trigger, 0xE9); }), &["not a media key ID"], &[]),
+            (
+                Box::new(move |c| { c.media_key_map.insert(media_trigger, 0xFFFF); }),
+                &["not in media_key_usage_names.csv"],
+                &[],
+            ),
+        ];
+        for (edit, errors, warnings) in cases {
+            let mut config = valid.clone();
+            edit(&mut config);
+            assert_report(&validate_config(&config, &general_setting), errors, warnings);
+        }
+    }
+
+    #[test]
+    fn reports_each_board_problem() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let known = general_setting.known_addresses();
+        let unknown = (0..=u8::MAX).find(|a| !known.contains(a)).unwrap();
+        let valid = BoardFile {
+            board_name: "test".into(),
+            default_logical_layout_name: "US_English".into(),
+            map_ids: vec![vec![Some(0x29), None]],
+            map_address: vec![vec![Some(0x60), None]],
+            map_widths: vec![vec![48, 20]],
+            ..BoardFile::default()
+        };
+        let key = |shape, w, h, notch| KeyGeometry {
+            x: 0, y: 0, w, h, shape, notch, label: String::new(), address: 0x60, id: Some(0x29),
+        };
+        let validate = |board_file: &BoardFile| validate_board_file(board_file, &known, &general_setting.avail_logical_layouts);
+        assert_report(&validate(&valid), &[], &[]);
+
+        type Edit = Box<dyn Fn(&mut BoardFile)>;
+        let cases: Vec<(Edit, &[&str], &[&str])> = vec![
+            (Box::new(|b| b.invalid_tokens.push("Row 1: 'zz' is not a hex byte".into())), &["'zz'"], &[]),
+            (Box::new(|b| b.board_name.clear()), &["[board_name] is missing"], &[]),
+            (
+                Box::new(|b| { b.map_address.clear(); b.map_ids.clear(); b.map_widths.clear(); }),
+                &["[key_address] is missing"],
+                &[],
+            ),
+            (Box::new(|b| b.map_widths.push(vec![48])), &["[key_width] has 2 rows"], &[]),
+            (Box::new(|b| { b.map_ids[0].pop(); }), &["[key_id] row 1 has 1 keys"], &[]),
+            (Box::new(|b| b.map_widths[0][0] = 0), &["has width 0"], &[]),
+            (Box::new(move |b| b.keys.push(key(KeyShape::Rect, 0, KEY_HEIGHT, 0))), &["has size 0x"], &[]),
+            (Box::new(move |b| b.keys.push(key(KeyShape::IsoEnter, 76, KEY_HEIGHT, 24))), &["ISO Enter"], &[]),
+            (
+                Box::new(|b| { b.map_address.push(vec![Some(0x60)]); b.map_ids.push(vec![None]); b.map_widths.push(vec![48]); }),
+                &["used 2 times"],
+                &[],
+            ),
+            (Box::new(move |b| b.map_address[0][1] = Some(unknown)), &["neither general_setting.csv nor keymatrix.csv"], &[]),
+            (Box::new(|b| b.default_logical_layout_name = "Klingon".into()), &[], &["'Klingon'"]),
+        ];
+        for (edit, errors, warnings) in cases {
+            let mut board_file = valid.clone();
+            edit(&mut board_file);
+            assert_report(&validate(&board_file), errors, warnings);
+        }
+    }
+
+    #[test]
+    fn reports_board_file_problems() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        assert_eq!(general_setting.board_diagnostics, vec![]);
+
+        let board_file = BoardFile {
+            board_name: "broken".into(),
+            default_logical_layout_name: "US_English".into(),
+            map_ids: vec![vec![Some(0x29), Some(0x3a)]],
+            map_address: vec![vec![Some(0x60), Some(0x60), None]],
+            map_widths: vec![vec![48, 0, 0]],
+            ..BoardFile::default()
+        };
+        let report = validate_board_file(&board_file, &general_setting.known_addresses(), &general_setting.avail_logical_layouts);
+        assert_eq!(report.errors.len(), 3, "{}", report);
+        assert!(report.warnings.is_empty());
+    }
+}
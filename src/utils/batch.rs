@@ -0,0 +1,167 @@
+use std::fmt::{self, Write as _};
+
+use crate::utils::flasher::{FlashError, FlashImage, FlashProgress, Flasher, Transport};
+
+/// Where one keyboard of a batch currently stands.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceOutcome {
+    Pending,
+    Flashing(FlashProgress),
+    /// Flashed and the post-programming checksum matched
+    Verified { checksum: u16 },
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchEntry {
+    pub device: String,
+    pub outcome: DeviceOutcome,
+}
+
+/// State of a batch flash, updated after every step of every device.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchStatus {
+    pub stage: String,
+    pub entries: Vec<BatchEntry>,
+    pub image_checksum: Option<u16>,
+    /// Set when the image could not be built; no device is touched then
+    pub build_error: Option<String>,
+    /// Problems of the build that did not stop it
+    pub build_notes: Vec<String>,
+    pub finished: bool,
+    pub report_path: Option<String>,
+}
+
+impl BatchStatus {
+    pub fn stage(caption: &str) -> BatchStatus {
+        BatchStatus { stage: caption.to_string(), ..BatchStatus::default() }
+    }
+
+    pub fn verified_count(&self) -> usize {
+        self.entries.iter().filter(|e| matches!(e.outcome, DeviceOutcome::Verified { .. })).count()
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.entries.iter().filter(|e| matches!(e.outcome, DeviceOutcome::Failed(_))).count()
+    }
+
+    /// Plain text summary, one line per device.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(&mut out, "KU-1255 batch flash report");
+        if let Some(checksum) = self.image_checksum {
+            let _ = writeln!(&mut out, "Image checksum: {:#06x}", checksum);
+        }
+        if let Some(err) = &self.build_error {
+            let _ = writeln!(&mut out, "Build failed: {}", err);
+        }
+        for note in &self.build_notes {
+            let _ = writeln!(&mut out, "Note: {}", note);
+        }
+        let _ = writeln!(
+            &mut out,
+            "Devices: {}, verified: {}, failed: {}",
+            self.entries.len(),
+            self.verified_count(),
+            self.failed_count()
+        );
+        let _ = writeln!(&mut out);
+        for entry in &self.entries {
+            let _ = writeln!(&mut out, "{}", entry);
+        }
+        out
+    }
+}
+
+impl fmt::Display for BatchEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            DeviceOutcome::Pending => write!(f, "[SKIPPED] {}  not flashed", self.device),
+            DeviceOutcome::Flashing(progress) => write!(f, "[RUNNING] {}  {}", self.device, progress.stage),
+            DeviceOutcome::Verified { checksum } => {
+                write!(f, "[OK]      {}  checksum {:#06x} verified", self.device, checksum)
+            }
+            DeviceOutcome::Failed(err) => write!(f, "[FAILED]  {}  {}", self.device, err),
+        }
+    }
+}
+
+/// Flash `image` to every device in turn, opening each with `open`.
+///
+/// A failure only marks its own entry; the remaining devices are still flashed.
+/// `update` receives a fresh snapshot whenever anything changes.
+pub fn flash_batch<D, T, O, U>(devices: &[D], image: &FlashImage, mut open: O, mut update: U) -> BatchStatus
+where
+    D: fmt::Display,
+    T: Transport,
+    O: FnMut(&D) -> Result<T, FlashError>,
+    U: FnMut(&BatchStatus) + Clone + 'static,
+{
+    let mut status = BatchStatus {
+        stage: "Flashing...".into(),
+        entries: devices
+            .iter()
+            .map(|d| BatchEntry { device: d.to_string(), outcome: DeviceOutcome::Pending })
+            .collect(),
+        image_checksum: Some(image.expected_checksum()),
+        ..BatchStatus::default()
+    };
+    update(&status);
+
+    for (i, device) in devices.iter().enumerate() {
+        status.stage = format!("Flashing keyboard {} of {}...", i + 1, devices.len());
+        status.entries[i].outcome = DeviceOutcome::Flashing(FlashProgress::stage("Opening keyboard..."));
+        update(&status);
+
+        let result = open(device).and_then(|transport| {
+            let mut snapshot = status.clone();
+            let mut observer_update = update.clone();
+            let mut flasher = Flasher::new(transport).with_observer(move |progress| {
+                snapshot.entries[i].outcome = DeviceOutcome::Flashing(progress.clone());
+                observer_update(&snapshot);
+            });
+            flasher.flash(image)
+        });
+        status.entries[i].outcome = match result {
+            Ok(()) => DeviceOutcome::Verified { checksum: image.expected_checksum() },
+            Err(e) => DeviceOutcome::Failed(e.to_string()),
+        };
+        update(&status);
+    }
+
+    status.stage = "Batch finished.".into();
+    status.finished = true;
+    update(&status);
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::emulator::{EmulatedDevice, Faults, test_image};
+
+    #[test]
+    fn continues_past_failed_devices() {
+        let stock = test_image(1);
+        let new = test_image(2);
+        let image = FlashImage::from_bytes(&new).unwrap();
+        let devices = ["kb-a", "kb-b", "kb-c"];
+        let status = flash_batch(
+            &devices,
+            &image,
+            |name| {
+                let device = EmulatedDevice::with_image(&stock);
+                Ok(match *name {
+                    "kb-b" => device.with_faults(Faults { disconnect_at: Some(500), ..Faults::default() }),
+                    _ => device,
+                })
+            },
+            |_| {},
+        );
+        assert!(status.finished);
+        assert_eq!(status.verified_count(), 2);
+        assert_eq!(status.failed_count(), 1);
+        assert_eq!(status.entries[1].outcome, DeviceOutcome::Failed(FlashError::Disconnected.to_string()));
+        assert!(status.report().contains("Devices: 3, verified: 2, failed: 1"));
+    }
+}
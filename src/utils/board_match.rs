@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::models::{Board, GeneralSeitting};
+use crate::utils::firmware::{board_keymask, decompile_firmware};
+use crate::utils::history::{find_flashed_firmware, find_history_by_device, load_history_image};
+
+/// Board inferred from the key mask and key table of a firmware.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoardMatch {
+    pub board_name: String,
+    pub default_logical_layout_name: String,
+    /// The firmware scans exactly the keys of this board
+    pub exact: bool,
+    /// Other boards the firmware is also consistent with
+    pub alternatives: Vec<String>,
+}
+
+impl BoardMatch {
+    /// Whether `board_name` is consistent with the firmware.
+    pub fn allows(&self, board_name: &str) -> bool {
+        self.board_name == board_name || self.alternatives.iter().any(|name| name == board_name)
+    }
+
+    /// Warning when `board_name` contradicts the detected board.
+    pub fn mismatch_warning(&self, board_name: &str) -> Option<String> {
+        (!self.allows(board_name)).then(|| {
+            format!(
+                "The selected keyboard '{}' does not match the detected keyboard '{}'. Check the ANSI/ISO/JIS variant before installing.",
+                board_name, self
+            )
+        })
+    }
+}
+
+impl fmt::Display for BoardMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.board_name)?;
+        if !self.alternatives.is_empty() {
+            write!(f, " (or {})", self.alternatives.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys of `board` that contradict the firmware, and keys the firmware scans
+/// that `board` lacks.
+fn compare_board(board: &Board, keymask: &[u8; 16], key_table: Option<&BTreeMap<u8, Option<u8>>>) -> (usize, usize) {
+    let board_mask = board_keymask(board);
+    let bits = |mask: &[u8; 16], f: fn(u8, u8) -> u8| mask.iter().zip(board_mask).map(|(m, b)| f(*m, b).count_ones() as usize).sum::<usize>();
+    let mut conflicts = bits(keymask, |mask, board| board & !mask);
+    let extra = bits(keymask, |mask, board| mask & !board);
+    if let Some(key_table) = key_table {
+        conflicts += board.initial_id_map
+            .iter()
+            .filter(|(address, id)| {
+                matches!((id, key_table.get(address)), (Some(id), Some(Some(table_id))) if id != table_id)
+            })
+            .count();
+    }
+    (conflicts, extra)
+}
+
+/// Most likely board for a firmware scanning the keys of `keymask`.
+///
+/// `key_table` is the firmware's main layer; it is only evidence for stock
+/// firmware, whose table holds the original key IDs. Boards with keys the
+/// firmware does not scan, or with other original key IDs, are ruled out;
+/// of the rest, the board leaving the fewest scanned keys unused wins.
+pub fn infer_board(
+    boards: &[Board],
+    keymask: &[u8; 16],
+    key_table: Option<&BTreeMap<u8, Option<u8>>>,
+) -> Option<BoardMatch> {
+    let mut candidates: Vec<(usize, &Board)> = boards
+        .iter()
+        .filter_map(|board| match compare_board(board, keymask, key_table) {
+            (0, extra) => Some((extra, board)),
+            _ => None,
+        })
+        .collect();
+    candidates.sort_by_key(|(extra, _)| *extra);
+    let (&(extra, best), rest) = candidates.split_first()?;
+    Some(BoardMatch {
+        board_name: best.board_name.clone(),
+        default_logical_layout_name: best.default_logical_layout_name.clone(),
+        exact: extra == 0,
+        alternatives: if extra == 0 {
+            Vec::new()
+        } else {
+            rest.iter().map(|(_, board)| board.board_name.clone()).collect()
+        },
+    })
+}
+
+/// Board of the firmware on a keyboard, decompiled from the flash history:
+/// the image matching the flash `checksum` when it is known, else the last
+/// verified flash of the keyboard with the history identity `device`.
+pub fn infer_board_from_history(
+    original_binary: &[u8],
+    device: &str,
+    checksum: Option<u16>,
+    general_setting: &GeneralSeitting,
+) -> Result<Option<BoardMatch>, String> {
+    let by_checksum = match checksum {
+        Some(checksum) => find_flashed_firmware(checksum, device)?.into_iter().next().map(|(entry, _)| entry),
+        None => None,
+    };
+    let entry = match by_checksum {
+        Some(entry) => entry,
+        None => match find_history_by_device(device)?.into_iter().next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        },
+    };
+    let (bytes, _) = load_history_image(&entry)?;
+    Ok(decompile_firmware(original_binary, &bytes, general_setting)?.board_match)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_board_from_key_mask() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let boards = &general_setting.avail_boards;
+        for board in boards {
+            let found = infer_board(boards, &board_keymask(board), None).unwrap();
+            assert_eq!(found.board_name, board.board_name);
+            assert!(found.exact);
+            assert_eq!(found.mismatch_warning(&board.board_name), None);
+        }
+
+        // A mask covering two boards suggests the closer one and allows both
+        let (a, b) = (&boards[0], &boards[1]);
+        let mut union = board_keymask(a);
+        for (mask, row) in union.iter_mut().zip(board_keymask(b)) {
+            *mask |= row;
+        }
+        let found = infer_board(boards, &union, None).unwrap();
+        assert!(!found.exact);
+        assert!(found.allows(&a.board_name) && found.allows(&b.board_name));
+
+        // Original key IDs that differ from the stock table rule a board out
+        let mut key_table = a.initial_id_map.clone();
+        for id in key_table.values_mut() {
+            *id = id.map(|id| id.wrapping_add(1));
+        }
+        assert_eq!(infer_board(std::slice::from_ref(a), &board_keymask(a), Some(&key_table)), None);
+        assert!(found.mismatch_warning("NO_SUCH_BOARD").is_some());
+    }
+
+    #[test]
+    fn warns_when_the_selected_board_contradicts_the_detected_one() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let boards = &general_setting.avail_boards;
+        let (a, b) = (&boards[0], &boards[1]);
+        let found = infer_board(boards, &board_keymask(a), None).unwrap();
+        let warning = found.mismatch_warning(&b.board_name).unwrap();
+        assert!(warning.contains(&format!("'{}'", b.board_name)));
+        assert!(warning.contains(&format!("'{}'", a.board_name)));
+        assert_eq!(found.mismatch_warning(&a.board_name), None);
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::models::{
+    Board, GeneralSeitting, default_fn_id, default_macro_key_map, default_media_key_map,
+    default_tp_sensitivity, keys_from_rows,
+};
+use crate::utils::BuildSettings;
+
+// Keys are drawn `width` px wide with a 4 px gap, so 1u is 52 px including the gap
+const UNIT_PX: f64 = 52.0;
+const KEY_GAP_PX: f64 = 4.0;
+
+/// Widths offered for a recorded key, in units.
+pub const WIZARD_KEY_UNITS: [f64; 11] = [1.0, 1.25, 1.5, 1.75, 2.0, 2.25, 2.5, 2.75, 3.0, 6.0, 6.25];
+
+/// Key IDs of the DOM `KeyboardEvent.code` values. "Backslash" is also sent
+/// by the ISO "#" key (0x32).
+const DOM_CODES: [(&str, u8); 127] = [
+    ("KeyA", 0x04), ("KeyB", 0x05), ("KeyC", 0x06), ("KeyD", 0x07), ("KeyE", 0x08),
+    ("KeyF", 0x09), ("KeyG", 0x0A), ("KeyH", 0x0B), ("KeyI", 0x0C), ("KeyJ", 0x0D),
+    ("KeyK", 0x0E), ("KeyL", 0x0F), ("KeyM", 0x10), ("KeyN", 0x11), ("KeyO", 0x12),
+    ("KeyP", 0x13), ("KeyQ", 0x14), ("KeyR", 0x15), ("KeyS", 0x16), ("KeyT", 0x17),
+    ("KeyU", 0x18), ("KeyV", 0x19), ("KeyW", 0x1A), ("KeyX", 0x1B), ("KeyY", 0x1C),
+    ("KeyZ", 0x1D),
+    ("Digit1", 0x1E), ("Digit2", 0x1F), ("Digit3", 0x20), ("Digit4", 0x21), ("Digit5", 0x22),
+    ("Digit6", 0x23), ("Digit7", 0x24), ("Digit8", 0x25), ("Digit9", 0x26), ("Digit0", 0x27),
+    ("Enter", 0x28), ("Escape", 0x29), ("Backspace", 0x2A), ("Tab", 0x2B), ("Space", 0x2C),
+    ("Minus", 0x2D), ("Equal", 0x2E), ("BracketLeft", 0x2F), ("BracketRight", 0x30),
+    ("Backslash", 0x31), ("Backslash", 0x32), ("Semicolon", 0x33), ("Quote", 0x34),
+    ("Backquote", 0x35), ("Comma", 0x36), ("Period", 0x37), ("Slash", 0x38), ("CapsLock", 0x39),
+    ("F1", 0x3A), ("F2", 0x3B), ("F3", 0x3C), ("F4", 0x3D), ("F5", 0x3E), ("F6", 0x3F),
+    ("F7", 0x40), ("F8", 0x41), ("F9", 0x42), ("F10", 0x43), ("F11", 0x44), ("F12", 0x45),
+    ("PrintScreen", 0x46), ("ScrollLock", 0x47), ("Pause", 0x48), ("Insert", 0x49),
+    ("Home", 0x4A), ("PageUp", 0x4B), ("Delete", 0x4C), ("End", 0x4D), ("PageDown", 0x4E),
+    ("ArrowRight", 0x4F), ("ArrowLeft", 0x50), ("ArrowDown", 0x51), ("ArrowUp", 0x52),
+    ("NumLock", 0x53), ("NumpadDivide", 0x54), ("NumpadMultiply", 0x55),
+    ("NumpadSubtract", 0x56), ("NumpadAdd", 0x57), ("NumpadEnter", 0x58),
+    ("Numpad1", 0x59), ("Numpad2", 0x5A), ("Numpad3", 0x5B), ("Numpad4", 0x5C),
+    ("Numpad5", 0x5D), ("Numpad6", 0x5E), ("Numpad7", 0x5F), ("Numpad8", 0x60),
+    ("Numpad9", 0x61), ("Numpad0", 0x62), ("NumpadDecimal", 0x63), ("IntlBackslash", 0x64),
+    ("ContextMenu", 0x65), ("NumpadEqual", 0x67),
+    ("F13", 0x68), ("F14", 0x69), ("F15", 0x6A), ("F16", 0x6B), ("F17", 0x6C), ("F18", 0x6D),
+    ("F19", 0x6E), ("F20", 0x6F), ("F21", 0x70), ("F22", 0x71), ("F23", 0x72), ("F24", 0x73),
+    ("NumpadComma", 0x85), ("IntlRo", 0x87), ("KanaMode", 0x88), ("IntlYen", 0x89),
+    ("Convert", 0x8A), ("NonConvert", 0x8B), ("Lang1", 0x90), ("Lang2", 0x91),
+    ("ControlLeft", 0xE0), ("ShiftLeft", 0xE1), ("AltLeft", 0xE2), ("MetaLeft", 0xE3),
+    ("ControlRight", 0xE4), ("ShiftRight", 0xE5), ("AltRight", 0xE6), ("MetaRight", 0xE7),
+];
+
+/// Key IDs never sent by the diagnostic firmware: ambiguous codes, lock keys,
+/// keys the OS acts on, and the firmware's Mod key.
+const DIAGNOSTIC_EXCLUDED_IDS: [u8; 9] = [0x31, 0x32, 0x39, 0x46, 0x47, 0x53, 0xE2, 0xE3, 0xE7];
+
+/// How key presses are traced back to matrix addresses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WizardMode {
+    /// The keyboard runs its stock firmware; keys are looked up in `general_setting.csv`
+    Stock,
+    /// The keyboard runs the diagnostic firmware, one unique key per address
+    Diagnostic,
+}
+
+/// Key IDs that a DOM `KeyboardEvent.code` may stand for.
+pub fn hid_ids_from_code(code: &str) -> Vec<u8> {
+    DOM_CODES.iter().filter(|(c, _)| *c == code).map(|(_, id)| *id).collect()
+}
+
+/// Layer mapping every known matrix address to a distinct key that the
+/// wizard can recognise.
+pub fn diagnostic_layer(general_setting: &GeneralSeitting) -> Result<BTreeMap<u8, Option<u8>>, String> {
+    let mut ids = DOM_CODES
+        .iter()
+        .map(|(_, id)| *id)
+        .filter(|id| !DIAGNOSTIC_EXCLUDED_IDS.contains(id))
+        .collect::<BTreeSet<u8>>()
+        .into_iter();
+    general_setting.initial_id_map
+        .keys()
+        .map(|address| {
+            ids.next()
+                .map(|id| (*address, Some(id)))
+                .ok_or_else(|| "Not enough distinct keys for a diagnostic mapping".to_string())
+        })
+        .collect()
+}
+
+/// Settings of the diagnostic firmware: both layers use [`diagnostic_layer`]
+/// and every known address is populated.
+pub fn diagnostic_settings(general_setting: &GeneralSeitting) -> Result<BuildSettings, String> {
+    let layer = diagnostic_layer(general_setting)?;
+    let map_address = vec![layer.keys().map(|address| Some(*address)).collect::<Vec<_>>()];
+    let map_widths = vec![vec![48; layer.len()]];
+    let keys = keys_from_rows(&map_address, &map_widths, &layer);
+    let board = Board {
+        board_name: "diagnostic".to_string(),
+        board_label: "Diagnostic mapping".to_string(),
+        default_logical_layout_name: String::new(),
+        map_widths,
+        map_address,
+        initial_id_map: layer.clone(),
+        keys,
+    };
+    Ok(BuildSettings {
+        layout0: layer.clone(),
+        layout1: layer,
+        fn_id: default_fn_id(),
+        tp_sensitivity: default_tp_sensitivity(),
+        macro_key_map: default_macro_key_map(),
+        media_key_map: default_media_key_map(),
+        enable_middle_click: false,
+        board,
+        logical_layout_name: String::new(),
+    })
+}
+
+/// Key width in px for a width in units.
+pub fn unit_width(units: f64) -> u16 {
+    (units * UNIT_PX - KEY_GAP_PX).round() as u16
+}
+
+/// Rows of matrix addresses recorded while the user presses each key in turn.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoardRecorder {
+    pub mode: WizardMode,
+    /// Matrix address of each key ID sent in `mode`
+    decode: BTreeMap<u8, u8>,
+    known_addresses: BTreeSet<u8>,
+    /// Address and width in px of each key, left to right
+    pub rows: Vec<Vec<(u8, u16)>>,
+}
+
+impl BoardRecorder {
+    pub fn new(mode: WizardMode, general_setting: &GeneralSeitting) -> Result<BoardRecorder, String> {
+        let layer = match mode {
+            WizardMode::Stock => general_setting.initial_id_map.clone(),
+            WizardMode::Diagnostic => diagnostic_layer(general_setting)?,
+        };
+        Ok(BoardRecorder {
+            mode,
+            decode: layer.into_iter().filter_map(|(address, id)| id.map(|id| (id, address))).collect(),
+            known_addresses: general_setting.known_addresses(),
+            rows: vec![Vec::new()],
+        })
+    }
+
+    fn is_recorded(&self, address: u8) -> bool {
+        self.rows.iter().flatten().any(|(a, _)| *a == address)
+    }
+
+    /// Record the key that sent the DOM `code` and return its address.
+    pub fn press(&mut self, code: &str) -> Result<u8, String> {
+        let addresses = hid_ids_from_code(code)
+            .into_iter()
+            .filter_map(|id| self.decode.get(&id).copied())
+            .collect::<Vec<_>>();
+        let Some(address) = addresses.iter().copied().find(|a| !self.is_recorded(*a)) else {
+            return Err(match addresses.first() {
+                Some(address) => format!("{} (address {:02X}) is already recorded", code, address),
+                None => format!("{} is not sent by any matrix address; enter the address by hand", code),
+            });
+        };
+        self.add_address(address)?;
+        Ok(address)
+    }
+
+    /// Record a key by its matrix address, e.g. one that sends nothing on the stock firmware.
+    pub fn add_address(&mut self, address: u8) -> Result<(), String> {
+        if !self.known_addresses.contains(&address) {
+            return Err(format!("Unknown matrix address {:02X}", address));
+        }
+        if self.is_recorded(address) {
+            return Err(format!("Address {:02X} is already recorded", address));
+        }
+        if let Some(row) = self.rows.last_mut() {
+            row.push((address, unit_width(1.0)));
+        }
+        Ok(())
+    }
+
+    /// Start a new row, unless the current one is still empty.
+    pub fn new_row(&mut self) {
+        if self.rows.last().is_some_and(|row| !row.is_empty()) {
+            self.rows.push(Vec::new());
+        }
+    }
+
+    /// Remove the last key, or the last empty row.
+    pub fn undo(&mut self) {
+        if let Some(row) = self.rows.last_mut()
+            && row.pop().is_none()
+            && self.rows.len() > 1
+        {
+            self.rows.pop();
+        }
+    }
+
+    pub fn set_last_width(&mut self, width: u16) {
+        if let Some(key) = self.rows.last_mut().and_then(|row| row.last_mut()) {
+            key.1 = width;
+        }
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.rows.iter().map(Vec::len).sum()
+    }
+
+    /// Board of the recorded rows, with the stock key IDs of `general_setting.csv`.
+    pub fn to_board(
+        &self,
+        board_name: &str,
+        board_label: &str,
+        default_logical_layout_name: &str,
+        general_setting: &GeneralSeitting,
+    ) -> Board {
+        let rows = self.rows.iter().filter(|row| !row.is_empty());
+        let map_address = rows.clone().map(|row| row.iter().map(|(a, _)| Some(*a)).collect()).collect::<Vec<_>>();
+        let map_widths = rows.map(|row| row.iter().map(|(_, w)| *w).collect()).collect::<Vec<_>>();
+        let initial_id_map = self.rows
+            .iter()
+            .flatten()
+            .map(|(address, _)| (*address, general_setting.initial_id_map.get(address).copied().flatten()))
+            .collect();
+        let keys = keys_from_rows(&map_address, &map_widths, &initial_id_map);
+        Board {
+            board_name: board_name.to_string(),
+            board_label: board_label.to_string(),
+            default_logical_layout_name: default_logical_layout_name.to_string(),
+            map_widths,
+            map_address,
+            initial_id_map,
+            keys,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_board_from_key_presses() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let layer = diagnostic_layer(&general_setting).unwrap();
+        assert_eq!(layer.len(), general_setting.initial_id_map.len());
+        let ids = layer.values().flatten().collect::<BTreeSet<_>>();
+        assert_eq!(ids.len(), layer.len());
+        assert!(!ids.contains(&crate::models::MOD_KEY_ID));
+
+        // The diagnostic key of an address leads back to it
+        let mut recorder = BoardRecorder::new(WizardMode::Diagnostic, &general_setting).unwrap();
+        let (&address, &id) = layer.iter().nth(3).unwrap();
+        let code = DOM_CODES.iter().find(|(_, i)| Some(*i) == id).unwrap().0;
+        assert_eq!(recorder.press(code), Ok(address));
+        assert!(recorder.press(code).is_err());
+        recorder.set_last_width(unit_width(1.5));
+        recorder.new_row();
+        let other = *layer.keys().next().unwrap();
+        recorder.add_address(other).unwrap();
+        assert!(recorder.add_address(0x7f).is_err());
+
+        let board = recorder.to_board("TEST", "Test", "US", &general_setting);
+        assert_eq!(board.map_address, vec![vec![Some(address)], vec![Some(other)]]);
+        assert_eq!(board.map_widths, vec![vec![74], vec![48]]);
+        assert_eq!(board.initial_id_map[&address], general_setting.initial_id_map[&address]);
+
+        // On the stock firmware, Escape is found at its address in general_setting.csv
+        let mut recorder = BoardRecorder::new(WizardMode::Stock, &general_setting).unwrap();
+        let escape = general_setting.initial_id_map.iter().find(|(_, id)| **id == Some(0x29)).unwrap().0;
+        assert_eq!(recorder.press("Escape"), Ok(*escape));
+        recorder.undo();
+        assert_eq!(recorder.key_count(), 0);
+    }
+}
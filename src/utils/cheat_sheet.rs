@@ -0,0 +1,362 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::models::{Board, Config, GeneralSeitting, KeyGeometry, KeyLabel, KeyShape, LogicalLayout};
+use crate::utils::keymap::Names;
+
+// Same geometry as the `Keyboard` component: keys placed in px, rows 40 high with 4 px gaps
+const KEY_HEIGHT: f64 = 40.0;
+const KEY_GAP: f64 = 4.0;
+const MARGIN: f64 = 24.0;
+const TITLE_SIZE: f64 = 16.0;
+const HEADING_SIZE: f64 = 12.0;
+const LIST_SIZE: f64 = 9.0;
+const LIST_LINE: f64 = 13.0;
+const LABEL_SIZE: f64 = 7.0;
+const SHIFTED_SIZE: f64 = 6.0;
+const LABEL_LINE: f64 = 8.0;
+// Average glyph width relative to the font size, used to wrap and centre text
+const GLYPH_WIDTH: f64 = 0.55;
+
+type Color = (u8, u8, u8);
+const BLACK: Color = (0, 0, 0);
+const GRAY: Color = (128, 128, 128);
+const ROSE: Color = (225, 29, 72);
+const GREEN: Color = (5, 150, 105);
+const ORANGE: Color = (217, 119, 6);
+
+/// Drawing primitive of a cheat sheet, in px from the top left corner.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Shape {
+    Rect { x: f64, y: f64, w: f64, h: f64, color: Color },
+    /// Closed outline, for keys such as ISO Enter
+    Polygon { points: Vec<(f64, f64)>, color: Color },
+    /// `y` is the baseline; centred text is centred on `x`
+    Text { x: f64, y: f64, size: f64, text: String, color: Color, centered: bool },
+}
+
+/// One printable page, rendered by [`sheet_to_svg`] or [`sheet_to_pdf`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sheet {
+    pub width: f64,
+    pub height: f64,
+    pub shapes: Vec<Shape>,
+}
+
+impl Sheet {
+    fn text(&mut self, x: f64, y: f64, size: f64, text: impl Into<String>, color: Color, centered: bool) {
+        self.shapes.push(Shape::Text { x, y, size, text: text.into(), color, centered });
+    }
+}
+
+/// Split `text` into at most 3 lines of about `width` px.
+fn wrap(text: &str, width: f64, size: f64) -> Vec<String> {
+    let max_chars = ((width / (size * GLYPH_WIDTH)) as usize).max(1);
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max_chars => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines.truncate(3);
+    lines
+}
+
+/// Printed label of a key ID, falling back to its usage name.
+fn key_label(logical_layout: &LogicalLayout, names: &Names, id: u8) -> KeyLabel {
+    match logical_layout.map_key_label.get(&id) {
+        Some(label) if !label.default.is_empty() => label.clone(),
+        _ => KeyLabel { default: names.id_name(id), ..KeyLabel::new() },
+    }
+}
+
+/// Colour of a legend: dead keys are orange.
+fn legend_color(color: Color, dead: bool) -> Color {
+    if dead { ORANGE } else { color }
+}
+
+/// Outline of `key` with its top left corner at `x`, `y`.
+fn key_outline(key: &KeyGeometry, x: f64, y: f64, color: Color) -> Shape {
+    let (w, h) = (key.w as f64, key.h as f64);
+    match key.shape {
+        KeyShape::Rect => Shape::Rect { x, y, w, h, color },
+        KeyShape::IsoEnter => {
+            let notch = x + key.notch as f64;
+            let points = vec![(x, y), (x + w, y), (x + w, y + h), (notch, y + h), (notch, y + KEY_HEIGHT), (x, y + KEY_HEIGHT)];
+            Shape::Polygon { points, color }
+        }
+    }
+}
+
+/// Legends of `label` centred in the `w` by `h` box at `x`, `y`.
+fn draw_legends(sheet: &mut Sheet, label: &KeyLabel, x: f64, y: f64, w: f64, h: f64) {
+    // AltGr legends take the right third of the key
+    let (main_x, main_w) = if label.has_altgr() { (x + w / 3.0, w * 2.0 / 3.0) } else { (x + w / 2.0, w) };
+    let mut lines: Vec<(String, f64, Color)> = Vec::new();
+    if !label.shifted.is_empty() {
+        lines.push((label.shifted.clone(), SHIFTED_SIZE, legend_color(GRAY, label.dead[1])));
+    }
+    let default_color = legend_color(BLACK, label.dead[0]);
+    lines.extend(wrap(&label.default, main_w - 4.0, LABEL_SIZE).into_iter().map(|l| (l, LABEL_SIZE, default_color)));
+    let mut line_y = y + (h - lines.len() as f64 * LABEL_LINE) / 2.0 + LABEL_SIZE;
+    for (line, size, line_color) in lines {
+        sheet.text(main_x, line_y, size, line, line_color, true);
+        line_y += LABEL_LINE;
+    }
+    if label.has_altgr() {
+        let altgr_x = x + w * 5.0 / 6.0;
+        let top = y + (h - 2.0 * LABEL_LINE) / 2.0 + LABEL_SIZE;
+        let levels = [(&label.altgr_shifted, SHIFTED_SIZE, label.dead[3]), (&label.altgr, LABEL_SIZE, label.dead[2])];
+        for (i, (text, size, dead)) in levels.into_iter().enumerate() {
+            if !text.is_empty() {
+                sheet.text(altgr_x, top + i as f64 * LABEL_LINE, size, text.clone(), legend_color(GREEN, dead), true);
+            }
+        }
+    }
+}
+
+/// Cheat sheet of `config` on `board`: both layers with the legends of
+/// `logical_layout`, followed by the macro and media keys and the Fn trigger.
+pub fn cheat_sheet(
+    config: &Config,
+    board: &Board,
+    logical_layout: &LogicalLayout,
+    general_setting: &GeneralSeitting,
+) -> Sheet {
+    let names = Names::new(general_setting);
+    let keys = board.key_geometry();
+    let board_width = keys.iter().map(|k| (k.x + k.w) as f64).fold(0.0, f64::max);
+    let board_height = keys.iter().map(|k| (k.y + k.h) as f64).fold(0.0, f64::max);
+    let mut sheet = Sheet { width: board_width + 2.0 * MARGIN, height: 0.0, shapes: Vec::new() };
+    let mut y = MARGIN + TITLE_SIZE;
+    sheet.text(MARGIN, y, TITLE_SIZE, format!("{} - {}", board.board_label, logical_layout.layout_label), BLACK, false);
+    if logical_layout.map_key_label.values().any(|label| label.has_altgr() || label.dead.contains(&true)) {
+        y += LIST_LINE;
+        sheet.text(MARGIN, y, LIST_SIZE, "AltGr symbols on the right in green, dead keys in orange", GRAY, false);
+    }
+
+    for (heading, layer) in [("Main Layer", &config.layer0), ("2nd Layer", &config.layer1)] {
+        y += HEADING_SIZE * 2.0;
+        sheet.text(MARGIN, y, HEADING_SIZE, heading, BLACK, false);
+        y += KEY_GAP * 2.0;
+        for key in &keys {
+            let Some(id) = layer.get(&key.address) else { continue };
+            let (x, top) = (MARGIN + key.x as f64, y + key.y as f64);
+            let color = if *id == Some(config.fn_id) { ROSE } else { BLACK };
+            sheet.shapes.push(key_outline(key, x, top, color));
+            let label = id.map_or_else(KeyLabel::new, |id| key_label(logical_layout, &names, id));
+            // Legends of an ISO Enter go in its upper row
+            let h = if key.shape == KeyShape::IsoEnter { KEY_HEIGHT } else { key.h as f64 };
+            draw_legends(&mut sheet, &label, x, top, key.w as f64, h);
+        }
+        y += board_height + KEY_GAP;
+    }
+
+    let macros: Vec<String> = config
+        .macro_key_map
+        .iter()
+        .filter(|(_, m)| m.key_id != 0)
+        .map(|(trigger, m)| format!("{}: {}", names.id_name(*trigger), names.macro_name(m)))
+        .collect();
+    let media: Vec<String> = config
+        .media_key_map
+        .iter()
+        .filter(|(_, usage)| **usage != 0)
+        .map(|(trigger, usage)| format!("{}: {}", names.id_name(*trigger), names.media_name(*usage)))
+        .collect();
+    let fn_label = key_label(logical_layout, &names, config.fn_id).default;
+    let columns = [
+        ("Macro keys", macros),
+        ("Media keys", media),
+        ("Fn trigger", vec![format!("{} (outlined in red)", fn_label)]),
+    ];
+
+    y += HEADING_SIZE * 2.0;
+    let column_width = board_width / columns.len() as f64;
+    let mut bottom = y;
+    for (i, (heading, lines)) in columns.iter().enumerate() {
+        let x = MARGIN + i as f64 * column_width;
+        sheet.text(x, y, HEADING_SIZE, *heading, BLACK, false);
+        let mut line_y = y;
+        if lines.is_empty() {
+            line_y += LIST_LINE + 2.0;
+            sheet.text(x, line_y, LIST_SIZE, "None", GRAY, false);
+        }
+        for line in lines {
+            line_y += LIST_LINE;
+            sheet.text(x, line_y + 2.0, LIST_SIZE, line.clone(), BLACK, false);
+        }
+        bottom = f64::max(bottom, line_y);
+    }
+    sheet.height = bottom + MARGIN;
+    sheet
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn sheet_to_svg(sheet: &Sheet) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"Helvetica, Arial, sans-serif\">\n\
+         <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n",
+        w = sheet.width,
+        h = sheet.height
+    );
+    for shape in &sheet.shapes {
+        match shape {
+            Shape::Rect { x, y, w, h, color: (r, g, b) } => {
+                let _ = writeln!(
+                    svg,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"3\" fill=\"none\" stroke=\"rgb({},{},{})\"/>",
+                    x, y, w, h, r, g, b
+                );
+            }
+            Shape::Polygon { points, color: (r, g, b) } => {
+                let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                let _ = writeln!(
+                    svg,
+                    "<polygon points=\"{}\" fill=\"none\" stroke=\"rgb({},{},{})\" stroke-linejoin=\"round\"/>",
+                    points.join(" "), r, g, b
+                );
+            }
+            Shape::Text { x, y, size, text, color: (r, g, b), centered } => {
+                let anchor = if *centered { " text-anchor=\"middle\"" } else { "" };
+                let _ = writeln!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-size=\"{}\" fill=\"rgb({},{},{})\"{}>{}</text>",
+                    x, y, size, r, g, b, anchor, escape_xml(text)
+                );
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// PDF string literal in WinAnsi encoding; characters outside Latin-1 and '€' become '?'.
+fn pdf_string(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '€' => out.push_str("\\200"),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// Single page PDF using the built-in Helvetica font.
+pub fn sheet_to_pdf(sheet: &Sheet) -> Vec<u8> {
+    let rgb = |(r, g, b): Color| format!("{:.3} {:.3} {:.3}", r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let mut content = String::from("0.8 w\n");
+    for shape in &sheet.shapes {
+        match shape {
+            Shape::Rect { x, y, w, h, color } => {
+                let _ = writeln!(content, "{} RG {:.2} {:.2} {:.2} {:.2} re S", rgb(*color), x, sheet.height - y - h, w, h);
+            }
+            Shape::Polygon { points, color } => {
+                let path: Vec<String> = points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (x, y))| format!("{:.2} {:.2} {}", x, sheet.height - y, if i == 0 { "m" } else { "l" }))
+                    .collect();
+                let _ = writeln!(content, "{} RG {} h S", rgb(*color), path.join(" "));
+            }
+            Shape::Text { x, y, size, text, color, centered } => {
+                let x = if *centered { x - text.chars().count() as f64 * size * GLYPH_WIDTH / 2.0 } else { *x };
+                let _ = writeln!(
+                    content,
+                    "BT {} rg /F1 {} Tf {:.2} {:.2} Td {} Tj ET",
+                    rgb(*color),
+                    size,
+                    x,
+                    sheet.height - y,
+                    pdf_string(text)
+                );
+            }
+        }
+    }
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.0} {:.0}] /Resources << /Font << /F1 4 0 R >> >> /Contents 5 0 R >>",
+            sheet.width.ceil(),
+            sheet.height.ceil()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+        format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+    ];
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        let _ = write!(pdf, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+    }
+    let xref = pdf.len();
+    let _ = write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(pdf, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        pdf,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    pdf.into_bytes()
+}
+
+/// Write a cheat sheet as PDF for `.pdf` paths and as SVG otherwise.
+pub fn write_cheat_sheet(filepath: &Path, sheet: &Sheet) -> io::Result<()> {
+    if filepath.extension().is_some_and(|ext| ext == "pdf") {
+        fs::write(filepath, sheet_to_pdf(sheet))
+    } else {
+        fs::write(filepath, sheet_to_svg(sheet))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_every_board() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let mut config: Config = serde_json::from_reader(fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json")).unwrap()).unwrap();
+        let logical_layout = &general_setting.avail_logical_layouts[0];
+        assert!(general_setting.avail_boards.iter().any(|b| b.keys.iter().any(|k| k.shape == KeyShape::IsoEnter)));
+        for board in &general_setting.avail_boards {
+            config.layer0 = board.initial_id_map.clone();
+            config.layer1 = board.initial_id_map.clone();
+            let sheet = cheat_sheet(&config, board, logical_layout, &general_setting);
+            let keys = sheet.shapes.iter().filter(|s| matches!(s, Shape::Rect { .. } | Shape::Polygon { .. })).count();
+            assert_eq!(keys, 2 * board.keys.len(), "{}", board.board_name);
+            let iso_enters = board.keys.iter().filter(|k| k.shape == KeyShape::IsoEnter).count();
+            assert_eq!(sheet.shapes.iter().filter(|s| matches!(s, Shape::Polygon { .. })).count(), 2 * iso_enters);
+
+            let svg = sheet_to_svg(&sheet);
+            assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+            let pdf = String::from_utf8(sheet_to_pdf(&sheet)).unwrap();
+            let xref: usize = pdf.lines().rev().nth(1).unwrap().parse().unwrap();
+            assert!(pdf[xref..].starts_with("xref"));
+        }
+    }
+}
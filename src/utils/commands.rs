@@ -1,4 +1,5 @@
 use std::process::Command;
+use crate::models::resources;
 
 // const PYTHON_PATH_UNX: &str = "python/python-linux-embed-amd64/python.exe";
 // const PYTHON_PATH_WIN: &str = "python/python-win-embed-amd64/python.exe";
@@ -29,7 +30,8 @@ pub fn run_dissn8(fw_bin_path: &str, out_asm_path: &str) -> std::io::Result<()>
     // let python_path = get_python_path()?;
     let sn8tool_path = get_sn8tool_path()?;
     let status = Command::new(sn8tool_path)
-        .args(["dissn8", fw_bin_path, "-o", out_asm_path, "-c", CFG_PATH])
+        .args(["dissn8", fw_bin_path, "-o", out_asm_path, "-c"])
+        .arg(resources().find(CFG_PATH))
         .status()?;
 
     if !status.success() {
@@ -55,20 +57,6 @@ pub fn run_assn8(fw_asm_path: &str, out_bin_path: &str) -> std::io::Result<()> {
     Ok(())
 }
 
-pub fn run_flashsn8_gui(fw_bin_path: &str, fw_org_path: &str) -> std::io::Result<()> {
-    // let python_path = get_python_path()?;
-    let sn8tool_path = get_sn8tool_path()?;
-    let status = Command::new(sn8tool_path)
-        .args(["flashsn8-gui", fw_bin_path, "-o", fw_org_path])
-        .status()?;
-    if !status.success() {
-        eprintln!("flashsn8-gui failed with {:?}", status);
-    } else {
-        println!("Finished flashsn8-gui with {}", fw_bin_path);
-    }
-    Ok(())
-}
-
 /*
 fn get_flasher_path() -> std::io::Result<&'static str> {
     if cfg!(target_os = "macos") || cfg!(target_os = "linux") {
@@ -98,11 +86,11 @@ pub fn run_flashsn8_gui(fw_bin_path: &str) -> std::io::Result<()> {
 }
 */
 
-fn get_sn8tool_path() -> std::io::Result<&'static str> {
+fn get_sn8tool_path() -> std::io::Result<std::path::PathBuf> {
     if cfg!(target_os = "macos") || cfg!(target_os = "linux") {
-        Ok(SN8TOOL_PATH_UNX)
+        Ok(resources().find(SN8TOOL_PATH_UNX))
     } else if cfg!(target_os = "windows") {                    
-        Ok(SN8TOOL_PATH_WIN)
+        Ok(resources().find(SN8TOOL_PATH_WIN))
     } else {
         Err(std::io::Error::new(
             std::io::ErrorKind::Other,
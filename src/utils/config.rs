@@ -1,31 +1,196 @@
 // mod crate::models;
 // use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
 use std::collections::BTreeMap;
-use std::io::{self, BufRead, BufReader, BufWriter};
+use std::io::{self, BufWriter};
 use std::path::Path;
 // use serde::{Serialize, Deserialize};
-use serde_json::{to_writer_pretty, from_reader};
-use crate::models::{Config, MacroKey};
+use serde_json::{Map, Value, json, to_writer_pretty};
+use crate::models::{
+    Config, GeneralSeitting, MacroKey, ValidationReport, validate_config,
+    default_fn_id, default_tp_sensitivity, default_macro_key_map, default_media_key_map, default_enable_middle_click,
+};
+use crate::utils::keymap::{config_to_keymap, keymap_to_config};
+use crate::utils::overlay::{OverlayBase, ResolvedConfig, overlay_value, resolve_config_file};
 
 
 
-pub fn load_config(filepath: &Path)
-    -> io::Result<(String, String, BTreeMap<u8, Option<u8>>, BTreeMap<u8, Option<u8>>, u8, u32, BTreeMap<u8, MacroKey>, BTreeMap<u8, u16>, bool)> 
-{
-    let file = File::open(filepath)?;
-    let config: Config = from_reader(file)?;
-    Ok((
-        config.physical_layout_name,
-        config.logical_layout_name,
-        config.layer0,
-        config.layer1,
-        config.fn_id,
-        config.tp_sensitivity,
-        config.macro_key_map,
-        config.media_key_map,
-        config.enable_middle_click,
-    ))
+/// Config version written by this app. Raising it needs a step in [`MIGRATIONS`].
+pub const CONFIG_VERSION: u32 = 4;
+
+pub const KEYMAP_EXTENSION: &str = "toml";
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[i]` upgrades a raw config from version `i + 1` to `i + 2`.
+///
+/// - v1: board and language names, `layer0` and `layer1`
+/// - v2: `fn_id` and `tp_sensitivity`
+/// - v3: `macro_key_map` and `media_key_map`
+/// - v4: `enable_middle_click`
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize - 1] = [migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4];
+
+/// Error type for reading and writing configs
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    NotAnObject,
+    InvalidVersion(Value),
+    Keymap(String),
+    Overlay(String),
+    NewerVersion { found: u32, supported: u32 },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Json(e) => write!(f, "Invalid config JSON: {}", e),
+            ConfigError::NotAnObject => write!(f, "Invalid config: top level must be a JSON object"),
+            ConfigError::InvalidVersion(v) => write!(f, "Invalid config_version: {}", v),
+            ConfigError::Keymap(msg) => write!(f, "{}", msg),
+            ConfigError::Overlay(msg) => write!(f, "{}", msg),
+            ConfigError::NewerVersion { found, supported } => write!(
+                f,
+                "This config has config_version {}, but this app only supports up to {}. Please update the app.",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> ConfigError {
+        ConfigError::Json(e)
+    }
+}
+
+/// Config read from a file, upgraded to [`CONFIG_VERSION`] and validated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoadedConfig {
+    pub config: Config,
+    /// Version found in the file; files without the field are treated as v1
+    pub original_version: u32,
+    pub report: ValidationReport,
+    /// Set when the file extends another config
+    pub overlay: Option<OverlayBase>,
+}
+
+impl LoadedConfig {
+    pub fn was_upgraded(&self) -> bool {
+        self.original_version < CONFIG_VERSION
+    }
+}
+
+fn migrate_v1_to_v2(config: &mut Map<String, Value>) {
+    config.entry("fn_id").or_insert(json!(default_fn_id()));
+    config.entry("tp_sensitivity").or_insert(json!(default_tp_sensitivity()));
+    config.insert("config_version".into(), json!(2));
+}
+
+fn migrate_v2_to_v3(config: &mut Map<String, Value>) {
+    config.entry("macro_key_map").or_insert(json!(default_macro_key_map()));
+    config.entry("media_key_map").or_insert(json!(default_media_key_map()));
+    config.insert("config_version".into(), json!(3));
+}
+
+fn migrate_v3_to_v4(config: &mut Map<String, Value>) {
+    config.entry("enable_middle_click").or_insert(json!(default_enable_middle_click()));
+    config.insert("config_version".into(), json!(4));
+}
+
+/// Version of a raw config, rejecting versions newer than [`CONFIG_VERSION`].
+pub fn check_config_version(config: &Map<String, Value>) -> Result<u32, ConfigError> {
+    let version = match config.get("config_version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .filter(|v| *v >= 1)
+            .map(|v| v as u32)
+            .ok_or_else(|| ConfigError::InvalidVersion(v.clone()))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(ConfigError::NewerVersion { found: version, supported: CONFIG_VERSION });
+    }
+    Ok(version)
+}
+
+/// Upgrade a raw config to [`CONFIG_VERSION`], one step per version, and
+/// return the version it had.
+pub fn migrate_config(value: &mut Value) -> Result<u32, ConfigError> {
+    let config = value.as_object_mut().ok_or(ConfigError::NotAnObject)?;
+    let original_version = check_config_version(config)?;
+    for migration in &MIGRATIONS[original_version as usize - 1..] {
+        migration(config);
+    }
+    Ok(original_version)
+}
+
+/// Files with this extension use the readable keymap format, see [`config_to_keymap`].
+fn is_keymap_file(filepath: &Path) -> bool {
+    filepath.extension().is_some_and(|ext| ext == KEYMAP_EXTENSION)
+}
+
+/// Read, upgrade and validate a config. Check `report` before applying it.
+pub fn load_config(filepath: &Path, general_setting: &GeneralSeitting) -> Result<LoadedConfig, ConfigError> {
+    let resolved = if is_keymap_file(filepath) {
+        let text = fs::read_to_string(filepath)?;
+        let config = keymap_to_config(&text, general_setting).map_err(ConfigError::Keymap)?;
+        let mut value = serde_json::to_value(config)?;
+        let original_version = migrate_config(&mut value)?;
+        ResolvedConfig { value, original_version, overlay: None }
+    } else {
+        resolve_config_file(filepath)?
+    };
+    let config: Config = serde_json::from_value(resolved.value)?;
+    let report = validate_config(&config, general_setting);
+    Ok(LoadedConfig {
+        config,
+        original_version: resolved.original_version,
+        report,
+        overlay: resolved.overlay,
+    })
+}
+
+/// Write `config` as JSON, or in the readable keymap format for `.toml` paths.
+pub fn write_config(filepath: &Path, config: &Config, general_setting: &GeneralSeitting) -> Result<(), ConfigError> {
+    if is_keymap_file(filepath) {
+        let text = config_to_keymap(config, general_setting).map_err(ConfigError::Keymap)?;
+        fs::write(filepath, text)?;
+        return Ok(());
+    }
+    let file = File::create(filepath)?;
+    let writer = BufWriter::new(file);
+    to_writer_pretty(writer, config)?;
+    Ok(())
+}
+
+/// Like [`write_config`], but JSON files with an `overlay` keep extending its base
+/// and only contain the overrides. Keymap files are always written complete.
+pub fn write_config_with_overlay(
+    filepath: &Path,
+    config: &Config,
+    overlay: Option<&OverlayBase>,
+    general_setting: &GeneralSeitting,
+) -> Result<(), ConfigError> {
+    match overlay {
+        Some(overlay) if !is_keymap_file(filepath) => {
+            let value = overlay_value(filepath, config, overlay)?;
+            let writer = BufWriter::new(File::create(filepath)?);
+            to_writer_pretty(writer, &value)?;
+            Ok(())
+        }
+        _ => write_config(filepath, config, general_setting),
+    }
 }
 
 pub fn save_config(
@@ -39,9 +204,11 @@ pub fn save_config(
     macro_key_map: &BTreeMap<u8, MacroKey>,
     media_key_map: &BTreeMap<u8, u16>,
     enable_middle_click: bool,
-) -> io::Result<()> {
+    overlay: Option<&OverlayBase>,
+    general_setting: &GeneralSeitting,
+) -> Result<(), ConfigError> {
     let config = Config {
-        config_version: 4,
+        config_version: CONFIG_VERSION,
         physical_layout_name: physical_layout_name.to_string(),
         logical_layout_name: logical_layout_name.to_string(),
         layer0: layer0.clone(),
@@ -52,17 +219,113 @@ pub fn save_config(
         media_key_map: media_key_map.clone(),
         enable_middle_click,
     };
-    let file = File::create(filepath)?;
-    let writer = BufWriter::new(file);
-    to_writer_pretty(writer, &config)?;
-    Ok(())
+    write_config_with_overlay(filepath, &config, overlay, general_setting)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_and_upgrades_older_versions() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let mut macro_key_map = default_macro_key_map();
+        let trigger = *macro_key_map.keys().next().unwrap();
+        macro_key_map.insert(trigger, MacroKey { key_id: 0x06, left_ctrl: true, ..MacroKey::new() });
+        let mut media_key_map = default_media_key_map();
+        let media_trigger = *media_key_map.keys().next().unwrap();
+        media_key_map.insert(media_trigger, 0xE9);
+
+        let v1 = json!({
+            "physical_layout_name": "0B47190",
+            "logical_layout_name": "US_English",
+            "layer0": { "16": 231, "17": 4 },
+            "layer1": { "16": 231 },
+        });
+        let mut v2 = v1.clone();
+        v2["config_version"] = json!(2);
+        v2["fn_id"] = json!(0xe3);
+        v2["tp_sensitivity"] = json!(3);
+        let mut v3 = v2.clone();
+        v3["config_version"] = json!(3);
+        v3["macro_key_map"] = json!(macro_key_map);
+        v3["media_key_map"] = json!(media_key_map);
+
+        let root = tempfile::tempdir().unwrap();
+        for (version, fixture) in [(1, &v1), (2, &v2), (3, &v3)] {
+            let path = root.path().join(format!("v{}.json", version));
+            fs::write(&path, fixture.to_string()).unwrap();
+            let loaded = load_config(&path, &general_setting).unwrap();
+            assert_eq!(loaded.original_version, version);
+            assert!(loaded.was_upgraded());
+            assert!(loaded.report.errors.is_empty(), "v{}: {:?}", version, loaded.report);
 
-pub fn load_url(filepath: &Path) -> io::Result<String> {
-    let file = File::open(filepath)?;
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
-    Ok(line.trim().to_string())
-}
\ No newline at end of file
+            let config = &loaded.config;
+            assert_eq!(config.config_version, CONFIG_VERSION);
+            assert_eq!(config.layer0[&0x11], Some(0x04));
+            // Fields of the file's version are kept, later ones get their defaults
+            let (fn_id, tp_sensitivity) = if version >= 2 { (0xe3, 3) } else { (default_fn_id(), default_tp_sensitivity()) };
+            assert_eq!((config.fn_id, config.tp_sensitivity), (fn_id, tp_sensitivity));
+            if version >= 3 {
+                assert_eq!(config.macro_key_map, macro_key_map);
+                assert_eq!(config.media_key_map, media_key_map);
+            } else {
+                assert_eq!(config.macro_key_map, default_macro_key_map());
+                assert_eq!(config.media_key_map, default_media_key_map());
+            }
+            assert_eq!(config.enable_middle_click, default_enable_middle_click());
+
+            // Saved back, the file is current and reads the same
+            write_config(&path, config, &general_setting).unwrap();
+            let saved = load_config(&path, &general_setting).unwrap();
+            assert!(!saved.was_upgraded());
+            assert_eq!(&saved.config, config);
+        }
+    }
+
+    #[test]
+    fn migrates_one_version_per_step() {
+        let v1 = json!({
+            "physical_layout_name": "0B47190",
+            "logical_layout_name": "US_English",
+            "layer0": { "16": 231 },
+            "layer1": { "16": 231 },
+        });
+        let steps = [
+            ("fn_id", json!(default_fn_id())),
+            ("tp_sensitivity", json!(default_tp_sensitivity())),
+            ("macro_key_map", json!(default_macro_key_map())),
+            ("media_key_map", json!(default_media_key_map())),
+            ("enable_middle_click", json!(default_enable_middle_click())),
+        ];
+        // Fields each step adds, by the version it upgrades from
+        let added = [&steps[0..2], &steps[2..4], &steps[4..5]];
+
+        let mut value = v1.clone();
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            let before = value.as_object().unwrap().clone();
+            migration(value.as_object_mut().unwrap());
+            let after = value.as_object().unwrap();
+            assert_eq!(after["config_version"], json!(i + 2));
+            for (field, default) in added[i] {
+                assert!(!before.contains_key(*field), "{}", field);
+                assert_eq!(&after[*field], default, "{}", field);
+            }
+            assert_eq!(after.len(), before.len() + added[i].len() + usize::from(i == 0));
+        }
+
+        // The whole chain runs from the file's version and keeps its fields
+        let mut migrated = v1.clone();
+        assert_eq!(migrate_config(&mut migrated).unwrap(), 1);
+        assert_eq!(migrated, value);
+        let mut v3 = json!({ "config_version": 3, "media_key_map": {} });
+        assert_eq!(migrate_config(&mut v3).unwrap(), 3);
+        assert_eq!(v3, json!({ "config_version": 4, "media_key_map": {}, "enable_middle_click": false }));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut value = json!({ "config_version": CONFIG_VERSION + 1 });
+        assert!(matches!(migrate_config(&mut value), Err(ConfigError::NewerVersion { .. })));
+    }
+}
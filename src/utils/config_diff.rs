@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::models::{Config, GeneralSeitting, ValidationReport, validate_config};
+use crate::utils::config::CONFIG_VERSION;
+use crate::utils::keymap::Names;
+
+/// Sections of a [`ConfigDiff`], in display order.
+pub const DIFF_SECTIONS: [&str; 5] = ["Settings", "Layer 0", "Layer 1", "Macro keys", "Media keys"];
+
+/// One difference between two configs. Keys are named by the legend at that
+/// position, as in the readable keymap format, values by usage name.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub section: &'static str,
+    pub item: String,
+    /// `None` when the entry is missing from the old config
+    pub old: Option<String>,
+    /// `None` when the entry is missing from the new config
+    pub new: Option<String>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "  {}: {} -> {}", self.item, old, new),
+            (None, Some(new)) => write!(f, "+ {}: {}", self.item, new),
+            (Some(old), None) => write!(f, "- {}: {}", self.item, old),
+            (None, None) => write!(f, "  {}", self.item),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ConfigDiff {
+    pub changes: Vec<Change>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn section(&self, section: &str) -> Vec<&Change> {
+        self.changes.iter().filter(|c| c.section == section).collect()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+        for section in DIFF_SECTIONS {
+            let changes = self.section(section);
+            if changes.is_empty() {
+                continue;
+            }
+            writeln!(f, "[{}]", section)?;
+            for change in changes {
+                writeln!(f, "{}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Entry changed differently by both sides of a merge. The merged config keeps ours.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Conflict {
+    pub section: &'static str,
+    pub item: String,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "(missing)".to_string());
+        write!(
+            f,
+            "{} / {}: base {}, ours {}, theirs {}",
+            self.section,
+            self.item,
+            show(&self.base),
+            show(&self.ours),
+            show(&self.theirs)
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MergeResult {
+    pub config: Config,
+    pub conflicts: Vec<Conflict>,
+    /// Problems of the merged config, which two valid edits can still produce
+    pub report: ValidationReport,
+}
+
+fn layer_value(names: &Names, id: &Option<u8>) -> String {
+    match id {
+        Some(id) => names.id_name(*id),
+        None => "(none)".to_string(),
+    }
+}
+
+fn map_changes<K: Ord + Copy, V: PartialEq>(
+    section: &'static str,
+    old: &BTreeMap<K, V>,
+    new: &BTreeMap<K, V>,
+    item: impl Fn(K) -> String,
+    value: impl Fn(&V) -> String,
+) -> Vec<Change> {
+    let keys: BTreeSet<K> = old.keys().chain(new.keys()).copied().collect();
+    keys.into_iter()
+        .filter(|k| old.get(k) != new.get(k))
+        .map(|k| Change {
+            section,
+            item: item(k),
+            old: old.get(&k).map(&value),
+            new: new.get(&k).map(&value),
+        })
+        .collect()
+}
+
+fn setting_change<T: PartialEq>(name: &str, old: &T, new: &T, value: impl Fn(&T) -> String) -> Option<Change> {
+    (old != new).then(|| Change {
+        section: DIFF_SECTIONS[0],
+        item: name.to_string(),
+        old: Some(value(old)),
+        new: Some(value(new)),
+    })
+}
+
+/// Changes from `old` to `new`, grouped by [`DIFF_SECTIONS`].
+pub fn diff_configs(old: &Config, new: &Config, general_setting: &GeneralSeitting) -> ConfigDiff {
+    let names = Names::new(general_setting).with_language(&new.logical_layout_name);
+    let mut changes: Vec<Change> = [
+        setting_change("board", &old.physical_layout_name, &new.physical_layout_name, String::clone),
+        setting_change("language", &old.logical_layout_name, &new.logical_layout_name, String::clone),
+        setting_change("fn_id", &old.fn_id, &new.fn_id, |id| names.id_name(*id)),
+        setting_change("tp_sensitivity", &old.tp_sensitivity, &new.tp_sensitivity, u32::to_string),
+        setting_change("enable_middle_click", &old.enable_middle_click, &new.enable_middle_click, bool::to_string),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    changes.extend(map_changes(DIFF_SECTIONS[1], &old.layer0, &new.layer0, |a| names.address_name(a), |v| layer_value(&names, v)));
+    changes.extend(map_changes(DIFF_SECTIONS[2], &old.layer1, &new.layer1, |a| names.address_name(a), |v| layer_value(&names, v)));
+    changes.extend(map_changes(DIFF_SECTIONS[3], &old.macro_key_map, &new.macro_key_map, |t| names.id_name(t), |m| names.macro_name(m)));
+    changes.extend(map_changes(DIFF_SECTIONS[4], &old.media_key_map, &new.media_key_map, |t| names.id_name(t), |u| names.media_name(*u)));
+    ConfigDiff { changes }
+}
+
+/// Three-way merge of one value; `None` when both sides changed it differently.
+fn merge3<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T) -> Option<T> {
+    if ours == theirs || theirs == base {
+        Some(ours.clone())
+    } else if ours == base {
+        Some(theirs.clone())
+    } else {
+        None
+    }
+}
+
+fn merge_setting<T: PartialEq + Clone>(
+    name: &str,
+    [base, ours, theirs]: [&T; 3],
+    value: impl Fn(&T) -> String,
+    conflicts: &mut Vec<Conflict>,
+) -> T {
+    merge3(base, ours, theirs).unwrap_or_else(|| {
+        conflicts.push(Conflict {
+            section: DIFF_SECTIONS[0],
+            item: name.to_string(),
+            base: Some(value(base)),
+            ours: Some(value(ours)),
+            theirs: Some(value(theirs)),
+        });
+        ours.clone()
+    })
+}
+
+fn merge_map<K: Ord + Copy, V: PartialEq + Clone>(
+    section: &'static str,
+    [base, ours, theirs]: [&BTreeMap<K, V>; 3],
+    item: impl Fn(K) -> String,
+    value: impl Fn(&V) -> String,
+    conflicts: &mut Vec<Conflict>,
+) -> BTreeMap<K, V> {
+    let keys: BTreeSet<K> = base.keys().chain(ours.keys()).chain(theirs.keys()).copied().collect();
+    let mut merged = BTreeMap::new();
+    for k in keys {
+        let (b, o, t) = (base.get(&k), ours.get(&k), theirs.get(&k));
+        let chosen = merge3(&b, &o, &t).unwrap_or_else(|| {
+            conflicts.push(Conflict {
+                section,
+                item: item(k),
+                base: b.map(&value),
+                ours: o.map(&value),
+                theirs: t.map(&value),
+            });
+            o
+        });
+        if let Some(v) = chosen {
+            merged.insert(k, v.clone());
+        }
+    }
+    merged
+}
+
+/// Combine the edits of `ours` and `theirs` to their common `base`.
+/// Entries changed differently on both sides are reported and keep our value.
+pub fn merge_configs(base: &Config, ours: &Config, theirs: &Config, general_setting: &GeneralSeitting) -> MergeResult {
+    let names = Names::new(general_setting).with_language(&ours.logical_layout_name);
+    let mut conflicts = Vec::new();
+    let c = &mut conflicts;
+    let config = Config {
+        config_version: CONFIG_VERSION,
+        physical_layout_name: merge_setting(
+            "board",
+            [&base.physical_layout_name, &ours.physical_layout_name, &theirs.physical_layout_name],
+            String::clone,
+            c,
+        ),
+        logical_layout_name: merge_setting(
+            "language",
+            [&base.logical_layout_name, &ours.logical_layout_name, &theirs.logical_layout_name],
+            String::clone,
+            c,
+        ),
+        fn_id: merge_setting("fn_id", [&base.fn_id, &ours.fn_id, &theirs.fn_id], |id| names.id_name(*id), c),
+        tp_sensitivity: merge_setting(
+            "tp_sensitivity",
+            [&base.tp_sensitivity, &ours.tp_sensitivity, &theirs.tp_sensitivity],
+            u32::to_string,
+            c,
+        ),
+        enable_middle_click: merge_setting(
+            "enable_middle_click",
+            [&base.enable_middle_click, &ours.enable_middle_click, &theirs.enable_middle_click],
+            bool::to_string,
+            c,
+        ),
+        layer0: merge_map(
+            DIFF_SECTIONS[1],
+            [&base.layer0, &ours.layer0, &theirs.layer0],
+            |a| names.address_name(a),
+            |v| layer_value(&names, v),
+            c,
+        ),
+        layer1: merge_map(
+            DIFF_SECTIONS[2],
+            [&base.layer1, &ours.layer1, &theirs.layer1],
+            |a| names.address_name(a),
+            |v| layer_value(&names, v),
+            c,
+        ),
+        macro_key_map: merge_map(
+            DIFF_SECTIONS[3],
+            [&base.macro_key_map, &ours.macro_key_map, &theirs.macro_key_map],
+            |t| names.id_name(t),
+            |m| names.macro_name(m),
+            c,
+        ),
+        media_key_map: merge_map(
+            DIFF_SECTIONS[4],
+            [&base.media_key_map, &ours.media_key_map, &theirs.media_key_map],
+            |t| names.id_name(t),
+            |u| names.media_name(*u),
+            c,
+        ),
+    };
+    let report = validate_config(&config, general_setting);
+    MergeResult { config, conflicts, report }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_and_merges_by_name() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let base: Config = serde_json::from_reader(std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json")).unwrap()).unwrap();
+        let caps_lock = 0x39;
+        let caps_address = *general_setting
+            .initial_id_map
+            .iter()
+            .find(|(_, id)| **id == Some(caps_lock))
+            .unwrap()
+            .0;
+
+        let mut ours = base.clone();
+        ours.tp_sensitivity = if base.tp_sensitivity == 5 { 4 } else { 5 };
+        ours.layer0.insert(caps_address, Some(0xE0));
+        let mut theirs = base.clone();
+        theirs.enable_middle_click = !base.enable_middle_click;
+        theirs.layer0.insert(caps_address, Some(0xE3));
+
+        let diff = diff_configs(&base, &ours, &general_setting);
+        assert_eq!(diff.section("Settings").len(), 1);
+        let change = diff.section("Layer 0")[0];
+        assert_eq!(change.item, "CapsLock");
+        assert_eq!(change.new, Some(names_of(&general_setting, 0xE0)));
+
+        let merged = merge_configs(&base, &ours, &theirs, &general_setting);
+        assert_eq!(merged.config.tp_sensitivity, ours.tp_sensitivity);
+        assert_eq!(merged.config.enable_middle_click, theirs.enable_middle_click);
+        assert_eq!(merged.config.layer0, ours.layer0);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].section, "Layer 0");
+        assert!(!merged.report.has_errors(), "{}", merged.report);
+
+        // An invalid edit is merged as it is and reported
+        let mut theirs = base.clone();
+        theirs.tp_sensitivity = 9;
+        let merged = merge_configs(&base, &base, &theirs, &general_setting);
+        assert!(merged.conflicts.is_empty());
+        assert!(merged.report.has_errors());
+    }
+
+    fn names_of(general_setting: &GeneralSeitting, id: u8) -> String {
+        Names::new(general_setting).id_name(id)
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::models::{Config, GeneralSeitting, MacroKey, default_fn_id};
+use crate::utils::config::CONFIG_VERSION;
+use crate::utils::fingerprint::{Fingerprint, read_fingerprint};
+use crate::utils::board_match::{BoardMatch, infer_board};
+use crate::utils::flasher::{FLASHER_BASE_ADDRESS_WORDS, IMAGE_LENGTH, SN8_HEADER_LENGTH, UNPROGRAMABLE_PREFIX_WORDS};
+use crate::utils::installer::{SN8_OFFSET, SN8_SIZE, extract_fw_from_installer_to_vec};
+use crate::utils::keymap::macro_key_from;
+use crate::utils::template::render_template;
+
+/// Config recovered from a firmware image, with the key mask it was built for.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DecompiledFirmware {
+    pub config: Config,
+    pub keymask: [u8; 16],
+    /// Board inferred from the key mask and, for stock firmware, the key table
+    pub board_match: Option<BoardMatch>,
+    /// The image is the unmodified stock firmware
+    pub stock: bool,
+    /// Build information embedded by this app, if present
+    pub fingerprint: Option<Fingerprint>,
+    pub notes: Vec<String>,
+}
+
+/// Template placeholder, e.g. `${s/fn_id/af}` or `${e/mclick/B0BSET/B0BCLR}`.
+#[derive(Clone, PartialEq, Debug)]
+enum Placeholder {
+    Value { name: String, default: String },
+    Choice { name: String, count: usize },
+}
+
+/// Values probed with one assembly; each needs its own low byte.
+const PROBES_PER_PASS: usize = 0x100;
+
+/// Word address of a placeholder in the assembled firmware.
+#[derive(Clone, PartialEq, Debug)]
+enum Slot {
+    /// The low `digits` hex digits of the word hold the value
+    Value { name: String, address: usize, digits: usize, default: String },
+    /// Assembled word of each choice
+    Choice { name: String, address: usize, words: Vec<u16> },
+}
+
+/// Plain 0x6000 byte image from a firmware binary, an SN8 file with header or an installer.
+pub fn firmware_image(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    match bytes.len() {
+        IMAGE_LENGTH => Ok(bytes.to_vec()),
+        len if len == IMAGE_LENGTH + SN8_HEADER_LENGTH => Ok(bytes[SN8_HEADER_LENGTH..].to_vec()),
+        len if len >= SN8_OFFSET + SN8_SIZE => extract_fw_from_installer_to_vec(bytes),
+        len => Err(format!(
+            "{} bytes is neither a firmware image ({:#x} bytes) nor an installer",
+            len, IMAGE_LENGTH
+        )),
+    }
+}
+
+fn placeholders(template: &str) -> Vec<Placeholder> {
+    let mut found = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else { break };
+        let parts: Vec<&str> = rest[start + 2..start + end].trim().split('/').collect();
+        match parts[..] {
+            ["s", name, ref default @ ..] if !default.is_empty() => {
+                found.push(Placeholder::Value { name: name.to_string(), default: default.join("/") })
+            }
+            ["e", name, ref choices @ ..] if !choices.is_empty() => {
+                found.push(Placeholder::Choice { name: name.to_string(), count: choices.len() })
+            }
+            _ => {}
+        }
+        rest = &rest[start + end + 1..];
+    }
+    found
+}
+
+/// Bits of a word holding a value of `digits` hex digits.
+fn value_mask(digits: usize) -> u16 {
+    if digits >= 4 { 0xffff } else { (1u16 << (digits * 4)) - 1 }
+}
+
+fn word(image: &[u8], address: usize) -> u16 {
+    u16::from_le_bytes([image[address * 2], image[address * 2 + 1]])
+}
+
+fn render(template: &str, s_values: &HashMap<String, String>, e_choices: &HashMap<String, usize>) -> Result<String, String> {
+    render_template(template, s_values, e_choices).map_err(|e| format!("Failed to render template: {}", e))
+}
+
+/// Locate every placeholder of the patched firmware template by assembling it with
+/// probe values and comparing the results with the default build.
+fn locate_slots(
+    template: &str,
+    assemble: &mut dyn FnMut(&str) -> Result<Vec<u8>, String>,
+) -> Result<Vec<Slot>, String> {
+    let placeholders = placeholders(template);
+    let base = assemble(&render(template, &HashMap::new(), &HashMap::new())?)?;
+
+    // Values are probed in passes. Within a pass every probe has its own low
+    // byte, so it is unique under the mask of every slot, be it a DW word or
+    // an 8-bit immediate. Values of a single digit are probed one by one.
+    let mut passes: Vec<Vec<(&String, &String)>> = Vec::new();
+    for placeholder in &placeholders {
+        let Placeholder::Value { name, default } = placeholder else { continue };
+        match passes.last_mut() {
+            Some(pass) if default.len() >= 2 && pass.len() < PROBES_PER_PASS && pass[0].1.len() >= 2 => {
+                pass.push((name, default))
+            }
+            _ => passes.push(vec![(name, default)]),
+        }
+    }
+    let mut addresses = HashMap::new();
+    for pass in passes {
+        let mut probes = HashMap::new();
+        let mut used = Vec::new();
+        for (name, default) in &pass {
+            let default_value = u16::from_str_radix(default, 16).map_err(|_| format!("Invalid default in '{}'", name))?;
+            let mask = value_mask(default.len());
+            let low = (0..=mask & 0xff)
+                .find(|b| !used.contains(b) && *b != default_value & mask & 0xff)
+                .ok_or_else(|| format!("No probe value left for '{}'", name))?;
+            used.push(low);
+            probes.insert((*name).clone(), if mask == 0xffff { 0x8000 | low } else { low });
+        }
+        let rendered: HashMap<String, String> = pass
+            .iter()
+            .map(|(name, default)| ((*name).clone(), format!("{:0width$x}", probes[*name], width = default.len())))
+            .collect();
+        let probed = assemble(&render(template, &rendered, &HashMap::new())?)?;
+        let changed: Vec<usize> = (0..base.len() / 2).filter(|a| word(&base, *a) != word(&probed, *a)).collect();
+        for (name, default) in pass {
+            let mask = value_mask(default.len());
+            let found: Vec<usize> = changed.iter().copied().filter(|a| word(&probed, *a) & mask == probes[name]).collect();
+            let [address] = found[..] else {
+                return Err(format!("Could not locate '{}' in the firmware template", name));
+            };
+            addresses.insert(name.clone(), address);
+        }
+    }
+
+    let mut slots = Vec::new();
+    for placeholder in placeholders {
+        match placeholder {
+            Placeholder::Value { name, default } => {
+                let address = addresses[&name];
+                slots.push(Slot::Value { name, address, digits: default.len(), default });
+            }
+            Placeholder::Choice { name, count } => {
+                let mut address = None;
+                let mut words = Vec::new();
+                for choice in 1..count {
+                    let choices = HashMap::from([(name.clone(), choice)]);
+                    let rom = assemble(&render(template, &HashMap::new(), &choices)?)?;
+                    let found: Vec<usize> = (0..base.len() / 2).filter(|a| word(&base, *a) != word(&rom, *a)).collect();
+                    match (&found[..], address) {
+                        (&[a], None) => address = Some(a),
+                        (&[a], Some(previous)) if a == previous => {}
+                        _ => return Err(format!("Could not locate '{}' in the firmware template", name)),
+                    }
+                    words.push(word(&rom, found[0]));
+                }
+                let Some(address) = address else { continue };
+                words.insert(0, word(&base, address));
+                slots.push(Slot::Choice { name, address, words });
+            }
+        }
+    }
+    Ok(slots)
+}
+
+/// Recover the config of `image` from the patched firmware `template`.
+///
+/// `stock` is the original firmware the template was made from and `assemble`
+/// builds a firmware image from assembly source.
+pub(crate) fn decompile_image(
+    template: &str,
+    stock: &[u8],
+    image: &[u8],
+    general_setting: &GeneralSeitting,
+    assemble: &mut dyn FnMut(&str) -> Result<Vec<u8>, String>,
+) -> Result<DecompiledFirmware, String> {
+    let slots = locate_slots(template, assemble)?;
+    let program = UNPROGRAMABLE_PREFIX_WORDS as usize * 2..FLASHER_BASE_ADDRESS_WORDS as usize * 2;
+    let is_stock = image[program.clone()] == stock[program];
+
+    let mut values = HashMap::new();
+    let mut choices = HashMap::new();
+    for slot in &slots {
+        match slot {
+            Slot::Value { name, address, digits, default } => {
+                let value = if is_stock {
+                    default.clone()
+                } else {
+                    format!("{:0width$x}", word(image, *address) & value_mask(*digits), width = digits)
+                };
+                values.insert(name.clone(), value);
+            }
+            Slot::Choice { name, address, words } => {
+                let choice = if is_stock {
+                    0
+                } else {
+                    let found = word(image, *address);
+                    words.iter().position(|w| *w == found).ok_or_else(|| {
+                        format!(
+                            "Unexpected instruction {:#06x} for '{}' at {:#06x}; the firmware was not built by this version",
+                            found, name, address
+                        )
+                    })?
+                };
+                choices.insert(name.clone(), choice);
+            }
+        }
+    }
+    let mut decompiled = config_from_values(&values, &choices, is_stock, general_setting);
+    decompiled.fingerprint = read_fingerprint(image);
+    Ok(decompiled)
+}
+
+fn hex_value(values: &HashMap<String, String>, name: &str) -> Option<u16> {
+    values.get(name).and_then(|v| u16::from_str_radix(v, 16).ok())
+}
+
+/// Inverse of the values written by `modify_asm_file`.
+fn config_from_values(
+    values: &HashMap<String, String>,
+    choices: &HashMap<String, usize>,
+    stock: bool,
+    general_setting: &GeneralSeitting,
+) -> DecompiledFirmware {
+    let mut notes = Vec::new();
+
+    let mut keymask = [0u8; 16];
+    for (row, mask) in keymask.iter_mut().enumerate() {
+        *mask = hex_value(values, &format!("km_{:01x}", row)).unwrap_or_default() as u8;
+    }
+    let mut layer0 = BTreeMap::new();
+    let mut layer1 = BTreeMap::new();
+    for address in general_setting.initial_id_map.keys() {
+        let Some(value) = hex_value(values, &format!("{:02x}", address)) else { continue };
+        let id = |id: u16| (id != 0).then_some(id as u8);
+        layer0.insert(*address, id(value & 0xff));
+        layer1.insert(*address, id(value >> 8));
+    }
+
+    // Only the stock key table holds original key IDs
+    let board_match = infer_board(&general_setting.avail_boards, &keymask, stock.then_some(&layer0));
+    let board = match &board_match {
+        Some(found) => {
+            if !found.exact {
+                notes.push(format!("The key mask of this firmware fits several boards; '{}' is selected", found));
+            }
+            general_setting.avail_boards.iter().find(|b| b.board_name == found.board_name)
+        }
+        None => None,
+    };
+    let board = board.unwrap_or_else(|| {
+        notes.push(format!(
+            "No board has the key mask of this firmware; '{}' is selected",
+            general_setting.avail_boards[0].board_name
+        ));
+        &general_setting.avail_boards[0]
+    });
+
+    let macro_key_map: BTreeMap<u8, MacroKey> = (0xE8..=0xFF)
+        .filter_map(|trigger: u8| {
+            let value = hex_value(values, &format!("macro_{:02x}", trigger))?;
+            let bits = (value >> 8) as u8;
+            let flags = std::array::from_fn(|i| bits & (1 << i) != 0);
+            Some((trigger, macro_key_from(value as u8, flags)))
+        })
+        .collect();
+    let media_key_map: BTreeMap<u8, u16> = (0xD5..=0xDF)
+        .filter_map(|trigger: u8| Some((trigger, hex_value(values, &format!("media_{:02x}", trigger))?)))
+        .collect();
+
+    let accel: Vec<usize> = (0..4).map(|i| choices.get(&format!("tp_accel_{}", i)).copied().unwrap_or(0)).collect();
+    let tp_sensitivity = 1 + accel.iter().take_while(|c| **c == 1).count() as u32;
+    if accel.iter().skip_while(|c| **c == 1).any(|c| *c != 0) {
+        notes.push(format!("TrackPoint acceleration switches {:?} do not match a sensitivity level", accel));
+    }
+
+    let config = Config {
+        config_version: CONFIG_VERSION,
+        physical_layout_name: board.board_name.clone(),
+        logical_layout_name: board.default_logical_layout_name.clone(),
+        layer0,
+        layer1,
+        fn_id: hex_value(values, "fn_id").map_or(default_fn_id(), |id| id as u8),
+        tp_sensitivity,
+        macro_key_map,
+        media_key_map,
+        enable_middle_click: choices.get("mclick") == Some(&1),
+    };
+    DecompiledFirmware { config, keymask, board_match, stock, fingerprint: None, notes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::firmware::board_keymask;
+
+    // Stand-in for assn8: DW words are kept, immediates go to the low byte and
+    // other instructions get a word derived from their text
+    fn assemble(source: &str) -> Result<Vec<u8>, String> {
+        let mut rom = vec![0u8; IMAGE_LENGTH];
+        let mut address = 0usize;
+        for line in source.lines().map(|l| l.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|l| !l.is_empty()) {
+            let line = line.as_str();
+            let word = if let Some(org) = line.strip_prefix("ORG 0x") {
+                address = usize::from_str_radix(org, 16).map_err(|e| e.to_string())?;
+                continue;
+            } else if let Some(value) = line.strip_prefix("DW 0x") {
+                u16::from_str_radix(value, 16).map_err(|e| e.to_string())?
+            } else if let Some((op, imm)) = line.split_once("#0x") {
+                (op.bytes().map(u16::from).sum::<u16>() << 8) | u16::from_str_radix(imm, 16).map_err(|e| e.to_string())?
+            } else {
+                line.bytes().map(u16::from).fold(0u16, |h, b| h.wrapping_mul(31).wrapping_add(b))
+            };
+            rom[address * 2..address * 2 + 2].copy_from_slice(&word.to_le_bytes());
+            address += 1;
+        }
+        Ok(rom)
+    }
+
+    #[test]
+    fn recovers_template_values() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let board = &general_setting.avail_boards[1];
+        let km = board_keymask(board);
+        let mut template = String::from("ORG 0x0010\n    NOP\n    CMPRS A, #0x${s/fn_id/af}\n");
+        for (row, mask) in km.iter().enumerate() {
+            template += &format!("    DW 0x${{s/km_{:x}/00{:02x}}}\n", row, mask);
+        }
+        template += "    DW 0x${s/60/0029}\n    DW 0x${s/61/0035}\n    DW 0x${s/macro_e8/0000}\n    DW 0x${s/media_d5/0000}\n";
+        for i in 0..4 {
+            template += &format!("    ${{e/tp_accel_{}/NOP/CALL func_07b7}}\n", i);
+        }
+        template += "    ${e/mclick/B0BSET/B0BCLR} 0x14.5\n";
+
+        let stock = assemble("ORG 0x0010\n    JMP 0x0100\n").unwrap();
+        let values = HashMap::from([
+            ("fn_id".to_string(), "e4".to_string()),
+            ("60".to_string(), "3929".to_string()),
+            ("macro_e8".to_string(), "0106".to_string()),
+            ("media_d5".to_string(), "00e9".to_string()),
+        ]);
+        let choices = HashMap::from([("tp_accel_0".to_string(), 1), ("tp_accel_1".to_string(), 1), ("mclick".to_string(), 1)]);
+        let image = assemble(&render_template(&template, &values, &choices).unwrap()).unwrap();
+
+        let decompiled = decompile_image(&template, &stock, &image, &general_setting, &mut assemble).unwrap();
+        let config = &decompiled.config;
+        assert!(!decompiled.stock);
+        assert_eq!(decompiled.keymask, km);
+        assert_eq!(config.physical_layout_name, board.board_name);
+        assert!(decompiled.board_match.as_ref().is_some_and(|found| found.exact));
+        assert_eq!(config.fn_id, 0xe4);
+        assert_eq!(config.layer0[&0x60], Some(0x29));
+        assert_eq!(config.layer1[&0x60], Some(0x39));
+        assert_eq!(config.layer1[&0x61], None);
+        assert_eq!(config.macro_key_map[&0xe8], macro_key_from(0x06, [true, false, false, false, false, false, false, false]));
+        assert_eq!(config.media_key_map[&0xd5], 0xe9);
+        assert_eq!(config.tp_sensitivity, 3);
+        assert!(config.enable_middle_click);
+
+        let decompiled = decompile_image(&template, &stock, &stock, &general_setting, &mut assemble).unwrap();
+        assert!(decompiled.stock);
+        assert_eq!(decompiled.config.layer0[&0x60], Some(0x29));
+        assert_eq!(decompiled.config.tp_sensitivity, 1);
+    }
+
+    #[test]
+    fn locates_every_placeholder_of_the_firmware_template() {
+        let diff: serde_json::Value =
+            serde_json::from_str(include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/template/diff.json"))).unwrap();
+        let mut template = String::from("ORG 0x0010\n");
+        for op in diff["ops"].as_array().unwrap() {
+            if let Some(code) = op["code"].as_str().filter(|code| code.contains("${")) {
+                template += code;
+                template += "\n";
+            }
+        }
+
+        let slots = locate_slots(&template, &mut assemble).unwrap();
+        let mut addresses: Vec<usize> = slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Value { address, .. } | Slot::Choice { address, .. } => *address,
+            })
+            .collect();
+        assert_eq!(slots.len(), placeholders(&template).len());
+        assert_eq!(slots.iter().filter(|slot| matches!(slot, Slot::Choice { .. })).count(), 5);
+        assert!(slots.iter().any(|slot| matches!(slot, Slot::Value { name, digits: 2, .. } if name == "fn_id")));
+        addresses.sort();
+        addresses.dedup();
+        assert_eq!(addresses.len(), slots.len());
+    }
+}
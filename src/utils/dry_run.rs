@@ -0,0 +1,190 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::utils::flasher::{
+    hexdump, FlashError, FlashImage, Flasher, Transport, ALL_ERASED_EXPECTED_CHECKSUM,
+    REQUEST_GET_REPORT, REQUEST_SET_REPORT, REPORT_LENGTH,
+};
+
+/// Ordered record of every control transfer, delay and stage caption.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    lines: Vec<String>,
+    transfers: usize,
+}
+
+impl Transcript {
+    fn push_transfer(&mut self, request: u8, value: u16, index: u16, data: &[u8]) {
+        self.transfers += 1;
+        let name = match request {
+            REQUEST_SET_REPORT => "SET_REPORT",
+            REQUEST_GET_REPORT => "GET_REPORT",
+            _ => "REQUEST",
+        };
+        self.lines.push(format!(
+            "{:05} {:<10} req={:#04x} val={:#06x} idx={:#06x} | {}",
+            self.transfers, name, request, value, index, hexdump(data)
+        ));
+    }
+
+    /// Mark the transfer just pushed as failed.
+    fn push_error(&mut self, error: &FlashError) {
+        self.lines.push(format!("      ! {}", error));
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            let _ = writeln!(&mut out, "{}", line);
+        }
+        out
+    }
+}
+
+/// Transport wrapper that writes everything passing through it to a [`Transcript`].
+pub struct Recorder<T: Transport> {
+    inner: T,
+    transcript: Transcript,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn new(inner: T) -> Recorder<T> {
+        Recorder { inner, transcript: Transcript::default() }
+    }
+
+    pub fn into_parts(self) -> (T, Transcript) {
+        (self.inner, self.transcript)
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn control_write(&mut self, request: u8, value: u16, index: u16, data: &[u8]) -> Result<(), FlashError> {
+        self.transcript.push_transfer(request, value, index, data);
+        let result = self.inner.control_write(request, value, index, data);
+        if let Err(e) = &result {
+            self.transcript.push_error(e);
+        }
+        result
+    }
+
+    fn control_read(&mut self, request: u8, value: u16, index: u16, length: usize) -> Result<Vec<u8>, FlashError> {
+        let result = self.inner.control_read(request, value, index, length);
+        match &result {
+            Ok(data) => self.transcript.push_transfer(request, value, index, data),
+            Err(e) => {
+                self.transcript.push_transfer(request, value, index, &[]);
+                self.transcript.push_error(e);
+            }
+        }
+        result
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.transcript.lines.push(format!("      sleep {} ms", duration.as_millis()));
+        self.inner.sleep(duration);
+    }
+
+    fn note(&mut self, text: &str) {
+        self.transcript.lines.push(format!("# {}", text));
+        self.inner.note(text);
+    }
+}
+
+/// Stand-in device that answers like a healthy keyboard would, without touching USB.
+///
+/// It starts in keyboard mode with a locked flash, so the transcript covers the
+/// full switch/unlock handshake. Only the checksum is tracked, not the flash contents.
+pub struct DryRunDevice {
+    code_options: [u8; 8],
+    in_flasher_mode: bool,
+    unlocked: bool,
+    checksum: u16,
+    packets_left: u16,
+    response: [u8; REPORT_LENGTH],
+}
+
+impl DryRunDevice {
+    /// Device whose code options match `image`.
+    pub fn for_image(image: &FlashImage) -> DryRunDevice {
+        DryRunDevice {
+            code_options: image.code_options,
+            in_flasher_mode: false,
+            unlocked: false,
+            checksum: 0,
+            packets_left: 0,
+            response: [0; REPORT_LENGTH],
+        }
+    }
+}
+
+impl Transport for DryRunDevice {
+    fn control_write(&mut self, _request: u8, _value: u16, _index: u16, data: &[u8]) -> Result<(), FlashError> {
+        if self.packets_left > 0 {
+            self.packets_left -= 1;
+            let sum = data.iter().fold(0u16, |acc, b| acc.wrapping_add(*b as u16));
+            self.checksum = self.checksum.wrapping_add(sum).wrapping_sub(0xff * data.len() as u16);
+            return Ok(());
+        }
+        if data == [0xaa, 0x55, 0xa5, 0x5a, 0xff, 0x00, 0x33, 0xcc] {
+            self.in_flasher_mode = true;
+            return Ok(());
+        }
+        if !self.in_flasher_mode || data.len() != REPORT_LENGTH || data[1..3] != [0xaa, 0x55] {
+            return Ok(());
+        }
+        let mut response = [data[0], 0xaa, 0x55, data[3], 0, 0, 0, 0];
+        match data[0] {
+            0x01 => response[5] = 0x03,
+            0x02 => {
+                self.unlocked = data[4..] == [0x12, 0x34, 0x56, 0x78];
+                if self.unlocked {
+                    response[4..].copy_from_slice(&[0xfa; 4]);
+                }
+            }
+            0x03 if self.unlocked => response[4..].copy_from_slice(&[0xfa; 4]),
+            0x04 => self.checksum = ALL_ERASED_EXPECTED_CHECKSUM,
+            0x05 => {
+                self.packets_left = u16::from_le_bytes([data[6], data[7]]);
+                response[4..].copy_from_slice(&[0xfa; 4]);
+            }
+            0x06 => {
+                let [lo, hi] = self.checksum.to_le_bytes();
+                response[4..].copy_from_slice(&[0xfa, 0xfa, lo, hi]);
+            }
+            0x07 => self.in_flasher_mode = false,
+            0x09 => {
+                let start = if data[3] == 0 { 0 } else { 4 };
+                response[4..].copy_from_slice(&self.code_options[start..start + 4]);
+            }
+            _ => {}
+        }
+        self.response = response;
+        Ok(())
+    }
+
+    fn control_read(&mut self, _request: u8, _value: u16, _index: u16, length: usize) -> Result<Vec<u8>, FlashError> {
+        if !self.in_flasher_mode {
+            // An ordinary keyboard input report
+            return Ok(vec![0; length]);
+        }
+        Ok(self.response[..length.min(REPORT_LENGTH)].to_vec())
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+}
+
+/// Run the whole flashing sequence for `image` against a [`DryRunDevice`] and
+/// return what would have been sent to the keyboard.
+pub fn dry_run(image: &FlashImage) -> Result<Transcript, FlashError> {
+    let mut recorder = Recorder::new(DryRunDevice::for_image(image));
+    recorder.note("KU-1255 flash transcript (dry run, nothing was sent to a device)");
+    recorder.note(&format!(
+        "Image: {} programmable bytes, expected checksum {:#06x}",
+        image.program.len(),
+        image.expected_checksum()
+    ));
+    let mut flasher = Flasher::new(recorder);
+    let result = flasher.flash(image);
+    let (_, transcript) = flasher.into_transport().into_parts();
+    result.map(|_| transcript)
+}
@@ -0,0 +1,399 @@
+use std::time::Duration;
+
+use crate::utils::flasher::{
+    FlashError, Transport, CANARY, CANARY_ADDRESS_WORDS, CODE_OPTIONS_ADDRESS_WORDS,
+    ERASE_BLOCK_LENGTH_WORDS, FLASHER_BASE_ADDRESS_WORDS, IMAGE_LENGTH, REPORT_LENGTH,
+    UNPROGRAMABLE_PREFIX_WORDS,
+};
+use crate::utils::fingerprint::FINGERPRINT_ADDRESS_WORDS;
+
+const FLASH_WORDS: usize = IMAGE_LENGTH / 2;
+// JMP 0x2800 written by the bootloader into word 0 after an erase
+const RESET_VECTOR: u16 = 0xa880;
+
+/// Faults to inject, counted in control transfers (1-based) or programming packets (0-based).
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    /// Transfers that time out without reaching the device
+    pub timeouts: Vec<usize>,
+    /// Programming packets that are acknowledged but never written
+    pub dropped_packets: Vec<usize>,
+    /// Every transfer from this one on fails as if the cable was pulled
+    pub disconnect_at: Option<usize>,
+}
+
+/// Software model of the KU-1255 bootloader.
+///
+/// Flash is kept as 0x3000 words. Programming can only clear bits, like real
+/// flash. The bootloader lives at 0x2800 and does not protect itself, so any
+/// erase or program touching it marks the device as destroyed.
+pub struct EmulatedDevice {
+    pub flash: Vec<u16>,
+    pub in_flasher_mode: bool,
+    pub unlocked: bool,
+    pub flasher_destroyed: bool,
+    pub faults: Faults,
+    pub transfers: usize,
+    response: Option<[u8; REPORT_LENGTH]>,
+    program_cursor: usize,
+    packets_left: usize,
+    packets_seen: usize,
+}
+
+impl EmulatedDevice {
+    /// Keyboard running `image` (a plain 0x6000 byte image).
+    pub fn with_image(image: &[u8]) -> EmulatedDevice {
+        let flash = image
+            .chunks_exact(2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .chain(std::iter::repeat(0xffff))
+            .take(FLASH_WORDS)
+            .collect();
+        EmulatedDevice {
+            flash,
+            in_flasher_mode: false,
+            unlocked: false,
+            flasher_destroyed: false,
+            faults: Faults::default(),
+            transfers: 0,
+            response: None,
+            program_cursor: 0,
+            packets_left: 0,
+            packets_seen: 0,
+        }
+    }
+
+    /// Keyboard left in the bootloader by an interrupted flash.
+    pub fn stuck_in_flasher(image: &[u8]) -> EmulatedDevice {
+        let mut device = EmulatedDevice::with_image(image);
+        device.erase_words(0, FLASHER_BASE_ADDRESS_WORDS as usize);
+        device.in_flasher_mode = true;
+        device
+    }
+
+    pub fn with_faults(mut self, faults: Faults) -> EmulatedDevice {
+        self.faults = faults;
+        self
+    }
+
+    /// Byte sum of words 0x0000..=0x27ff, as reported by the bootloader.
+    pub fn checksum(&self) -> u16 {
+        self.flash[..FLASHER_BASE_ADDRESS_WORDS as usize]
+            .iter()
+            .fold(0u16, |acc, w| {
+                let [lo, hi] = w.to_le_bytes();
+                acc.wrapping_add(lo as u16).wrapping_add(hi as u16)
+            })
+    }
+
+    pub fn has_canary(&self) -> bool {
+        self.flash[CANARY_ADDRESS_WORDS as usize] == u16::from_le_bytes(CANARY)
+    }
+
+    /// Application bytes (words 0x0008..0x2800) currently in flash.
+    pub fn program_bytes(&self) -> Vec<u8> {
+        self.flash[UNPROGRAMABLE_PREFIX_WORDS as usize..FLASHER_BASE_ADDRESS_WORDS as usize]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect()
+    }
+
+    fn erase_words(&mut self, start: usize, end: usize) {
+        for addr in start..end.min(FLASH_WORDS) {
+            self.flash[addr] = 0xffff;
+        }
+        if start == 0 {
+            self.flash[0] = RESET_VECTOR;
+            for addr in 1..UNPROGRAMABLE_PREFIX_WORDS as usize {
+                self.flash[addr] = 0;
+            }
+        }
+    }
+
+    fn touches_flasher(&self, end_words: usize) -> bool {
+        end_words > FLASHER_BASE_ADDRESS_WORDS as usize
+    }
+
+    fn command(&mut self, data: &[u8]) {
+        if data == [0xaa, 0x55, 0xa5, 0x5a, 0xff, 0x00, 0x33, 0xcc] {
+            self.in_flasher_mode = true;
+            self.response = None;
+            return;
+        }
+        if !self.in_flasher_mode || data.len() != REPORT_LENGTH || data[1..3] != [0xaa, 0x55] {
+            return;
+        }
+        let arg0 = u16::from_le_bytes([data[4], data[5]]) as usize;
+        let arg1 = u16::from_le_bytes([data[6], data[7]]) as usize;
+        let mut response = [data[0], 0xaa, 0x55, data[3], 0, 0, 0, 0];
+        match data[0] {
+            0x01 => response[5] = 0x03,
+            0x02 => {
+                self.unlocked = data[4..] == [0x12, 0x34, 0x56, 0x78];
+                if self.unlocked {
+                    response[4..].copy_from_slice(&[0xfa; 4]);
+                }
+            }
+            0x03 if self.unlocked => response[4..].copy_from_slice(&[0xfa; 4]),
+            0x04 if self.unlocked => {
+                let end = arg0 + arg1 * ERASE_BLOCK_LENGTH_WORDS as usize;
+                if self.touches_flasher(end) {
+                    self.flasher_destroyed = true;
+                }
+                self.erase_words(arg0, end);
+                self.response = None;
+                return;
+            }
+            0x05 if self.unlocked => {
+                if self.touches_flasher(arg0 + arg1 * 4) {
+                    self.flasher_destroyed = true;
+                }
+                self.program_cursor = arg0;
+                self.packets_left = arg1;
+                response[4..].copy_from_slice(&[0xfa; 4]);
+            }
+            0x06 if self.unlocked => {
+                let [lo, hi] = self.checksum().to_le_bytes();
+                response[4..].copy_from_slice(&[0xfa, 0xfa, lo, hi]);
+            }
+            0x07 => {
+                // The bootloader only starts the application when the canary is present
+                self.in_flasher_mode = !self.has_canary();
+                self.unlocked = false;
+                self.response = None;
+                return;
+            }
+            0x09 => {
+                let start = CODE_OPTIONS_ADDRESS_WORDS as usize + if data[3] == 0 { 0 } else { 2 };
+                for (i, w) in self.flash[start..start + 2].iter().enumerate() {
+                    response[4 + i * 2..6 + i * 2].copy_from_slice(&w.to_le_bytes());
+                }
+            }
+            _ => {}
+        }
+        self.response = Some(response);
+    }
+
+    fn program_packet(&mut self, data: &[u8]) {
+        let packet = self.packets_seen;
+        self.packets_seen += 1;
+        self.packets_left -= 1;
+        let cursor = self.program_cursor;
+        self.program_cursor += data.len() / 2;
+        if self.faults.dropped_packets.contains(&packet) {
+            return;
+        }
+        for (i, w) in data.chunks_exact(2).enumerate() {
+            if let Some(word) = self.flash.get_mut(cursor + i) {
+                *word &= u16::from_le_bytes([w[0], w[1]]);
+            }
+        }
+    }
+
+    fn begin_transfer(&mut self) -> Result<(), FlashError> {
+        self.transfers += 1;
+        if self.faults.disconnect_at.is_some_and(|n| self.transfers >= n) {
+            return Err(FlashError::Disconnected);
+        }
+        if self.faults.timeouts.contains(&self.transfers) || self.flasher_destroyed {
+            return Err(FlashError::Timeout);
+        }
+        Ok(())
+    }
+}
+
+impl Transport for EmulatedDevice {
+    fn control_write(&mut self, _request: u8, _value: u16, _index: u16, data: &[u8]) -> Result<(), FlashError> {
+        self.begin_transfer()?;
+        if self.packets_left > 0 {
+            self.program_packet(data);
+        } else {
+            self.command(data);
+        }
+        Ok(())
+    }
+
+    fn control_read(&mut self, _request: u8, _value: u16, _index: u16, length: usize) -> Result<Vec<u8>, FlashError> {
+        self.begin_transfer()?;
+        if !self.in_flasher_mode {
+            // An ordinary keyboard input report
+            return Ok(vec![0; length]);
+        }
+        match self.response.take() {
+            Some(response) => Ok(response[..length.min(REPORT_LENGTH)].to_vec()),
+            None => Err(FlashError::Timeout),
+        }
+    }
+
+    fn sleep(&mut self, _duration: Duration) {}
+}
+
+/// Firmware image for tests, with `seed` varying the program. Like the stock
+/// firmware, the ROM between the program and the canary is left blank for the
+/// fingerprint.
+pub fn test_image(seed: u8) -> Vec<u8> {
+    let mut image = vec![0xffu8; IMAGE_LENGTH];
+    image[0..2].copy_from_slice(&RESET_VECTOR.to_le_bytes());
+    image[2..16].fill(0);
+    for (i, b) in image[16..FINGERPRINT_ADDRESS_WORDS as usize * 2].iter_mut().enumerate() {
+        *b = (i as u8).wrapping_mul(31).wrapping_add(seed);
+    }
+    image[0x4ffe..0x5000].copy_from_slice(&CANARY);
+    let options = CODE_OPTIONS_ADDRESS_WORDS as usize * 2;
+    image[options..options + 8].copy_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]);
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Config;
+    use crate::utils::fingerprint::{firmware_identity, read_fingerprint};
+    use crate::utils::firmware::finish_flash_image;
+    use crate::utils::history::config_hash;
+    use crate::utils::dry_run::Recorder;
+    use crate::utils::flasher::{FlashImage, Flasher, ALL_ERASED_EXPECTED_CHECKSUM};
+    use crate::utils::recovery::{detect_state, recover, ApplicationState, DeviceState};
+
+    fn flash(device: EmulatedDevice, image: &[u8]) -> (EmulatedDevice, Result<(), FlashError>) {
+        let image = FlashImage::from_bytes(image).unwrap();
+        let mut flasher = Flasher::new(device);
+        let result = flasher.flash(&image);
+        (flasher.into_transport(), result)
+    }
+
+    #[test]
+    fn flashes_and_reboots_into_new_image() {
+        let new = test_image(7);
+        let (device, result) = flash(EmulatedDevice::with_image(&test_image(1)), &new);
+        result.unwrap();
+        assert_eq!(device.program_bytes(), new[16..0x5000]);
+        assert_eq!(device.checksum(), FlashImage::from_bytes(&new).unwrap().expected_checksum());
+        assert!(!device.in_flasher_mode);
+        assert!(!device.flasher_destroyed);
+    }
+
+    #[test]
+    fn builds_and_flashes_a_config() {
+        let config: Config = serde_json::from_reader(std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json")).unwrap()).unwrap();
+        let stock = test_image(1);
+        // Assembled from the stock firmware: same prefix, changed key map
+        let mut assembled = stock.clone();
+        assembled[0x1000..0x1100].copy_from_slice(&test_image(2)[0x1000..0x1100]);
+
+        let built = finish_flash_image(assembled, &stock, &config).unwrap();
+        assert_eq!(built.notes, Vec::<String>::new());
+        let (device, result) = flash(EmulatedDevice::with_image(&stock), &built.bytes);
+        result.unwrap();
+        assert!(!device.in_flasher_mode);
+        assert!(device.has_canary());
+
+        // The keyboard reports the checksum of the build and carries its fingerprint
+        let (checksum, fingerprint) = firmware_identity(&built.bytes).unwrap();
+        assert_eq!(device.checksum(), checksum);
+        assert_eq!(device.checksum(), built.image.expected_checksum());
+        let flashed: Vec<u8> = device.flash.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(read_fingerprint(&flashed), fingerprint);
+        assert!(fingerprint.unwrap().matches_config_hash(&config_hash(&config)));
+
+        // A build that touches the protected prefix is never flashed
+        let mut unsafe_build = stock.clone();
+        unsafe_build[0x20] ^= 0xff;
+        let Err(err) = finish_flash_image(unsafe_build, &stock, &config) else { panic!("unsafe build accepted") };
+        assert!(err.contains("safety check"), "{}", err);
+    }
+
+    #[test]
+    fn retries_timed_out_packets() {
+        let faults = Faults { timeouts: vec![30, 31, 500], ..Faults::default() };
+        let device = EmulatedDevice::with_image(&test_image(1)).with_faults(faults);
+        let (device, result) = flash(device, &test_image(2));
+        result.unwrap();
+        assert!(!device.in_flasher_mode);
+    }
+
+    #[test]
+    fn transcript_marks_failed_transfers() {
+        let faults = Faults { timeouts: vec![30, 31, 500], ..Faults::default() };
+        let device = EmulatedDevice::with_image(&test_image(1)).with_faults(faults);
+        let image = FlashImage::from_bytes(&test_image(2)).unwrap();
+        let mut flasher = Flasher::new(Recorder::new(device));
+        flasher.flash(&image).unwrap();
+        let (_, transcript) = flasher.into_transport().into_parts();
+        let text = transcript.to_text();
+        let failed: Vec<_> = text.lines().filter(|line| line.starts_with("      ! ")).collect();
+        assert_eq!(failed, vec![format!("      ! {}", FlashError::Timeout); 3], "{}", text);
+    }
+
+    #[test]
+    fn dropped_packet_fails_verification_and_leaves_device_recoverable() {
+        let stock = test_image(1);
+        let faults = Faults { dropped_packets: vec![100], ..Faults::default() };
+        let device = EmulatedDevice::with_image(&stock).with_faults(faults);
+        let (mut device, result) = flash(device, &test_image(2));
+        assert!(matches!(result, Err(FlashError::ChecksumMismatch { stage: "program", .. })));
+        assert!(device.in_flasher_mode);
+        assert_eq!(device.checksum(), ALL_ERASED_EXPECTED_CHECKSUM);
+
+        device.faults = Faults::default();
+        let stock_image = FlashImage::from_bytes(&stock).unwrap();
+        let mut flasher = Flasher::new(device);
+        let state = recover(&mut flasher, &stock_image).unwrap();
+        assert_eq!(
+            state,
+            DeviceState::Flasher { checksum: Some(ALL_ERASED_EXPECTED_CHECKSUM), application: ApplicationState::Erased }
+        );
+        let device = flasher.into_transport();
+        assert_eq!(device.program_bytes(), stock[16..0x5000]);
+        assert!(!device.in_flasher_mode);
+    }
+
+    #[test]
+    fn disconnect_midway_is_reported() {
+        let faults = Faults { disconnect_at: Some(1000), ..Faults::default() };
+        let device = EmulatedDevice::with_image(&test_image(1)).with_faults(faults);
+        let (device, result) = flash(device, &test_image(2));
+        assert!(matches!(result, Err(FlashError::Disconnected)));
+        assert!(!device.has_canary());
+
+        let stuck = EmulatedDevice::stuck_in_flasher(&test_image(1));
+        let stock_image = FlashImage::from_bytes(&test_image(1)).unwrap();
+        let mut flasher = Flasher::new(stuck);
+        let state = detect_state(&mut flasher, &stock_image).unwrap();
+        assert_eq!(state, DeviceState::Flasher { checksum: None, application: ApplicationState::Locked });
+        assert!(!flasher.into_transport().unlocked);
+    }
+
+    #[test]
+    fn recovery_unlocks_and_reports_the_missing_canary() {
+        let stock = test_image(1);
+        let stock_image = FlashImage::from_bytes(&stock).unwrap();
+        let mut flasher = Flasher::new(EmulatedDevice::stuck_in_flasher(&stock));
+        let state = recover(&mut flasher, &stock_image).unwrap();
+        let DeviceState::Flasher { checksum: Some(_), application } = state else { panic!("{:?}", state) };
+        assert_eq!(application.canary(), Some(false));
+        let device = flasher.into_transport();
+        assert!(device.has_canary());
+        assert!(!device.in_flasher_mode);
+    }
+
+    #[test]
+    fn running_keyboard_needs_no_recovery() {
+        let stock = test_image(1);
+        let stock_image = FlashImage::from_bytes(&stock).unwrap();
+        let mut flasher = Flasher::new(EmulatedDevice::with_image(&stock));
+        assert_eq!(recover(&mut flasher, &stock_image).unwrap(), DeviceState::Keyboard);
+        assert_eq!(flasher.into_transport().flash[8..0x2800], EmulatedDevice::with_image(&stock).flash[8..0x2800]);
+    }
+
+    #[test]
+    fn code_option_mismatch_aborts_before_erase() {
+        let mut other = test_image(2);
+        let options = CODE_OPTIONS_ADDRESS_WORDS as usize * 2;
+        other[options] ^= 0xff;
+        let stock = test_image(1);
+        let (device, result) = flash(EmulatedDevice::with_image(&stock), &other);
+        assert!(matches!(result, Err(FlashError::CodeOptionMismatch)));
+        assert!(device.has_canary());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::models::Config;
+use crate::utils::decompile::firmware_image;
+use crate::utils::flasher::{CANARY_ADDRESS_WORDS, FlashImage};
+use crate::utils::history::config_hash;
+
+/// Word address of the fingerprint, in ROM left unused below the canary and the flasher.
+pub const FINGERPRINT_ADDRESS_WORDS: u16 = CANARY_ADDRESS_WORDS - 0x1f;
+/// 16 words: magic, format, app version, build time and config hash
+const FINGERPRINT_LENGTH: usize = 32;
+const MAGIC: &[u8; 4] = b"KUFP";
+const FORMAT_VERSION: u8 = 1;
+const HASH_LENGTH: usize = 16;
+
+/// Build information embedded into a modified firmware.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Fingerprint {
+    pub app_version: [u8; 3],
+    /// Unix time of the build
+    pub build_time: u32,
+    /// First 16 bytes of the config hash recorded in the flash history, as hex
+    pub config_hash: String,
+}
+
+impl Fingerprint {
+    pub fn new(config: &Config) -> Fingerprint {
+        let mut app_version = [0u8; 3];
+        for (part, number) in app_version.iter_mut().zip(env!("CARGO_PKG_VERSION").split('.')) {
+            *part = number.parse().unwrap_or(0);
+        }
+        Fingerprint {
+            app_version,
+            build_time: chrono::Utc::now().timestamp().clamp(0, u32::MAX as i64) as u32,
+            config_hash: config_hash(config)[..HASH_LENGTH * 2].to_string(),
+        }
+    }
+
+    /// Whether `hash` (a full config hash) is the config this firmware was built from.
+    pub fn matches_config_hash(&self, hash: &str) -> bool {
+        hash.starts_with(&self.config_hash)
+    }
+
+    fn to_bytes(&self) -> [u8; FINGERPRINT_LENGTH] {
+        let mut bytes = [0u8; FINGERPRINT_LENGTH];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = FORMAT_VERSION;
+        bytes[5..8].copy_from_slice(&self.app_version);
+        bytes[8..12].copy_from_slice(&self.build_time.to_le_bytes());
+        for (i, byte) in bytes[12..12 + HASH_LENGTH].iter_mut().enumerate() {
+            *byte = u8::from_str_radix(self.config_hash.get(i * 2..i * 2 + 2).unwrap_or("00"), 16).unwrap_or(0);
+        }
+        let sum = bytes[..FINGERPRINT_LENGTH - 2].iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        bytes[FINGERPRINT_LENGTH - 2..].copy_from_slice(&sum.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Fingerprint> {
+        if &bytes[..4] != MAGIC || bytes[4] != FORMAT_VERSION {
+            return None;
+        }
+        let sum = bytes[..FINGERPRINT_LENGTH - 2].iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16));
+        if bytes[FINGERPRINT_LENGTH - 2..] != sum.to_le_bytes() {
+            return None;
+        }
+        Some(Fingerprint {
+            app_version: [bytes[5], bytes[6], bytes[7]],
+            build_time: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            config_hash: bytes[12..12 + HASH_LENGTH].iter().map(|b| format!("{:02x}", b)).collect(),
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [major, minor, patch] = self.app_version;
+        let built = chrono::DateTime::from_timestamp(self.build_time as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default();
+        write!(f, "v{}.{}.{}, config {}, built {}", major, minor, patch, &self.config_hash[..12], built)
+    }
+}
+
+fn fingerprint_range() -> std::ops::Range<usize> {
+    let start = FINGERPRINT_ADDRESS_WORDS as usize * 2;
+    start..start + FINGERPRINT_LENGTH
+}
+
+/// Write `fingerprint` into a plain firmware image. The area must still be blank,
+/// i.e. filled with a single repeated word.
+pub fn embed_fingerprint(image: &mut [u8], fingerprint: &Fingerprint) -> Result<(), String> {
+    let area = image.get_mut(fingerprint_range()).ok_or("Image too short for a fingerprint")?;
+    if area.chunks_exact(2).any(|word| word != &area[..2]) {
+        return Err(format!("ROM at {:#06x} is in use; no fingerprint embedded", FINGERPRINT_ADDRESS_WORDS));
+    }
+    area.copy_from_slice(&fingerprint.to_bytes());
+    Ok(())
+}
+
+/// Fingerprint of a plain firmware image, if it has one.
+pub fn read_fingerprint(image: &[u8]) -> Option<Fingerprint> {
+    image.get(fingerprint_range()).and_then(Fingerprint::from_bytes)
+}
+
+/// Flash checksum and fingerprint of a firmware binary, SN8 file or installer.
+pub fn firmware_identity(bytes: &[u8]) -> Result<(u16, Option<Fingerprint>), String> {
+    let image = firmware_image(bytes)?;
+    let checksum = FlashImage::from_bytes(&image).map_err(|e| e.to_string())?.expected_checksum();
+    Ok((checksum, read_fingerprint(&image)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::flasher::IMAGE_LENGTH;
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let config: Config = serde_json::from_reader(std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json")).unwrap()).unwrap();
+        let fingerprint = Fingerprint::new(&config);
+        assert!(fingerprint.matches_config_hash(&config_hash(&config)));
+
+        let mut image = vec![0xff; IMAGE_LENGTH];
+        assert_eq!(read_fingerprint(&image), None);
+        embed_fingerprint(&mut image, &fingerprint).unwrap();
+        assert_eq!(read_fingerprint(&image), Some(fingerprint.clone()));
+        assert!(embed_fingerprint(&mut image, &fingerprint).is_err());
+
+        image[FINGERPRINT_ADDRESS_WORDS as usize * 2 + 9] ^= 1;
+        assert_eq!(read_fingerprint(&image), None);
+    }
+}
@@ -1,40 +1,156 @@
 use std::collections::{HashMap, BTreeMap};
-use dioxus::prelude::{Signal, ReadSignal, Resource, ReadableExt, WritableExt};
+use dioxus::prelude::{Signal, ReadSignal, Resource, ReadableExt, WritableExt, SyncStorage};
 use std::fs;
-use std::path::{Path};
+use std::path::{PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use std::io;
 use std::io::{Write};
-use crate::models::{Board, MacroKey};
+use crate::models::{Board, Config, GeneralSeitting, MacroKey, BUILD_DIR, resources};
 
+use crate::utils::config::CONFIG_VERSION;
 use crate::utils::template::render_template_file;
 use crate::utils::diff::apply_diff_files;
 use crate::utils::format::format_asm_file;
-use crate::utils::commands::{run_dissn8, run_assn8, run_flashsn8_gui};
+use crate::utils::commands::{run_dissn8, run_assn8};
 use crate::utils::installer::{
-    extract_fw_from_installer_to_file,
+    extract_fw_from_installer_to_file, extract_fw_from_installer_to_vec,
 };
+use crate::utils::flasher::{FlashImage, FlashProgress, Flasher, check_against_original, DEFAULT_DEVICE_IDS};
+use crate::utils::dry_run::dry_run;
+use crate::utils::recovery::{DeviceState, detect_usb_device_state, recover_usb_device};
+use crate::utils::usb::{UsbDeviceInfo, UsbTransport, find_devices, find_single_device};
+use crate::utils::batch::{BatchStatus, DeviceOutcome, flash_batch};
+use crate::utils::history::{HistoryEntry, find_flashed_firmware, load_history_image, record_flash};
+use crate::utils::fingerprint::{Fingerprint, embed_fingerprint};
+use crate::utils::decompile::{DecompiledFirmware, decompile_image, firmware_image};
+use crate::utils::board_match::{BoardMatch, infer_board_from_history};
 
-const ORG_INSTALLER_PATH: &str = "firmware/tp_compact_usb_kb_with_trackpoint_fw.exe";
+// Files in the build directory of `Resources`; the others are in a `BuildDir`
+const ORG_INSTALLER: &str = "tp_compact_usb_kb_with_trackpoint_fw.exe";
 
-const ORG_BIN_PATH: &str = "firmware/fw_org.bin";
-const MOD_BIN_PATH: &str = "firmware/fw_mod.bin";
+const ORG_BIN: &str = "fw_org.bin";
+const MOD_BIN: &str = "fw_mod.bin";
 
-const ORG_ASM_PATH: &str = "firmware/fw_org.asm";
-const FMT_ASM_PATH: &str = "firmware/fw_fmt.asm";
-const TMP_ASM_PATH: &str = "firmware/fw_tmp.asm";
-const MOD_ASM_PATH: &str = "firmware/fw_mod.asm";
+const ORG_ASM: &str = "fw_org.asm";
+const FMT_ASM: &str = "fw_fmt.asm";
+const TMP_ASM: &str = "fw_tmp.asm";
+const MOD_ASM: &str = "fw_mod.asm";
+const PROBE_ASM: &str = "fw_probe.asm";
+const PROBE_BIN: &str = "fw_probe.bin";
+
+const BATCH_REPORT: &str = "batch_report.txt";
+
+const KEYBOARD_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 const DIFF_PATH: &str = "template/diff.json";
 const COMMENTS_PATH: &str = "template/comments.txt";
 
+/// Scratch directory of one build below the per-user build directory, so
+/// that concurrent jobs do not overwrite each other's files. Removed on drop.
+struct BuildDir(tempfile::TempDir);
+
+impl BuildDir {
+    fn new() -> Result<BuildDir, String> {
+        let parent = resources().build_dir();
+        tempfile::Builder::new()
+            .prefix("build-")
+            .tempdir_in(&parent)
+            .map(BuildDir)
+            .map_err(|e| format!("Failed to create a build directory in {}: {}", parent.display(), e))
+    }
+
+    fn file(&self, file_name: &str) -> String {
+        self.0.path().join(file_name).to_string_lossy().into_owned()
+    }
+}
+
+/// Run `job`, turning a panic into an error so that a background job
+/// always reports a result.
+fn catch_panic<T>(job: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into());
+        Err(format!("Internal error: {}", msg))
+    })
+}
+
+/// Snapshot of everything that goes into the modified firmware,
+/// so that building can run away from the UI thread.
+#[derive(Clone)]
+pub struct BuildSettings {
+    pub layout0: BTreeMap<u8, Option<u8>>,
+    pub layout1: BTreeMap<u8, Option<u8>>,
+    pub fn_id: u8,
+    pub tp_sensitivity: u32,
+    pub macro_key_map: BTreeMap<u8, MacroKey>,
+    pub media_key_map: BTreeMap<u8, u16>,
+    pub enable_middle_click: bool,
+    pub board: Board,
+    pub logical_layout_name: String,
+}
+
+impl BuildSettings {
+    pub fn from_signals(
+        layout0: Signal<BTreeMap<u8, Option<u8>>>,
+        layout1: Signal<BTreeMap<u8, Option<u8>>>,
+        fn_id: Signal<u8>,
+        tp_sensitivity: Signal<u32>,
+        macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
+        media_key_map: Signal<BTreeMap<u8, u16>>,
+        enable_middle_click: Signal<bool>,
+        selected_board: ReadSignal<Board>,
+        selected_logical_layout_name: ReadSignal<String>,
+    ) -> BuildSettings {
+        BuildSettings {
+            layout0: layout0(),
+            layout1: layout1(),
+            fn_id: fn_id(),
+            tp_sensitivity: tp_sensitivity(),
+            macro_key_map: macro_key_map(),
+            media_key_map: media_key_map(),
+            enable_middle_click: enable_middle_click(),
+            board: selected_board(),
+            logical_layout_name: selected_logical_layout_name(),
+        }
+    }
+
+    /// Config as recorded in the flash history, with the selected logical
+    /// layout so that re-loading it shows the same key labels.
+    pub fn to_config(&self) -> Config {
+        Config {
+            config_version: CONFIG_VERSION,
+            physical_layout_name: self.board.board_name.clone(),
+            logical_layout_name: self.logical_layout_name.clone(),
+            layer0: self.layout0.clone(),
+            layer1: self.layout1.clone(),
+            fn_id: self.fn_id,
+            tp_sensitivity: self.tp_sensitivity,
+            macro_key_map: self.macro_key_map.clone(),
+            media_key_map: self.media_key_map.clone(),
+            enable_middle_click: self.enable_middle_click,
+        }
+    }
+}
+
+/// State of a background install, shown by the progress view.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InstallStatus {
+    Running(FlashProgress),
+    Finished(String),
+    Failed(String),
+}
 
 fn validate_mod_key_position(
-    layout0: Signal<BTreeMap<u8, Option<u8>>>,
-    layout1: Signal<BTreeMap<u8, Option<u8>>>,
+    layout0: &BTreeMap<u8, Option<u8>>,
+    layout1: &BTreeMap<u8, Option<u8>>,
 ) -> Option<String> {
-    for (k, v) in layout0() {
-        if v == Some(231) {
-            if layout1().get(&k) != Some(&Some(231)) {
+    for (k, v) in layout0 {
+        if *v == Some(231) {
+            if layout1.get(k) != Some(&Some(231)) {
                 return Some("The 'Mod' key position must be same on the Main and 2nd layers.".into());
             }
         }
@@ -43,91 +159,515 @@ fn validate_mod_key_position(
 }
 
 
-fn build_mod_fw(
-    firmware_future: Resource<Vec<u8>>,
-    layout0: Signal<BTreeMap<u8, Option<u8>>>,
-    layout1: Signal<BTreeMap<u8, Option<u8>>>,
-    fn_id: Signal<u8>,
-    tp_sensitivity: Signal<u32>,
-    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
-    media_key_map: Signal<BTreeMap<u8, u16>>,
-    enable_middle_click: Signal<bool>,
-    selected_board: ReadSignal<Board>,
-) -> Result<(), String> {
-
-    let Some(original_binary) = &*firmware_future.read_unchecked() else {
-        return Err("Firmware binary not loaded.".into());
-    };
-
-    let _r = extract_fw_from_installer_to_file(original_binary, ORG_BIN_PATH)?;
-    let _r = run_dissn8(ORG_BIN_PATH, ORG_ASM_PATH)
+/// Disassemble the original firmware and apply the patches, leaving the
+/// template with its placeholders in `TMP_ASM` of `dir`.
+fn prepare_template(dir: &BuildDir, original_binary: &[u8], on_stage: &mut dyn FnMut(&str)) -> Result<(), String> {
+    let (org_bin, org_asm, fmt_asm, tmp_asm) = (dir.file(ORG_BIN), dir.file(ORG_ASM), dir.file(FMT_ASM), dir.file(TMP_ASM));
+    let diff_path = resources().find(DIFF_PATH).to_string_lossy().into_owned();
+    let comments_path = resources().find(COMMENTS_PATH).to_string_lossy().into_owned();
+    on_stage("Extracting original firmware...");
+    let _r = extract_fw_from_installer_to_file(original_binary, &org_bin)?;
+    on_stage("Disassembling original firmware...");
+    let _r = run_dissn8(&org_bin, &org_asm)
         .map_err(|e| format!("dissn8 failed: {}", e))?;
-    let _r = format_asm_file(ORG_ASM_PATH, FMT_ASM_PATH)
+    on_stage("Applying patches...");
+    let _r = format_asm_file(&org_asm, &fmt_asm)
         .map_err(|e| format!("Failed to format ASM: {}", e))?;
-    let _r = apply_diff_files(FMT_ASM_PATH, DIFF_PATH, COMMENTS_PATH, TMP_ASM_PATH)
+    let _r = apply_diff_files(&fmt_asm, &diff_path, &comments_path, &tmp_asm)
         .map_err(|e| format!("Failed to apply diff: {}", e))?;
+    Ok(())
+}
+
+fn build_mod_fw(
+    dir: &BuildDir,
+    original_binary: &[u8],
+    settings: &BuildSettings,
+    on_stage: &mut dyn FnMut(&str),
+) -> Result<(), String> {
+
+    prepare_template(dir, original_binary, on_stage)?;
+    let (mod_asm, mod_bin) = (dir.file(MOD_ASM), dir.file(MOD_BIN));
+    on_stage("Writing key map...");
     let _r = modify_asm_file(
-        TMP_ASM_PATH, 
-        MOD_ASM_PATH, 
-        &layout0(), 
-        &layout1(), 
-        fn_id(), 
-        tp_sensitivity(), 
-        &macro_key_map(), 
-        &media_key_map(), 
-        enable_middle_click(),
-        &selected_board(),
+        &dir.file(TMP_ASM), 
+        &mod_asm, 
+        &settings.layout0, 
+        &settings.layout1, 
+        settings.fn_id, 
+        settings.tp_sensitivity, 
+        &settings.macro_key_map, 
+        &settings.media_key_map, 
+        settings.enable_middle_click,
+        &settings.board,
     )
         .map_err(|e| format!("Failed to modify ASM: {}", e))?;
-    let _r = run_assn8(MOD_ASM_PATH, MOD_BIN_PATH)
+    on_stage("Assembling modified firmware...");
+    let _r = run_assn8(&mod_asm, &mod_bin)
         .map_err(|e| format!("assn8 failed: {}", e))?;
 
     Ok(())
 }
 
-pub fn install_firmware_by_flashsn8(
-    id_layout_l0: Signal<BTreeMap<u8, Option<u8>>>,
-    id_layout_l1: Signal<BTreeMap<u8, Option<u8>>>,
+/// Modified firmware ready to flash.
+pub(crate) struct BuiltFirmware {
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) image: FlashImage,
+    /// Problems that did not stop the build, to show with the result
+    pub(crate) notes: Vec<String>,
+}
+
+/// Build the modified firmware and check it against the original.
+fn build_flash_image(
+    original_binary: &[u8],
+    settings: &BuildSettings,
+    on_stage: &mut dyn FnMut(&str),
+) -> Result<BuiltFirmware, String> {
+    let dir = BuildDir::new()?;
+    build_mod_fw(&dir, original_binary, settings, on_stage)
+        .map_err(|err| format!("Failed to build modified firmware: {}", err))?;
+
+    let (mod_bin_path, org_bin_path) = (dir.file(MOD_BIN), dir.file(ORG_BIN));
+    let mod_bin = fs::read(&mod_bin_path)
+        .map_err(|e| format!("Failed to read {}: {}", mod_bin_path, e))?;
+    let org_bin = fs::read(&org_bin_path)
+        .map_err(|e| format!("Failed to read {}: {}", org_bin_path, e))?;
+    on_stage("Embedding fingerprint...");
+    finish_flash_image(mod_bin, &org_bin, &settings.to_config())
+}
+
+/// Embed the fingerprint of `config` into an assembled firmware and check it
+/// against the original firmware it was built from.
+pub(crate) fn finish_flash_image(mut mod_bin: Vec<u8>, org_bin: &[u8], config: &Config) -> Result<BuiltFirmware, String> {
+    let mut notes = Vec::new();
+    if let Err(err) = embed_fingerprint(&mut mod_bin, &Fingerprint::new(config)) {
+        notes.push(format!("{}. The firmware cannot be traced back to its config.", err));
+    }
+    check_against_original(&mod_bin, org_bin)
+        .map_err(|e| format!("Firmware safety check failed: {}", e))?;
+
+    let image = FlashImage::from_bytes(&mod_bin).map_err(|e| e.to_string())?;
+    Ok(BuiltFirmware { bytes: mod_bin, image, notes })
+}
+
+const INSTALLED: &str = "Firmware installed. Unplug and reconnect the keyboard to apply the changes.";
+
+/// Run `job` in a background thread, reporting its result to `install_status`:
+/// `finished` followed by the notes the job returns.
+fn spawn_install(
+    mut install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    finished: &'static str,
+    job: impl FnOnce() -> Result<Vec<String>, String> + Send + 'static,
+) -> Result<(), String> {
+    if let Some(InstallStatus::Running(_)) = install_status() {
+        return Err("An installation is already running.".into());
+    }
+    install_status.set(Some(InstallStatus::Running(FlashProgress::stage("Starting..."))));
+    std::thread::spawn(move || {
+        install_status.set(Some(match catch_panic(job) {
+            Ok(notes) => InstallStatus::Finished(
+                std::iter::once(finished)
+                    .chain(notes.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Err(err) => InstallStatus::Failed(err),
+        }));
+    });
+    Ok(())
+}
+
+/// Start building and flashing in a background thread.
+/// Progress and the final result are written to `install_status`.
+pub fn start_firmware_install(
+    firmware_future: Resource<Vec<u8>>,
+    settings: BuildSettings,
+    mut install_status: Signal<Option<InstallStatus>, SyncStorage>,
+) -> Result<(), String> {
+    if let Some(msg) = validate_mod_key_position(&settings.layout0, &settings.layout1) {
+        return Err(msg);
+    }
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+
+    spawn_install(install_status, INSTALLED, move || {
+        let built = build_flash_image(&original_binary, &settings, &mut |stage| {
+            install_status.set(Some(InstallStatus::Running(FlashProgress::stage(stage))));
+        })?;
+        flash_and_record(&original_binary, &built.bytes, &built.image, Some(settings.to_config()), install_status)?;
+        Ok(built.notes)
+    })
+}
+
+/// Flash an entry of the history journal again, or the stock firmware when `entry` is `None`.
+pub fn start_history_reflash(
+    firmware_future: Resource<Vec<u8>>,
+    entry: Option<HistoryEntry>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+) -> Result<(), String> {
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+    let stock_bytes = extract_fw_from_installer_to_vec(&original_binary)?;
+    let (image_bytes, image, config) = match entry {
+        Some(entry) => {
+            let (image_bytes, image) = load_history_image(&entry)?;
+            check_against_original(&image_bytes, &stock_bytes)
+                .map_err(|e| format!("Firmware safety check failed: {}", e))?;
+            (image_bytes, image, entry.config)
+        }
+        None => {
+            let image = FlashImage::from_bytes(&stock_bytes).map_err(|e| e.to_string())?;
+            (stock_bytes, image, None)
+        }
+    };
+
+    spawn_install(install_status, INSTALLED, move || {
+        flash_and_record(&original_binary, &image_bytes, &image, config, install_status)?;
+        Ok(Vec::new())
+    })
+}
+
+/// Flash the single connected keyboard and append the outcome to the history journal.
+fn flash_and_record(
+    original_binary: &[u8],
+    image_bytes: &[u8],
+    image: &FlashImage,
+    config: Option<Config>,
+    mut install_status: Signal<Option<InstallStatus>, SyncStorage>,
+) -> Result<(), String> {
+    install_status.set(Some(InstallStatus::Running(FlashProgress::stage("Opening keyboard..."))));
+    let device = find_single_device(&DEFAULT_DEVICE_IDS)
+        .map_err(|e| format!("Failed to open keyboard: {}", e))?;
+    let transport = UsbTransport::open(&device)
+        .map_err(|e| format!("Failed to open keyboard: {}", e))?;
+    let mut flasher = Flasher::new(transport).with_observer(move |progress| {
+        install_status.set(Some(InstallStatus::Running(progress.clone())));
+    });
+    let result = flasher.flash(image).map_err(|e| format!("Failed to flash firmware: {}", e));
+
+    let entry = HistoryEntry::new(&device.identity(), config, original_binary, image_bytes, &result);
+    if let Err(e) = record_flash(&entry, image_bytes) {
+        eprintln!("{}", e);
+    }
+    result
+}
+
+/// Watch for a keyboard being plugged in, in a background thread. Whenever
+/// another single keyboard shows up, its board is inferred from its flash
+/// history and written to `connected_board`. Paused while installing.
+pub fn watch_connected_board(
+    original_binary: Vec<u8>,
+    general_setting: Arc<GeneralSeitting>,
+    install_status: Signal<Option<InstallStatus>, SyncStorage>,
+    mut connected_board: Signal<Option<BoardMatch>, SyncStorage>,
+) {
+    std::thread::spawn(move || {
+        let mut connected: Option<String> = None;
+        loop {
+            if !matches!(*install_status.peek(), Some(InstallStatus::Running(_)))
+                && let Ok(devices) = list_keyboards()
+            {
+                let identity = match &devices[..] {
+                    [device] => Some(device.identity()),
+                    _ => None,
+                };
+                if let Some(identity) = identity.as_ref().filter(|identity| connected.as_ref() != Some(*identity)) {
+                    match catch_panic(|| infer_board_from_history(&original_binary, identity, None, &general_setting)) {
+                        Ok(Some(found)) => connected_board.set(Some(found)),
+                        Ok(None) => {}
+                        Err(e) => eprintln!("Failed to identify the connected keyboard: {}", e),
+                    }
+                }
+                connected = identity;
+            }
+            std::thread::sleep(KEYBOARD_POLL_INTERVAL);
+        }
+    });
+}
+
+/// Connected keyboards that can be flashed.
+pub fn list_keyboards() -> Result<Vec<UsbDeviceInfo>, String> {
+    find_devices(&DEFAULT_DEVICE_IDS).map_err(|e| format!("Failed to list keyboards: {}", e))
+}
+
+/// Build once and flash the image to several keyboards in a background thread.
+/// With `devices` set to `None`, every matching keyboard found at start is flashed.
+/// The summary report is written to `batch_report.txt` in the build directory at the end.
+pub fn start_batch_install(
     firmware_future: Resource<Vec<u8>>,
-    fn_id: Signal<u8>,
-    tp_sensitivity: Signal<u32>,
-    macro_key_map: Signal<BTreeMap<u8, MacroKey>>,
-    media_key_map: Signal<BTreeMap<u8, u16>>,
-    enable_middle_click: Signal<bool>,
-    selected_board: ReadSignal<Board>,
-    error_msg: &mut Signal<Option<String>>,    
+    settings: BuildSettings,
+    devices: Option<Vec<UsbDeviceInfo>>,
+    mut batch_status: Signal<Option<BatchStatus>, SyncStorage>,
+) -> Result<(), String> {
+    if matches!(batch_status(), Some(BatchStatus { finished: false, .. })) {
+        return Err("A batch installation is already running.".into());
+    }
+    if let Some(msg) = validate_mod_key_position(&settings.layout0, &settings.layout1) {
+        return Err(msg);
+    }
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+    let devices = match devices {
+        Some(devices) => devices,
+        None => list_keyboards()?,
+    };
+    if devices.is_empty() {
+        return Err("No keyboard selected.".into());
+    }
+
+    batch_status.set(Some(BatchStatus::stage("Starting...")));
+    std::thread::spawn(move || {
+        let built = catch_panic(|| {
+            let built = build_flash_image(&original_binary, &settings, &mut |stage| {
+                batch_status.set(Some(BatchStatus::stage(stage)));
+            })?;
+            let notes = built.notes.clone();
+            let mut status = flash_batch(&devices, &built.image, UsbTransport::open, move |status| {
+                batch_status.set(Some(BatchStatus { build_notes: notes.clone(), ..status.clone() }));
+            });
+            status.build_notes = built.notes;
+            record_batch(&status, &devices, &original_binary, &built.bytes, &settings.to_config());
+            Ok(status)
+        });
+        let mut status = built.unwrap_or_else(|err| BatchStatus {
+            stage: "Build failed.".into(),
+            build_error: Some(err),
+            finished: true,
+            ..BatchStatus::default()
+        });
+        let report_path = resources().build_dir().join(BATCH_REPORT).to_string_lossy().into_owned();
+        match fs::write(&report_path, status.report()) {
+            Ok(()) => status.report_path = Some(report_path),
+            Err(e) => eprintln!("Failed to write {}: {}", report_path, e),
+        }
+        batch_status.set(Some(status));
+    });
+    Ok(())
+}
+
+fn record_batch(
+    status: &BatchStatus,
+    devices: &[UsbDeviceInfo],
+    original_binary: &[u8],
+    image_bytes: &[u8],
+    config: &Config,
 ) {
-    if let Some(msg) = validate_mod_key_position(id_layout_l0, id_layout_l1) {
-        error_msg.set(Some(msg));
-        return;
-    }
-
-    let _r = build_mod_fw(
-        firmware_future, 
-        id_layout_l0, 
-        id_layout_l1, 
-        fn_id, 
-        tp_sensitivity, 
-        macro_key_map, 
-        media_key_map, 
-        enable_middle_click,
-        selected_board,
-    ).unwrap_or_else(|err| {
-        error_msg.set(Some(format!("Failed to build modified firmware: {}", err)));
-        return;
+    for (entry, device) in status.entries.iter().zip(devices) {
+        let result = match &entry.outcome {
+            DeviceOutcome::Verified { .. } => Ok(()),
+            DeviceOutcome::Failed(err) => Err(err.clone()),
+            _ => continue,
+        };
+        let history = HistoryEntry::new(&device.identity(), Some(config.clone()), original_binary, image_bytes, &result);
+        if let Err(e) = record_flash(&history, image_bytes) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Build the modified firmware and simulate flashing it in a background thread,
+/// like [`start_firmware_install`]. The transcript of control transfers the real
+/// flash would perform is written to `transcript_path`.
+pub fn start_dry_run(
+    firmware_future: Resource<Vec<u8>>,
+    settings: BuildSettings,
+    transcript_path: PathBuf,
+    mut install_status: Signal<Option<InstallStatus>, SyncStorage>,
+) -> Result<(), String> {
+    if let Some(msg) = validate_mod_key_position(&settings.layout0, &settings.layout1) {
+        return Err(msg);
+    }
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+
+    spawn_install(install_status, "Dry run finished; nothing was sent to the keyboard.", move || {
+        let built = build_flash_image(&original_binary, &settings, &mut |stage| {
+            install_status.set(Some(InstallStatus::Running(FlashProgress::stage(stage))));
+        })?;
+        install_status.set(Some(InstallStatus::Running(FlashProgress::stage("Simulating flash..."))));
+        let transcript = dry_run(&built.image).map_err(|e| format!("Dry run failed: {}", e))?;
+        let notes: String = built.notes.iter().map(|note| format!("Note: {}\n", note)).collect();
+        fs::write(&transcript_path, notes + &transcript.to_text())
+            .map_err(|e| format!("Failed to save transcript: {}", e))?;
+        let mut notes = built.notes;
+        notes.push(format!("Transcript saved to {}.", transcript_path.display()));
+        Ok(notes)
+    })
+}
+
+/// State of the recovery dialog, written by its background thread.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecoveryStatus {
+    Running(String),
+    /// `message` describes `state` and the matching flash history entry;
+    /// `board` is the keyboard found from that entry
+    Detected { state: DeviceState, message: String, board: Option<BoardMatch> },
+    Finished(String),
+    Failed(String),
+}
+
+fn spawn_recovery_job(
+    firmware_future: Resource<Vec<u8>>,
+    mut recovery_status: Signal<Option<RecoveryStatus>, SyncStorage>,
+    caption: &str,
+    job: impl FnOnce(&[u8]) -> Result<RecoveryStatus, String> + Send + 'static,
+) -> Result<(), String> {
+    if let Some(RecoveryStatus::Running(_)) = recovery_status() {
+        return Err("The keyboard is busy.".into());
+    }
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+    recovery_status.set(Some(RecoveryStatus::Running(caption.into())));
+    std::thread::spawn(move || {
+        recovery_status.set(Some(catch_panic(|| job(&original_binary)).unwrap_or_else(RecoveryStatus::Failed)));
     });
+    Ok(())
+}
+
+/// Probe the connected keyboard for the recovery dialog in a background thread.
+/// Does not depend on any user configuration and leaves the keyboard as it is.
+pub fn start_keyboard_detection(
+    firmware_future: Resource<Vec<u8>>,
+    general_setting: Arc<GeneralSeitting>,
+    recovery_status: Signal<Option<RecoveryStatus>, SyncStorage>,
+) -> Result<(), String> {
+    spawn_recovery_job(firmware_future, recovery_status, "Detecting keyboard...", move |original_binary| {
+        let (state, identity) = detect_usb_device_state(original_binary)?;
+        let mut message = state.to_string();
+        let checksum = match state {
+            DeviceState::Keyboard => None,
+            DeviceState::Flasher { checksum, .. } => checksum,
+        };
+        let found = match checksum {
+            Some(checksum) => find_flashed_firmware(checksum, &identity)?,
+            None => Vec::new(),
+        };
+        if let Some((entry, fingerprint)) = found.first() {
+            message += &format!(" Matches the firmware flashed on {}", entry.timestamp);
+            if let Some(fingerprint) = fingerprint {
+                message += &format!(" ({})", fingerprint);
+            }
+            message += ".";
+            if found.iter().any(|(other, _)| other.image_hash != entry.image_hash) {
+                message += " Other recorded images have the same checksum, so the match is not certain.";
+            }
+        }
+        let board = match infer_board_from_history(original_binary, &identity, checksum, &general_setting) {
+            Ok(found) => found,
+            Err(e) => {
+                message += &format!(" The keyboard could not be identified: {}", e);
+                None
+            }
+        };
+        if let Some(found) = &board {
+            message += &format!(" Detected keyboard: {}.", found);
+        }
+        Ok(RecoveryStatus::Detected { state, message, board })
+    })
+}
 
-    run_flashsn8_gui(MOD_BIN_PATH, ORG_BIN_PATH).unwrap_or_else(|err| {
-        error_msg.set(Some(format!("Failed to launch flashsn8: {}", err)));
-        return;
+/// Restore the stock firmware onto a keyboard stuck in flasher mode, in a
+/// background thread.
+pub fn start_keyboard_recovery(
+    firmware_future: Resource<Vec<u8>>,
+    recovery_status: Signal<Option<RecoveryStatus>, SyncStorage>,
+) -> Result<(), String> {
+    spawn_recovery_job(firmware_future, recovery_status, "Restoring stock firmware...", |original_binary| {
+        recover_usb_device(original_binary).map(RecoveryStatus::Finished)
+    })
+}
+
+/// Recover the config of a firmware binary or installer built by this app, or of
+/// the stock firmware, by locating the template placeholders in `image_bytes`.
+pub fn decompile_firmware(
+    original_binary: &[u8],
+    image_bytes: &[u8],
+    general_setting: &GeneralSeitting,
+) -> Result<DecompiledFirmware, String> {
+    let image = firmware_image(image_bytes)?;
+    let dir = BuildDir::new()?;
+    prepare_template(&dir, original_binary, &mut |_| {})?;
+    let (tmp_asm, org_bin) = (dir.file(TMP_ASM), dir.file(ORG_BIN));
+    let template = fs::read_to_string(&tmp_asm)
+        .map_err(|e| format!("Failed to read {}: {}", tmp_asm, e))?;
+    let stock = fs::read(&org_bin)
+        .map_err(|e| format!("Failed to read {}: {}", org_bin, e))?;
+    let (probe_asm, probe_bin) = (dir.file(PROBE_ASM), dir.file(PROBE_BIN));
+    let mut assemble = |source: &str| {
+        let _ = fs::remove_file(&probe_bin);
+        fs::write(&probe_asm, source)
+            .map_err(|e| format!("Failed to write {}: {}", probe_asm, e))?;
+        run_assn8(&probe_asm, &probe_bin)
+            .map_err(|e| format!("assn8 failed: {}", e))?;
+        fs::read(&probe_bin)
+            .map_err(|e| format!("Failed to read {}: {}", probe_bin, e))
+    };
+    decompile_image(&template, &stock, &image, general_setting, &mut assemble)
+}
+
+/// State of a background decompilation, for the firmware file it was started on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecompileStatus {
+    Running(PathBuf),
+    Finished(PathBuf, Box<DecompiledFirmware>),
+    Failed(String),
+}
+
+/// Decompile the firmware file at `path` in a background thread, see
+/// [`decompile_firmware`]. The result is written to `decompile_status`.
+pub fn start_decompile(
+    firmware_future: Resource<Vec<u8>>,
+    path: PathBuf,
+    general_setting: Arc<GeneralSeitting>,
+    mut decompile_status: Signal<Option<DecompileStatus>, SyncStorage>,
+) -> Result<(), String> {
+    if let Some(DecompileStatus::Running(_)) = decompile_status() {
+        return Err("A firmware is already being decompiled.".into());
+    }
+    let Some(original_binary) = firmware_future.read_unchecked().clone() else {
+        return Err("Firmware binary not loaded.".into());
+    };
+    decompile_status.set(Some(DecompileStatus::Running(path.clone())));
+    std::thread::spawn(move || {
+        let result = catch_panic(|| {
+            let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+            decompile_firmware(&original_binary, &bytes, &general_setting)
+        });
+        decompile_status.set(Some(match result {
+            Ok(decompiled) => DecompileStatus::Finished(path, Box::new(decompiled)),
+            Err(e) => DecompileStatus::Failed(format!("Failed to decompile {}: {}", path.display(), e)),
+        }));
     });
+    Ok(())
+}
+
+/// Downloaded installer, in the build directory or, from older versions of
+/// the app, in the bundled `firmware/` directory.
+fn original_installer_path() -> PathBuf {
+    let path = resources().build_dir().join(ORG_INSTALLER);
+    if path.exists() {
+        return path;
+    }
+    let legacy = resources().find(&format!("{}/{}", BUILD_DIR, ORG_INSTALLER));
+    if legacy.exists() { legacy } else { path }
+}
 
+/// Installer downloaded by an earlier start of the app.
+pub fn load_original_installer() -> Result<Vec<u8>, String> {
+    let firmware_path = original_installer_path();
+    fs::read(&firmware_path).map_err(|e| {
+        format!("Failed to read {}: {}. Start the app once to download it.", firmware_path.display(), e)
+    })
 }
 
 pub async fn load_or_download_firmware(exe_url_cloned: &str) -> Vec<u8>  {
-    let firmware_path = Path::new(ORG_INSTALLER_PATH);
+    let firmware_path = original_installer_path();
     if firmware_path.exists() {
-        println!("Firmware found at {}. Loading from disk...", ORG_INSTALLER_PATH);
+        println!("Firmware found at {}. Loading from disk...", firmware_path.display());
         return fs::read(firmware_path).unwrap_or_else(|err| {
             eprintln!("Error reading firmware: {}", err);
             vec![]
@@ -137,12 +677,12 @@ pub async fn load_or_download_firmware(exe_url_cloned: &str) -> Vec<u8>  {
     match reqwest::get(exe_url_cloned).await {
         Ok(resp) => match resp.bytes().await {
             Ok(bytes) => {
-                if let Err(err) = fs::File::create(firmware_path)
+                if let Err(err) = fs::File::create(&firmware_path)
                     .and_then(|mut file| file.write_all(&bytes))
                 {
-                    eprintln!("Failed to save firmware to {}: {}", ORG_INSTALLER_PATH, err);
+                    eprintln!("Failed to save firmware to {}: {}", firmware_path.display(), err);
                 } else {
-                    println!("Firmware downloaded and saved to {}", ORG_INSTALLER_PATH);
+                    println!("Firmware downloaded and saved to {}", firmware_path.display());
                 }
                 bytes.to_vec()
             }
@@ -231,16 +771,39 @@ fn modify_asm_file(
     e_choices.insert("mclick".to_string(), if enable_middle_click {1} else {0});
 
     // Set keymask
+    for (i, km) in board_keymask(selected_board).into_iter().enumerate() {
+        s_values.insert(format!("km_{:01x}", i), format!("00{:02x}", km));
+    }
+
+    let _r = render_template_file(in_path, out_path, &s_values, &e_choices)?;
+    Ok(())
+}
+
+/// Populated columns of each matrix row, as written to the firmware key mask.
+pub fn board_keymask(board: &Board) -> [u8; 16] {
     let mut kms: [u8; 16] = [0; 16];
-    for &addr in selected_board.map_address.iter().flatten().flatten() {
+    for &addr in board.map_address.iter().flatten().flatten() {
         let row = (addr >> 4) as usize;
         let col = (addr & 0x07) as u32;
         kms[row] |= 1u8 << col;
     }
-    for (i, km) in kms.into_iter().enumerate() {
-        s_values.insert(format!("km_{:01x}", i), format!("00{:02x}", km));
-    }
+    kms
+}
 
-    let _r = render_template_file(in_path, out_path, &s_values, &e_choices)?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_get_their_own_build_dir_and_report_panics() {
+        let (a, b) = (BuildDir::new().unwrap(), BuildDir::new().unwrap());
+        assert_ne!(a.file(MOD_BIN), b.file(MOD_BIN));
+        let path = a.0.path().to_path_buf();
+        drop(a);
+        assert!(!path.exists());
+
+        assert_eq!(catch_panic(|| Ok(1)), Ok(1));
+        let result: Result<(), String> = catch_panic(|| panic!("broken template"));
+        assert_eq!(result, Err("Internal error: broken template".into()));
+    }
 }
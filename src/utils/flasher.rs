@@ -0,0 +1,417 @@
+use std::fmt;
+use std::time::Duration;
+
+// Flash geometry (in 16-bit words unless noted), see sn8/flashsn8_gui.py
+pub const ERASE_BLOCK_LENGTH_WORDS: u16 = 0x80;
+pub const IMAGE_LENGTH: usize = 0x3000 * 2;
+pub const SN8_HEADER_LENGTH: usize = 0x100;
+pub const CANARY_ADDRESS_WORDS: u16 = 0x27ff;
+pub const CANARY: [u8; 2] = [0xaa, 0xaa];
+pub const FLASHER_BASE_ADDRESS_WORDS: u16 = 0x2800;
+pub const UNPROGRAMABLE_PREFIX_WORDS: u16 = 8;
+pub const CODE_OPTIONS_ADDRESS_WORDS: u16 = 0x2ffc;
+pub const FIRST_8_WORDS_CHECKSUM: u16 = 0x80 + 0xa8;
+pub const ALL_ERASED_EXPECTED_CHECKSUM: u16 = 0xa138;
+pub const ERASABLE_PAGE_COUNT: u16 = FLASHER_BASE_ADDRESS_WORDS / ERASE_BLOCK_LENGTH_WORDS;
+
+// Bytes 0x0000..=0x011E must never differ from the original firmware
+const SAFE_PREFIX_LENGTH: usize = 0x11f;
+
+// HID class requests used by the flasher
+pub const REQUEST_GET_REPORT: u8 = 0x01;
+pub const REQUEST_SET_REPORT: u8 = 0x09;
+pub const REPORT_VALUE: u16 = 0x0300;
+pub const REPORT_INDEX: u16 = 0x0000;
+pub const REPORT_LENGTH: usize = 8;
+
+pub const DEFAULT_DEVICE_IDS: [(u16, u16); 2] = [(0x0c45, 0x7500), (0x17ef, 0x6047)];
+
+const ERASE_SETTLE_TIME: Duration = Duration::from_millis(2500);
+const PACKET_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_TIMEOUT_RETRIES: u32 = 100;
+const PROGRESS_PACKET_INTERVAL: usize = 32;
+
+/// Error type for flashing
+#[derive(Debug)]
+pub enum FlashError {
+    InvalidImage(String),
+    UnexpectedResponse(String),
+    Timeout,
+    Disconnected,
+    Usb(String),
+    UnlockFailed,
+    CodeOptionMismatch,
+    ChecksumMismatch { stage: &'static str, expected: u16, actual: u16 },
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashError::InvalidImage(msg) => write!(f, "Invalid firmware image: {}", msg),
+            FlashError::UnexpectedResponse(dump) => write!(f, "Unexpected response from device: {}", dump),
+            FlashError::Timeout => write!(f, "USB transfer timed out"),
+            FlashError::Disconnected => write!(f, "Device was disconnected"),
+            FlashError::Usb(msg) => write!(f, "USB error: {}", msg),
+            FlashError::UnlockFailed => write!(f, "Failed to unlock flash"),
+            FlashError::CodeOptionMismatch => write!(f, "Code option mismatch between flash and image"),
+            FlashError::ChecksumMismatch { stage, expected, actual } => write!(
+                f,
+                "Post-{} checksum mismatch: expected {:#06x}, got {:#06x}",
+                stage, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FlashError {}
+
+/// Low-level access to the keyboard's control endpoint.
+///
+/// `sleep` and `note` exist so that a non-hardware transport can record
+/// delays and stage captions instead of acting on them.
+pub trait Transport {
+    fn control_write(&mut self, request: u8, value: u16, index: u16, data: &[u8]) -> Result<(), FlashError>;
+    fn control_read(&mut self, request: u8, value: u16, index: u16, length: usize) -> Result<Vec<u8>, FlashError>;
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+    fn note(&mut self, _text: &str) {}
+}
+
+pub fn hexdump(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Programmable part of a firmware image plus its code options.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashImage {
+    /// Bytes from word 0x0008 up to (excluding) the flasher at word 0x2800
+    pub program: Vec<u8>,
+    /// Words 0x2ffc..=0x2fff
+    pub code_options: [u8; 8],
+}
+
+impl FlashImage {
+    /// Accept a plain image (0x6000 bytes) or an SN8 image with a 0x100 byte header.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FlashImage, FlashError> {
+        let image = match bytes.len() {
+            IMAGE_LENGTH => bytes,
+            len if len == IMAGE_LENGTH + SN8_HEADER_LENGTH => &bytes[SN8_HEADER_LENGTH..],
+            len => {
+                return Err(FlashError::InvalidImage(format!(
+                    "{} bytes, expected {:#x} or {:#x}",
+                    len,
+                    IMAGE_LENGTH,
+                    IMAGE_LENGTH + SN8_HEADER_LENGTH
+                )))
+            }
+        };
+
+        let options_start = CODE_OPTIONS_ADDRESS_WORDS as usize * 2;
+        let mut code_options = [0u8; 8];
+        code_options.copy_from_slice(&image[options_start..options_start + 8]);
+
+        let program_start = UNPROGRAMABLE_PREFIX_WORDS as usize * 2;
+        let program_end = FLASHER_BASE_ADDRESS_WORDS as usize * 2;
+        let canary_start = CANARY_ADDRESS_WORDS as usize * 2;
+        if image[canary_start..program_end] != CANARY {
+            return Err(FlashError::InvalidImage(format!(
+                "canary missing. Add \".ORG {:#06x} DW {:#06x}\" to source and rebuild",
+                CANARY_ADDRESS_WORDS,
+                u16::from_le_bytes(CANARY)
+            )));
+        }
+
+        Ok(FlashImage {
+            program: image[program_start..program_end].to_vec(),
+            code_options,
+        })
+    }
+
+    /// Checksum the flasher reports once this image has been programmed.
+    pub fn expected_checksum(&self) -> u16 {
+        self.program
+            .iter()
+            .fold(FIRST_8_WORDS_CHECKSUM, |acc, b| acc.wrapping_add(*b as u16))
+    }
+}
+
+/// Port of the safety check done by flashsn8_gui.py before enabling "Start":
+/// the target must have the original's size and identical bytes 0x0000..=0x011E.
+pub fn check_against_original(target: &[u8], original: &[u8]) -> Result<(), FlashError> {
+    if target.len() != original.len() {
+        return Err(FlashError::InvalidImage(format!(
+            "size mismatch (original={}, target={})",
+            original.len(),
+            target.len()
+        )));
+    }
+    if target.len() < SAFE_PREFIX_LENGTH {
+        return Err(FlashError::InvalidImage("image too short for prefix comparison".into()));
+    }
+    if target[..SAFE_PREFIX_LENGTH] != original[..SAFE_PREFIX_LENGTH] {
+        return Err(FlashError::InvalidImage(format!(
+            "prefix region (0x0000-{:#06x}) differs from the original firmware",
+            SAFE_PREFIX_LENGTH - 1
+        )));
+    }
+    Ok(())
+}
+
+/// Snapshot of a running flash, published to the observer after every step.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlashProgress {
+    pub stage: String,
+    pub erased_bytes: usize,
+    pub erase_total_bytes: usize,
+    pub programmed_bytes: usize,
+    pub program_total_bytes: usize,
+    pub timeout_retries: u32,
+    /// Set from the first erase until the keyboard is told to reboot
+    pub do_not_unplug: bool,
+}
+
+impl FlashProgress {
+    pub fn stage(caption: &str) -> FlashProgress {
+        FlashProgress { stage: caption.to_string(), ..FlashProgress::default() }
+    }
+}
+
+type ProgressObserver = Box<dyn FnMut(&FlashProgress)>;
+
+/// SN8 flasher protocol on top of a [`Transport`].
+pub struct Flasher<T: Transport> {
+    transport: T,
+    progress: FlashProgress,
+    observer: Option<ProgressObserver>,
+}
+
+impl<T: Transport> Flasher<T> {
+    pub fn new(transport: T) -> Flasher<T> {
+        Flasher { transport, progress: FlashProgress::default(), observer: None }
+    }
+
+    /// Call `observer` whenever the progress snapshot changes.
+    pub fn with_observer(mut self, observer: impl FnMut(&FlashProgress) + 'static) -> Flasher<T> {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn publish(&mut self) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer(&self.progress);
+        }
+    }
+
+    fn stage(&mut self, caption: &str) {
+        println!("{}", caption);
+        self.transport.note(caption);
+        self.progress.stage = caption.to_string();
+        self.publish();
+    }
+
+    fn send(&mut self, data: [u8; REPORT_LENGTH]) -> Result<(), FlashError> {
+        self.transport.control_write(REQUEST_SET_REPORT, REPORT_VALUE, REPORT_INDEX, &data)
+    }
+
+    fn recv(&mut self, expected: &[u8]) -> Result<Vec<u8>, FlashError> {
+        let result = self
+            .transport
+            .control_read(REQUEST_GET_REPORT, REPORT_VALUE, REPORT_INDEX, REPORT_LENGTH)?;
+        if !result.starts_with(expected) || result.len() != REPORT_LENGTH {
+            return Err(FlashError::UnexpectedResponse(hexdump(&result)));
+        }
+        Ok(result)
+    }
+
+    pub fn switch_to_flasher(&mut self) -> Result<(), FlashError> {
+        self.stage("Switching to flasher...");
+        self.send([0xaa, 0x55, 0xa5, 0x5a, 0xff, 0x00, 0x33, 0xcc])
+    }
+
+    pub fn unlock_flash(&mut self) -> Result<(), FlashError> {
+        self.stage("Unlocking flash...");
+        self.send([0x01, 0xaa, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+        self.recv(&[0x01, 0xaa, 0x55, 0x00, 0x00, 0x03, 0x00, 0x00])?;
+        self.send([0x02, 0xaa, 0x55, 0x00, 0x12, 0x34, 0x56, 0x78])?;
+        self.recv(&[0x02, 0xaa, 0x55, 0x00, 0xfa, 0xfa, 0xfa, 0xfa])?;
+        Ok(())
+    }
+
+    pub fn get_flash_unlock_state(&mut self) -> Result<bool, FlashError> {
+        self.stage("Getting flash lock state...");
+        self.send([0x03, 0xaa, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+        let result = self.recv(&[0x03, 0xaa, 0x55, 0x00])?;
+        Ok(result[4..] == [0xfa; 4])
+    }
+
+    pub fn erase(&mut self, base_address_words: u16, page_count: u16) -> Result<(), FlashError> {
+        if page_count < 1 || base_address_words & (ERASE_BLOCK_LENGTH_WORDS - 1) != 0 {
+            return Err(FlashError::InvalidImage(format!(
+                "bad erase request: base {:#06x}, {} pages",
+                base_address_words, page_count
+            )));
+        }
+        let last_erased_address_words =
+            base_address_words as u32 + page_count as u32 * ERASE_BLOCK_LENGTH_WORDS as u32 - 1;
+        // Flasher does not protect itself, do it instead.
+        if last_erased_address_words >= FLASHER_BASE_ADDRESS_WORDS as u32 {
+            return Err(FlashError::InvalidImage("refusing to erase flasher program".into()));
+        }
+        let erase_bytes = page_count as usize * ERASE_BLOCK_LENGTH_WORDS as usize * 2;
+        self.progress.do_not_unplug = true;
+        self.progress.erased_bytes = 0;
+        self.progress.erase_total_bytes = erase_bytes;
+        self.stage(&format!(
+            "Erasing {:#06x} to {:#06x} ({} pages)...",
+            base_address_words, last_erased_address_words, page_count
+        ));
+        let [b0, b1] = base_address_words.to_le_bytes();
+        let [c0, c1] = page_count.to_le_bytes();
+        self.send([0x04, 0xaa, 0x55, 0x00, b0, b1, c0, c1])?;
+        self.progress.erased_bytes = erase_bytes;
+        self.publish();
+        Ok(())
+    }
+
+    pub fn get_checksum(&mut self) -> Result<u16, FlashError> {
+        self.stage("Getting 0x0000 to 0x27ff checksum...");
+        self.send([0x06, 0xaa, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+        let result = self.recv(&[0x06, 0xaa, 0x55, 0x00, 0xfa, 0xfa])?;
+        Ok(u16::from_le_bytes([result[6], result[7]]))
+    }
+
+    pub fn get_code_options(&mut self) -> Result<[u8; 8], FlashError> {
+        self.stage("Retrieving code options...");
+        let mut options = [0u8; 8];
+        for half in 0..2u8 {
+            self.send([0x09, 0xaa, 0x55, half, 0x00, 0x00, 0x00, 0x00])?;
+            let result = self.recv(&[0x09, 0xaa, 0x55, half])?;
+            let start = half as usize * 4;
+            options[start..start + 4].copy_from_slice(&result[4..]);
+        }
+        Ok(options)
+    }
+
+    pub fn reboot(&mut self) -> Result<(), FlashError> {
+        self.stage("Asking device to reboot...");
+        // There is (should be) no answer
+        self.send([0x07, 0xaa, 0x55, 0x00, 0x00, 0x00, 0x00, 0x00])?;
+        self.progress.do_not_unplug = false;
+        self.publish();
+        Ok(())
+    }
+
+    pub fn program(&mut self, base_address_words: u16, data: &[u8]) -> Result<(), FlashError> {
+        // Flasher always flashes 4 words / 8 bytes per packet.
+        if !data.len().is_multiple_of(REPORT_LENGTH) {
+            return Err(FlashError::InvalidImage("data length must be a multiple of 8".into()));
+        }
+        let packet_count = (data.len() / REPORT_LENGTH) as u16;
+        let last_programmed_address_words = base_address_words as usize + data.len() / 2 - 1;
+        self.progress.do_not_unplug = true;
+        self.progress.programmed_bytes = 0;
+        self.progress.program_total_bytes = data.len();
+        self.stage(&format!(
+            "Programming {:#06x} to {:#06x} ({} packets)...",
+            base_address_words, last_programmed_address_words, packet_count
+        ));
+        let [b0, b1] = base_address_words.to_le_bytes();
+        let [c0, c1] = packet_count.to_le_bytes();
+        self.send([0x05, 0xaa, 0x55, 0x00, b0, b1, c0, c1])?;
+        self.recv(&[0x05, 0xaa, 0x55, 0x00, 0xfa, 0xfa, 0xfa, 0xfa])?;
+
+        for (i, chunk) in data.chunks_exact(REPORT_LENGTH).enumerate() {
+            let mut packet = [0u8; REPORT_LENGTH];
+            packet.copy_from_slice(chunk);
+            let mut retries = 0;
+            loop {
+                match self.send(packet) {
+                    Ok(()) => break,
+                    Err(FlashError::Timeout) if retries < MAX_TIMEOUT_RETRIES => {
+                        retries += 1;
+                        self.progress.timeout_retries += 1;
+                        println!("Timed out, retrying");
+                        self.publish();
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            // Firmware seems to clear the USB interrupt late, causing the next
+            // packet to be lost. 1ms is cheaper than triggering timeouts.
+            self.transport.sleep(PACKET_INTERVAL);
+            self.progress.programmed_bytes += REPORT_LENGTH;
+            if (i + 1) % PROGRESS_PACKET_INTERVAL == 0 || self.progress.programmed_bytes == data.len() {
+                self.publish();
+            }
+        }
+        Ok(())
+    }
+
+    fn get_checksum_retrying(&mut self) -> Result<u16, FlashError> {
+        let mut retries = 0;
+        loop {
+            match self.get_checksum() {
+                Err(FlashError::Timeout) if retries < MAX_TIMEOUT_RETRIES => {
+                    retries += 1;
+                    self.progress.timeout_retries += 1;
+                    self.publish();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Full flashing sequence, equivalent to `FlashWorker.run` once the device is open.
+    pub fn flash(&mut self, image: &FlashImage) -> Result<(), FlashError> {
+        let unlocked = match self.get_flash_unlock_state() {
+            Ok(state) => state,
+            Err(FlashError::UnexpectedResponse(_)) => {
+                self.stage("Not in flasher mode. Switching...");
+                self.switch_to_flasher()?;
+                self.get_flash_unlock_state()?
+            }
+            Err(e) => return Err(e),
+        };
+
+        if !unlocked {
+            self.unlock_flash()?;
+            if !self.get_flash_unlock_state()? {
+                return Err(FlashError::UnlockFailed);
+            }
+        }
+
+        if self.get_code_options()? != image.code_options {
+            return Err(FlashError::CodeOptionMismatch);
+        }
+
+        self.erase(0, ERASABLE_PAGE_COUNT)?;
+        self.transport.sleep(ERASE_SETTLE_TIME);
+
+        let erased_checksum = self.get_checksum_retrying()?;
+        if erased_checksum != ALL_ERASED_EXPECTED_CHECKSUM {
+            return Err(FlashError::ChecksumMismatch {
+                stage: "erase",
+                expected: ALL_ERASED_EXPECTED_CHECKSUM,
+                actual: erased_checksum,
+            });
+        }
+
+        self.program(UNPROGRAMABLE_PREFIX_WORDS, &image.program)?;
+
+        let checksum = self.get_checksum()?;
+        let expected = image.expected_checksum();
+        if checksum != expected {
+            self.stage("Checksum mismatch after programming. Re-erasing...");
+            self.erase(0, ERASABLE_PAGE_COUNT)?;
+            return Err(FlashError::ChecksumMismatch { stage: "program", expected, actual: checksum });
+        }
+
+        self.stage("Success!");
+        self.reboot()
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::{Config, resources};
+use crate::utils::fingerprint::{Fingerprint, read_fingerprint};
+use crate::utils::flasher::FlashImage;
+
+// In the user data directory
+const HISTORY_PATH: &str = "history.jsonl";
+const HISTORY_IMAGE_DIR: &str = "history";
+
+/// One line of the flash journal.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct HistoryEntry {
+    pub timestamp: String,
+    pub device: String,
+    /// `None` when the stock firmware was flashed
+    pub config: Option<Config>,
+    pub config_hash: Option<String>,
+    pub installer_hash: String,
+    pub image_hash: String,
+    pub verified: bool,
+    pub result: String,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        device: &str,
+        config: Option<Config>,
+        installer: &[u8],
+        image_bytes: &[u8],
+        result: &Result<(), String>,
+    ) -> HistoryEntry {
+        let config_hash = config.as_ref().map(config_hash);
+        HistoryEntry {
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            device: device.to_string(),
+            config,
+            config_hash,
+            installer_hash: sha256_hex(installer),
+            image_hash: sha256_hex(image_bytes),
+            verified: result.is_ok(),
+            result: match result {
+                Ok(()) => "Verified".into(),
+                Err(err) => err.clone(),
+            },
+        }
+    }
+
+    pub fn is_stock(&self) -> bool {
+        self.config.is_none()
+    }
+}
+
+/// Hash identifying `config` in the history and in firmware fingerprints.
+pub fn config_hash(config: &Config) -> String {
+    sha256_hex(&serde_json::to_vec(config).unwrap_or_default())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn history_path() -> PathBuf {
+    resources().data_dir.join(HISTORY_PATH)
+}
+
+fn image_path(image_hash: &str) -> PathBuf {
+    resources().user_data_dir(HISTORY_IMAGE_DIR).join(format!("{}.bin", image_hash))
+}
+
+/// Append `entry` to the journal and keep a copy of the flashed image
+/// so that the entry can be re-flashed later without rebuilding.
+pub fn record_flash(entry: &HistoryEntry, image_bytes: &[u8]) -> Result<(), String> {
+    let path = image_path(&entry.image_hash);
+    if !path.exists() {
+        fs::write(&path, image_bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path())
+        .map_err(|e| format!("Failed to open {}: {}", history_path().display(), e))?;
+    writeln!(file, "{}", line).map_err(|e| format!("Failed to write {}: {}", history_path().display(), e))
+}
+
+/// Journal entries, oldest first. Unreadable lines are skipped.
+pub fn load_history() -> Result<Vec<HistoryEntry>, String> {
+    let path = history_path();
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                eprintln!("Skipping history line: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Image stored for `entry`, checked against the recorded hash.
+pub fn load_history_image(entry: &HistoryEntry) -> Result<(Vec<u8>, FlashImage), String> {
+    let path = image_path(&entry.image_hash);
+    let bytes = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if sha256_hex(&bytes) != entry.image_hash {
+        return Err(format!("{} does not match the recorded image hash", path.display()));
+    }
+    let image = FlashImage::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    Ok((bytes, image))
+}
+
+/// Flash checksum and embedded fingerprint of the image stored for `entry`.
+pub fn history_image_identity(entry: &HistoryEntry) -> Result<(u16, Option<Fingerprint>), String> {
+    let (bytes, image) = load_history_image(entry)?;
+    Ok((image.expected_checksum(), read_fingerprint(&bytes)))
+}
+
+/// Recorded flashes whose image has the flash `checksum`, newest first.
+pub fn find_history_by_checksum(checksum: u16) -> Result<Vec<(HistoryEntry, Option<Fingerprint>)>, String> {
+    Ok(load_history()?
+        .into_iter()
+        .rev()
+        .filter_map(|entry| match history_image_identity(&entry) {
+            Ok((found, fingerprint)) if found == checksum => Some((entry, fingerprint)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Recorded flashes that may be the firmware on the keyboard `device`, whose
+/// flash reads `checksum`: its own flashes first, then the others, newest first.
+/// The checksum is only 16 bits, so an image whose embedded fingerprint names
+/// another config than the one recorded with it is not counted as a match.
+pub fn find_flashed_firmware(checksum: u16, device: &str) -> Result<Vec<(HistoryEntry, Option<Fingerprint>)>, String> {
+    let mut found: Vec<_> = find_history_by_checksum(checksum)?
+        .into_iter()
+        .filter(|(entry, fingerprint)| {
+            fingerprint.as_ref().is_none_or(|fingerprint| {
+                entry.config_hash.as_deref().is_some_and(|hash| fingerprint.matches_config_hash(hash))
+            })
+        })
+        .collect();
+    found.sort_by_key(|(entry, _)| entry.device != device);
+    Ok(found)
+}
+
+/// Verified flashes of the keyboard with `identity`, newest first.
+pub fn find_history_by_device(identity: &str) -> Result<Vec<HistoryEntry>, String> {
+    Ok(load_history()?
+        .into_iter()
+        .rev()
+        .filter(|entry| entry.verified && entry.device == identity)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::GeneralSeitting;
+    use crate::utils::diagnostic_settings;
+    use crate::utils::fingerprint::embed_fingerprint;
+    use crate::utils::flasher::{CANARY, IMAGE_LENGTH};
+
+    fn image(seed: u8) -> Vec<u8> {
+        let mut image = vec![seed; IMAGE_LENGTH];
+        image[0x4ffe..0x5000].copy_from_slice(&CANARY);
+        image
+    }
+
+    #[test]
+    fn records_and_finds_flashes() {
+        let _ = fs::remove_file(history_path());
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let mut settings = diagnostic_settings(&general_setting).unwrap();
+        settings.logical_layout_name = "German".to_string();
+        let config = settings.to_config();
+        assert_eq!(config.logical_layout_name, "German");
+
+        let keyboard = "17ef:6047 SN0001";
+        let (old, new, other) = (image(1), image(2), image(3));
+        let entries = [
+            HistoryEntry::new(keyboard, None, b"installer", &old, &Ok(())),
+            HistoryEntry::new("17ef:6047 SN0002", None, b"installer", &other, &Ok(())),
+            HistoryEntry::new(keyboard, Some(config.clone()), b"installer", &new, &Ok(())),
+            HistoryEntry::new(keyboard, Some(config.clone()), b"installer", &other, &Err("Disconnected".into())),
+        ];
+        for (entry, bytes) in entries.iter().zip([&old, &other, &new, &other]) {
+            record_flash(entry, bytes).unwrap();
+        }
+
+        assert_eq!(load_history().unwrap(), entries);
+        assert_eq!(load_history_image(&entries[2]).unwrap().0, new);
+        assert_eq!(entries[2].config.as_ref().unwrap().logical_layout_name, "German");
+
+        let checksum = FlashImage::from_bytes(&new).unwrap().expected_checksum();
+        let found = find_history_by_checksum(checksum).unwrap();
+        assert_eq!(found, vec![(entries[2].clone(), None)]);
+
+        let found = find_history_by_device(keyboard).unwrap();
+        assert_eq!(found, vec![entries[2].clone(), entries[0].clone()]);
+        assert!(find_history_by_device("17ef:6047").unwrap().is_empty());
+
+        // Same checksum: the same build moved by a byte pair, and an image
+        // recorded with another config than its fingerprint names
+        let mut built = image(4);
+        embed_fingerprint(&mut built, &Fingerprint::new(&config)).unwrap();
+        let moved = |mut bytes: Vec<u8>, at: usize| {
+            bytes[at] += 1;
+            bytes[at + 1] -= 1;
+            bytes
+        };
+        let (theirs, relabelled) = (moved(built.clone(), 0x1000), moved(built.clone(), 0x1002));
+        let mut other_config = config.clone();
+        other_config.logical_layout_name = "US_English".to_string();
+        let entries = [
+            HistoryEntry::new(keyboard, Some(config.clone()), b"installer", &built, &Ok(())),
+            HistoryEntry::new("17ef:6047 SN0002", Some(config.clone()), b"installer", &theirs, &Ok(())),
+            HistoryEntry::new(keyboard, Some(other_config), b"installer", &relabelled, &Ok(())),
+        ];
+        for (entry, bytes) in entries.iter().zip([&built, &theirs, &relabelled]) {
+            record_flash(entry, bytes).unwrap();
+        }
+        let checksum = FlashImage::from_bytes(&built).unwrap().expected_checksum();
+        assert_eq!(find_history_by_checksum(checksum).unwrap().len(), 3);
+        let fingerprint = read_fingerprint(&built);
+        assert_eq!(
+            find_flashed_firmware(checksum, keyboard).unwrap(),
+            vec![(entries[0].clone(), fingerprint.clone()), (entries[1].clone(), fingerprint.clone())]
+        );
+        assert_eq!(find_flashed_firmware(checksum, "17ef:6047 SN0002").unwrap()[0].0, entries[1]);
+    }
+}
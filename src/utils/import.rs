@@ -0,0 +1,701 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::models::{
+    Board, Config, GeneralSeitting, MacroKey, MOD_KEY_ID,
+    default_fn_id, default_tp_sensitivity, default_macro_key_map, default_media_key_map, default_enable_middle_click,
+};
+use crate::utils::config::CONFIG_VERSION;
+use crate::utils::keymap::{Names, macro_key_from};
+
+/// File extensions accepted by [`import_keymap`]
+pub const IMPORT_EXTENSIONS: [&str; 3] = ["c", "json", "vil"];
+
+/// QMK basic keycodes (without `KC_`) other than letters, digits and F-keys, by HID ID
+const QMK_KEYCODES: [(&[&str], u8); 86] = [
+    (&["ENTER", "ENT"], 0x28),
+    (&["ESCAPE", "ESC"], 0x29),
+    (&["BACKSPACE", "BSPC"], 0x2A),
+    (&["TAB"], 0x2B),
+    (&["SPACE", "SPC"], 0x2C),
+    (&["MINUS", "MINS"], 0x2D),
+    (&["EQUAL", "EQL"], 0x2E),
+    (&["LEFT_BRACKET", "LBRC"], 0x2F),
+    (&["RIGHT_BRACKET", "RBRC"], 0x30),
+    (&["BACKSLASH", "BSLS"], 0x31),
+    (&["NONUS_HASH", "NUHS"], 0x32),
+    (&["SEMICOLON", "SCLN"], 0x33),
+    (&["QUOTE", "QUOT"], 0x34),
+    (&["GRAVE", "GRV"], 0x35),
+    (&["COMMA", "COMM"], 0x36),
+    (&["DOT"], 0x37),
+    (&["SLASH", "SLSH"], 0x38),
+    (&["CAPS_LOCK", "CAPS"], 0x39),
+    (&["PRINT_SCREEN", "PSCR"], 0x46),
+    (&["SCROLL_LOCK", "SCRL", "BRMD"], 0x47),
+    (&["PAUSE", "PAUS", "BRK", "BRMU"], 0x48),
+    (&["INSERT", "INS"], 0x49),
+    (&["HOME"], 0x4A),
+    (&["PAGE_UP", "PGUP"], 0x4B),
+    (&["DELETE", "DEL"], 0x4C),
+    (&["END"], 0x4D),
+    (&["PAGE_DOWN", "PGDN"], 0x4E),
+    (&["RIGHT", "RGHT"], 0x4F),
+    (&["LEFT"], 0x50),
+    (&["DOWN"], 0x51),
+    (&["UP"], 0x52),
+    (&["NUM_LOCK", "NUM"], 0x53),
+    (&["KP_SLASH", "PSLS"], 0x54),
+    (&["KP_ASTERISK", "PAST"], 0x55),
+    (&["KP_MINUS", "PMNS"], 0x56),
+    (&["KP_PLUS", "PPLS"], 0x57),
+    (&["KP_ENTER", "PENT"], 0x58),
+    (&["KP_1", "P1"], 0x59),
+    (&["KP_2", "P2"], 0x5A),
+    (&["KP_3", "P3"], 0x5B),
+    (&["KP_4", "P4"], 0x5C),
+    (&["KP_5", "P5"], 0x5D),
+    (&["KP_6", "P6"], 0x5E),
+    (&["KP_7", "P7"], 0x5F),
+    (&["KP_8", "P8"], 0x60),
+    (&["KP_9", "P9"], 0x61),
+    (&["KP_0", "P0"], 0x62),
+    (&["KP_DOT", "PDOT"], 0x63),
+    (&["NONUS_BACKSLASH", "NUBS"], 0x64),
+    (&["APPLICATION", "APP"], 0x65),
+    (&["KB_POWER"], 0x66),
+    (&["KP_EQUAL", "PEQL"], 0x67),
+    (&["EXECUTE", "EXEC"], 0x74),
+    (&["HELP"], 0x75),
+    (&["MENU"], 0x76),
+    (&["SELECT", "SLCT"], 0x77),
+    (&["STOP"], 0x78),
+    (&["AGAIN", "AGIN"], 0x79),
+    (&["UNDO"], 0x7A),
+    (&["CUT"], 0x7B),
+    (&["COPY"], 0x7C),
+    (&["PASTE", "PSTE"], 0x7D),
+    (&["FIND"], 0x7E),
+    (&["KB_MUTE"], 0x7F),
+    (&["KB_VOLUME_UP"], 0x80),
+    (&["KB_VOLUME_DOWN"], 0x81),
+    (&["KP_COMMA", "PCMM"], 0x85),
+    (&["INTERNATIONAL_1", "INT1"], 0x87),
+    (&["INTERNATIONAL_2", "INT2"], 0x88),
+    (&["INTERNATIONAL_3", "INT3"], 0x89),
+    (&["INTERNATIONAL_4", "INT4"], 0x8A),
+    (&["INTERNATIONAL_5", "INT5"], 0x8B),
+    (&["LANGUAGE_1", "LNG1"], 0x90),
+    (&["LANGUAGE_2", "LNG2"], 0x91),
+    (&["LANGUAGE_3", "LNG3"], 0x92),
+    (&["LANGUAGE_4", "LNG4"], 0x93),
+    (&["LANGUAGE_5", "LNG5"], 0x94),
+    (&["LEFT_CTRL", "LCTL"], 0xE0),
+    (&["LEFT_SHIFT", "LSFT"], 0xE1),
+    (&["LEFT_ALT", "LALT", "LOPT"], 0xE2),
+    (&["LEFT_GUI", "LGUI", "LCMD", "LWIN"], 0xE3),
+    (&["RIGHT_CTRL", "RCTL"], 0xE4),
+    (&["RIGHT_SHIFT", "RSFT"], 0xE5),
+    (&["RIGHT_ALT", "RALT", "ROPT", "ALGR"], 0xE6),
+    (&["NONUS_BSLASH"], 0x64),
+    (&["POWER"], 0x66),
+];
+
+/// QMK names of Right GUI, whose usage ID the firmware uses for the Mod key
+const QMK_RIGHT_GUI: [&str; 4] = ["RIGHT_GUI", "RGUI", "RCMD", "RWIN"];
+const RIGHT_GUI_UNSUPPORTED: &str =
+    "Right GUI is not supported, as the KU-1255 firmware uses its usage ID for the Mod key (MO(1))";
+
+/// QMK shifted keycodes (without `KC_`), as the HID ID sent with Left Shift
+const QMK_SHIFTED_KEYCODES: [(&[&str], u8); 21] = [
+    (&["TILDE", "TILD"], 0x35),
+    (&["EXCLAIM", "EXLM"], 0x1E),
+    (&["AT"], 0x1F),
+    (&["HASH"], 0x20),
+    (&["DOLLAR", "DLR"], 0x21),
+    (&["PERCENT", "PERC"], 0x22),
+    (&["CIRCUMFLEX", "CIRC"], 0x23),
+    (&["AMPERSAND", "AMPR"], 0x24),
+    (&["ASTERISK", "ASTR"], 0x25),
+    (&["LEFT_PAREN", "LPRN"], 0x26),
+    (&["RIGHT_PAREN", "RPRN"], 0x27),
+    (&["UNDERSCORE", "UNDS"], 0x2D),
+    (&["PLUS"], 0x2E),
+    (&["LEFT_CURLY_BRACE", "LCBR"], 0x2F),
+    (&["RIGHT_CURLY_BRACE", "RCBR"], 0x30),
+    (&["PIPE"], 0x31),
+    (&["COLON", "COLN"], 0x33),
+    (&["DOUBLE_QUOTE", "DQUO", "DQT"], 0x34),
+    (&["LEFT_ANGLE_BRACKET", "LABK", "LT"], 0x36),
+    (&["RIGHT_ANGLE_BRACKET", "RABK", "GT"], 0x37),
+    (&["QUESTION", "QUES"], 0x38),
+];
+
+/// QMK media keycodes (without `KC_`), as consumer usage IDs for media key slots
+const QMK_MEDIA_KEYCODES: [(&[&str], u16); 21] = [
+    (&["AUDIO_MUTE", "MUTE"], 0xE2),
+    (&["AUDIO_VOL_UP", "VOLU"], 0xE9),
+    (&["AUDIO_VOL_DOWN", "VOLD"], 0xEA),
+    (&["MEDIA_NEXT_TRACK", "MNXT"], 0xB5),
+    (&["MEDIA_PREV_TRACK", "MPRV"], 0xB6),
+    (&["MEDIA_STOP", "MSTP"], 0xB7),
+    (&["MEDIA_PLAY_PAUSE", "MPLY"], 0xCD),
+    (&["MEDIA_SELECT", "MSEL"], 0x183),
+    (&["MEDIA_EJECT", "EJCT"], 0xB8),
+    (&["MAIL"], 0x18A),
+    (&["CALCULATOR", "CALC"], 0x192),
+    (&["MY_COMPUTER", "MYCM"], 0x194),
+    (&["WWW_SEARCH", "WSCH"], 0x221),
+    (&["WWW_HOME", "WHOM"], 0x223),
+    (&["WWW_BACK", "WBAK"], 0x224),
+    (&["WWW_FORWARD", "WFWD"], 0x225),
+    (&["WWW_STOP", "WSTP"], 0x226),
+    (&["WWW_REFRESH", "WREF"], 0x227),
+    (&["WWW_FAVORITES", "WFAV"], 0x22A),
+    (&["BRIGHTNESS_UP", "BRIU"], 0x6F),
+    (&["BRIGHTNESS_DOWN", "BRID"], 0x70),
+];
+
+/// Modifier wrappers such as `LCTL(kc)`, as flags in the order of the `MacroKey` fields
+const QMK_MODIFIER_WRAPPERS: [(&[&str], [bool; 8]); 15] = [
+    (&["LCTL", "C"], [true, false, false, false, false, false, false, false]),
+    (&["LSFT", "S"], [false, true, false, false, false, false, false, false]),
+    (&["LALT", "A", "LOPT"], [false, false, true, false, false, false, false, false]),
+    (&["LGUI", "G", "LCMD", "LWIN"], [false, false, false, true, false, false, false, false]),
+    (&["RCTL"], [false, false, false, false, true, false, false, false]),
+    (&["RSFT"], [false, false, false, false, false, true, false, false]),
+    (&["RALT", "ROPT", "ALGR"], [false, false, false, false, false, false, true, false]),
+    (&["RGUI", "RCMD", "RWIN"], [false, false, false, false, false, false, false, true]),
+    (&["LCS", "C_S"], [true, true, false, false, false, false, false, false]),
+    (&["LCA"], [true, false, true, false, false, false, false, false]),
+    (&["LSA"], [false, true, true, false, false, false, false, false]),
+    (&["SGUI", "SCMD", "SWIN"], [false, true, false, true, false, false, false, false]),
+    (&["LCAG"], [true, false, true, true, false, false, false, false]),
+    (&["MEH"], [true, true, true, false, false, false, false, false]),
+    (&["HYPR"], [true, true, true, true, false, false, false, false]),
+];
+
+/// A QMK keycode as far as the KU-1255 can express it.
+#[derive(Clone, PartialEq, Debug)]
+enum Keycode {
+    /// HID ID, or `None` for `KC_NO`
+    Basic(Option<u8>),
+    /// Falls through to the layer below
+    Transparent,
+    /// Key with modifiers, needs a macro key slot
+    Modded([bool; 8], u8),
+    /// Consumer usage, needs a media key slot
+    Media(u16),
+    /// Holds the Mod layer
+    ModLayer,
+}
+
+/// Config imported from another keymap format, with everything that could not be
+/// expressed or was changed on the way.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportedKeymap {
+    pub config: Config,
+    pub notes: Vec<String>,
+}
+
+fn lookup<T: Copy>(table: &[(&[&str], T)], name: &str) -> Option<T> {
+    table.iter().find(|(names, _)| names.contains(&name)).map(|(_, v)| *v)
+}
+
+fn basic_keycode(name: &str) -> Option<u8> {
+    let bytes = name.as_bytes();
+    match bytes {
+        [c @ b'A'..=b'Z'] => Some(c - b'A' + 0x04),
+        [b'0'] => Some(0x27),
+        [c @ b'1'..=b'9'] => Some(c - b'1' + 0x1E),
+        [b'F', ..] => match name[1..].parse::<u8>() {
+            Ok(n @ 1..=12) => Some(0x3A + n - 1),
+            Ok(n @ 13..=24) => Some(0x68 + n - 13),
+            _ => lookup(&QMK_KEYCODES, name),
+        },
+        _ => lookup(&QMK_KEYCODES, name),
+    }
+}
+
+/// Split `F(a, G(b))` into `F` and its top-level arguments.
+fn split_call(text: &str) -> Option<(&str, Vec<&str>)> {
+    let open = text.find('(')?;
+    let inner = text[open + 1..].strip_suffix(')')?;
+    Some((text[..open].trim(), split_top_level(inner)))
+}
+
+fn split_top_level(text: &str) -> Vec<&str> {
+    split_top_level_rows(text).into_iter().flatten().collect()
+}
+
+/// Top-level arguments, in rows wherever a line break separates two of them.
+fn split_top_level_rows(text: &str) -> Vec<Vec<&str>> {
+    let mut rows = vec![Vec::new()];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                rows.last_mut().unwrap().push(text[start..i].trim());
+                start = i + 1;
+            }
+            '\n' if depth == 0
+                && text[start..i].trim().is_empty()
+                && rows.last().is_some_and(|row| !row.is_empty()) =>
+            {
+                rows.push(Vec::new());
+            }
+            _ => {}
+        }
+    }
+    rows.last_mut().unwrap().push(text[start..].trim());
+    for row in &mut rows {
+        row.retain(|p| !p.is_empty());
+    }
+    rows.retain(|row| !row.is_empty());
+    rows
+}
+
+/// Numeric QMK keycode as used by older VIA files: basic keycodes are HID IDs and
+/// bits 8..=12 hold the modifiers of a modifier-wrapped key.
+fn numeric_keycode(code: u32) -> Result<Keycode, String> {
+    match code {
+        0 => Ok(Keycode::Basic(None)),
+        1 => Ok(Keycode::Transparent),
+        _ if code == MOD_KEY_ID as u32 => Err(RIGHT_GUI_UNSUPPORTED.to_string()),
+        0x04..=0xFF => Ok(Keycode::Basic(Some(code as u8))),
+        0x100..=0x1FFF => {
+            let mods = (code >> 8) & 0x0F;
+            let offset = if code & 0x1000 != 0 { 4 } else { 0 };
+            let mut flags = [false; 8];
+            for bit in 0..4 {
+                flags[offset + bit] = mods & (1 << bit) != 0;
+            }
+            Ok(Keycode::Modded(flags, (code & 0xFF) as u8))
+        }
+        _ => Err(format!("keycode {:#06x} is not supported", code)),
+    }
+}
+
+/// Parse one QMK keycode expression. Lossy conversions are added to `notes`.
+fn parse_keycode(text: &str, notes: &mut Vec<String>) -> Result<Keycode, String> {
+    let text = text.trim();
+    match text {
+        "KC_NO" | "XXXXXXX" => return Ok(Keycode::Basic(None)),
+        "KC_TRNS" | "KC_TRANSPARENT" | "_______" => return Ok(Keycode::Transparent),
+        _ => {}
+    }
+    if let Some(hex) = text.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16).map_err(|_| format!("'{}' is not a keycode", text)).and_then(numeric_keycode);
+    }
+    if let Ok(code) = text.parse::<u32>() {
+        return numeric_keycode(code);
+    }
+    if let Some((function, args)) = split_call(text) {
+        return match (function, &args[..]) {
+            ("MO", ["1"]) => Ok(Keycode::ModLayer),
+            ("LT", ["1", _]) => {
+                notes.push(format!("{}: the tap key is dropped; the key only holds the Mod layer", text));
+                Ok(Keycode::ModLayer)
+            }
+            ("TT", ["1"]) => {
+                notes.push(format!("{}: tap-toggle is not supported; the key only holds the Mod layer", text));
+                Ok(Keycode::ModLayer)
+            }
+            ("MO" | "LT" | "TT" | "TG" | "TO" | "OSL" | "DF", _) => {
+                Err("only holding layer 1 (the Mod layer) is supported".to_string())
+            }
+            ("MT", [_, key]) => {
+                notes.push(format!("{}: the hold modifier is dropped; only the tap key is kept", text));
+                parse_keycode(key, notes)
+            }
+            (_, [key]) if function.ends_with("_T") => {
+                notes.push(format!("{}: the hold modifier is dropped; only the tap key is kept", text));
+                parse_keycode(key, notes)
+            }
+            (_, [key]) => {
+                let flags = lookup(&QMK_MODIFIER_WRAPPERS, function)
+                    .ok_or_else(|| format!("{}() is not supported", function))?;
+                match parse_keycode(key, notes)? {
+                    Keycode::Basic(Some(id)) => Ok(Keycode::Modded(flags, id)),
+                    Keycode::Modded(inner, id) => {
+                        Ok(Keycode::Modded(std::array::from_fn(|i| flags[i] || inner[i]), id))
+                    }
+                    _ => Err(format!("{}() only wraps basic keys", function)),
+                }
+            }
+            _ => Err(format!("{}() is not supported", function)),
+        };
+    }
+    let name = text.strip_prefix("KC_").ok_or_else(|| format!("'{}' is not supported", text))?;
+    if QMK_RIGHT_GUI.contains(&name) {
+        return Err(RIGHT_GUI_UNSUPPORTED.to_string());
+    }
+    if let Some(id) = basic_keycode(name) {
+        return Ok(Keycode::Basic(Some(id)));
+    }
+    if let Some(id) = lookup(&QMK_SHIFTED_KEYCODES, name) {
+        return Ok(Keycode::Modded([false, true, false, false, false, false, false, false], id));
+    }
+    if let Some(usage) = lookup(&QMK_MEDIA_KEYCODES, name) {
+        return Ok(Keycode::Media(usage));
+    }
+    Err(format!("'{}' is not supported", text))
+}
+
+/// Keycodes of one layer, in the rows of the source file.
+type Layer = Vec<Vec<String>>;
+
+/// Keycode expressions of every `LAYOUT...(...)` in a QMK keymap.c, in order of
+/// appearance. Each line of a `LAYOUT` is a row.
+fn parse_qmk_keymap(text: &str) -> Result<Vec<Layer>, String> {
+    let comments = Regex::new(r"(?s)/\*.*?\*/|//[^\n]*").unwrap();
+    let text = comments.replace_all(text, "");
+    let layout = Regex::new(r"LAYOUT\w*\s*\(").unwrap();
+    let mut layers = Vec::new();
+    for found in layout.find_iter(&text) {
+        let rest = &text[found.end()..];
+        let mut depth = 1;
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(i, _)| i)
+            .ok_or("Unbalanced parentheses in LAYOUT(...)")?;
+        layers.push(
+            split_top_level_rows(&rest[..end])
+                .into_iter()
+                .map(|row| row.into_iter().map(str::to_string).collect())
+                .collect(),
+        );
+    }
+    if layers.is_empty() {
+        return Err("No LAYOUT(...) found in keymap.c".to_string());
+    }
+    Ok(layers)
+}
+
+/// Keycodes of a VIA keymap (`layers`, a single row) or Vial layout (`layout`,
+/// in matrix rows, -1 for no key).
+fn parse_via_json(text: &str) -> Result<Vec<Layer>, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let keycode = |v: &Value| match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) if n.as_i64() == Some(-1) => None,
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+    if let Some(layers) = value.get("layers").and_then(Value::as_array) {
+        return Ok(layers
+            .iter()
+            .map(|layer| vec![layer.as_array().into_iter().flatten().filter_map(keycode).collect()])
+            .collect());
+    }
+    if let Some(layers) = value.get("layout").and_then(Value::as_array) {
+        return Ok(layers
+            .iter()
+            .map(|layer| {
+                layer.as_array().into_iter().flatten()
+                    .map(|row| row.as_array().into_iter().flatten().filter_map(keycode).collect::<Vec<_>>())
+                    .filter(|row| !row.is_empty())
+                    .collect()
+            })
+            .collect());
+    }
+    Err("Neither 'layers' (VIA) nor 'layout' (Vial) found".to_string())
+}
+
+/// Macro and media key slots filled while importing.
+struct Slots {
+    macro_key_map: BTreeMap<u8, MacroKey>,
+    media_key_map: BTreeMap<u8, u16>,
+}
+
+impl Slots {
+    fn macro_slot(&mut self, macro_key: MacroKey) -> Option<u8> {
+        let trigger = match self.macro_key_map.iter().find(|(_, m)| **m == macro_key) {
+            Some((trigger, _)) => *trigger,
+            None => *self.macro_key_map.iter().find(|(_, m)| m.key_id == 0)?.0,
+        };
+        self.macro_key_map.insert(trigger, macro_key);
+        Some(trigger)
+    }
+
+    fn media_slot(&mut self, usage: u16) -> Option<u8> {
+        let trigger = match self.media_key_map.iter().find(|(_, u)| **u == usage) {
+            Some((trigger, _)) => *trigger,
+            None => *self.media_key_map.iter().find(|(_, u)| **u == 0)?.0,
+        };
+        self.media_key_map.insert(trigger, usage);
+        Some(trigger)
+    }
+}
+
+/// Import a QMK keymap.c, VIA keymap JSON or Vial `.vil` file onto `board`.
+///
+/// Keys are matched to the board by their position, see [`match_positions`].
+/// Layer 0 becomes the base layer and layer 1 the Mod layer; `MO(1)` becomes the Mod key.
+pub fn import_keymap(filepath: &Path, board: &Board, general_setting: &GeneralSeitting) -> Result<ImportedKeymap, String> {
+    let text = fs::read_to_string(filepath).map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+    let layers = match filepath.extension().and_then(|ext| ext.to_str()) {
+        Some("c") => parse_qmk_keymap(&text)?,
+        Some("json" | "vil") => parse_via_json(&text)?,
+        _ => return Err(format!("Unknown keymap format; expected one of {}", IMPORT_EXTENSIONS.join(", "))),
+    };
+    Ok(build_config(&layers, board, general_setting))
+}
+
+/// Matrix address of every key of `rows`, in order, or `None` for a key with no
+/// place on the board. When the keymap has as many rows as the board, each row is
+/// matched to the board row at the same place, and a row with another number of
+/// keys is matched by where its keys sit along the row. Otherwise keys are
+/// matched in order, row by row.
+fn match_positions(rows: &[Vec<String>], board: &Board, notes: &mut Vec<String>) -> Vec<Option<u8>> {
+    let board_rows: Vec<Vec<u8>> = board
+        .map_address
+        .iter()
+        .map(|row| row.iter().flatten().copied().collect::<Vec<u8>>())
+        .filter(|row| !row.is_empty())
+        .collect();
+    let key_count: usize = rows.iter().map(Vec::len).sum();
+    if rows.len() != board_rows.len() {
+        let board_count: usize = board_rows.iter().map(Vec::len).sum();
+        if key_count != board_count {
+            notes.push(format!(
+                "The keymap has {} keys in {} rows but board '{}' has {} in {}; keys are matched in order, row by row",
+                key_count,
+                rows.len(),
+                board.board_name,
+                board_count,
+                board_rows.len()
+            ));
+        }
+        let mut addresses: Vec<Option<u8>> = board_rows.into_iter().flatten().map(Some).collect();
+        addresses.resize(key_count, None);
+        return addresses;
+    }
+
+    let geometry: BTreeMap<u8, (f64, f64)> =
+        board.key_geometry().iter().map(|key| (key.address, (key.x as f64, (key.x + key.w) as f64))).collect();
+    let mut addresses = Vec::new();
+    for (number, (row, board_row)) in rows.iter().zip(&board_rows).enumerate() {
+        if row.len() == board_row.len() {
+            addresses.extend(board_row.iter().copied().map(Some));
+            continue;
+        }
+        notes.push(format!(
+            "Row {}: the keymap has {} keys but board '{}' has {}; keys are matched by their place in the row",
+            number + 1,
+            row.len(),
+            board.board_name,
+            board_row.len()
+        ));
+        // Centres along the row, 0 at its left and 1 at its right edge
+        let extents: Vec<(f64, f64)> = board_row.iter().map(|a| geometry.get(a).copied().unwrap_or_default()).collect();
+        let left = extents.iter().map(|e| e.0).fold(f64::MAX, f64::min);
+        let right = extents.iter().map(|e| e.1).fold(f64::MIN, f64::max);
+        let board_centres: Vec<f64> =
+            extents.iter().map(|(x0, x1)| ((x0 + x1) / 2.0 - left) / (right - left).max(1.0)).collect();
+        let mut pairs = Vec::new();
+        for i in 0..row.len() {
+            let centre = (i as f64 + 0.5) / row.len() as f64;
+            for (j, board_centre) in board_centres.iter().enumerate() {
+                pairs.push(((centre - board_centre).abs(), i, j));
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut matched = vec![None; row.len()];
+        let mut taken = vec![false; board_row.len()];
+        for (_, i, j) in pairs {
+            if matched[i].is_none() && !taken[j] {
+                matched[i] = Some(board_row[j]);
+                taken[j] = true;
+            }
+        }
+        addresses.extend(matched);
+    }
+    addresses
+}
+
+fn build_config(layers: &[Layer], board: &Board, general_setting: &GeneralSeitting) -> ImportedKeymap {
+    let names = Names::new(general_setting);
+    let mut notes = Vec::new();
+    if layers.len() > 2 {
+        notes.push(format!(
+            "Layers 2 to {} are dropped; the KU-1255 only has a base layer and a Mod layer",
+            layers.len() - 1
+        ));
+    }
+    let positions = match_positions(layers.first().map_or(&[], Vec::as_slice), board, &mut notes);
+
+    let mut slots = Slots { macro_key_map: default_macro_key_map(), media_key_map: default_media_key_map() };
+    let mut layer0 = general_setting.initial_id_map.clone();
+    let mut layer1 = general_setting.initial_id_map.clone();
+    for (number, layer) in layers.iter().take(2).enumerate() {
+        for (address, text) in positions.iter().zip(layer.iter().flatten()) {
+            let Some(address) = address else { continue };
+            let mut keycode_notes = Vec::new();
+            let keycode = parse_keycode(text, &mut keycode_notes);
+            let label = format!("Layer {}, {}", number, names.address_name(*address));
+            notes.extend(keycode_notes.into_iter().map(|note| format!("{}: {}", label, note)));
+            let id = match keycode {
+                Ok(Keycode::Basic(Some(id))) if general_setting.avail_hid_usage_names.get(&id).is_none_or(String::is_empty) => {
+                    Err(format!("{} has no usage name", text))
+                }
+                Ok(Keycode::Basic(id)) => Ok(id),
+                Ok(Keycode::Transparent) if number == 0 => continue,
+                Ok(Keycode::Transparent) => Ok(layer0.get(address).copied().flatten()),
+                Ok(Keycode::Modded(flags, id)) => slots
+                    .macro_slot(macro_key_from(id, flags))
+                    .map(Some)
+                    .ok_or_else(|| format!("{} needs a macro key, but all are in use", text)),
+                Ok(Keycode::Media(usage)) => slots
+                    .media_slot(usage)
+                    .map(Some)
+                    .ok_or_else(|| format!("{} needs a media key, but all are in use", text)),
+                Ok(Keycode::ModLayer) => Ok(Some(MOD_KEY_ID)),
+                Err(reason) => Err(format!("{}: {}", text, reason)),
+            };
+            let layer = if number == 0 { &mut layer0 } else { &mut layer1 };
+            match id {
+                Ok(id) => {
+                    layer.insert(*address, id);
+                }
+                Err(reason) => notes.push(format!("{}: {}; the key is left unchanged", label, reason)),
+            }
+        }
+    }
+
+    // The Mod layer is held by a key at the same position on both layers
+    for (address, id) in &layer0 {
+        if *id == Some(MOD_KEY_ID) && layer1.get(address) != Some(&Some(MOD_KEY_ID)) {
+            notes.push(format!("Layer 1, {}: set to Mod to match layer 0", names.address_name(*address)));
+            layer1.insert(*address, Some(MOD_KEY_ID));
+        }
+    }
+
+    let config = Config {
+        config_version: CONFIG_VERSION,
+        physical_layout_name: board.board_name.clone(),
+        logical_layout_name: board.default_logical_layout_name.clone(),
+        layer0,
+        layer1,
+        fn_id: default_fn_id(),
+        tp_sensitivity: default_tp_sensitivity(),
+        macro_key_map: slots.macro_key_map,
+        media_key_map: slots.media_key_map,
+        enable_middle_click: default_enable_middle_click(),
+    };
+    ImportedKeymap { config, notes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_qmk_keycodes() {
+        let mut notes = Vec::new();
+        assert_eq!(parse_keycode("KC_A", &mut notes), Ok(Keycode::Basic(Some(0x04))));
+        assert_eq!(parse_keycode("KC_F13", &mut notes), Ok(Keycode::Basic(Some(0x68))));
+        assert_eq!(
+            parse_keycode("LCTL(KC_C)", &mut notes),
+            Ok(Keycode::Modded([true, false, false, false, false, false, false, false], 0x06))
+        );
+        assert_eq!(
+            parse_keycode("C(S(KC_ESC))", &mut notes),
+            Ok(Keycode::Modded([true, true, false, false, false, false, false, false], 0x29))
+        );
+        assert_eq!(parse_keycode("KC_VOLU", &mut notes), Ok(Keycode::Media(0xE9)));
+        assert_eq!(parse_keycode("MO(1)", &mut notes), Ok(Keycode::ModLayer));
+        assert!(notes.is_empty());
+        assert_eq!(parse_keycode("LT(1, KC_SPC)", &mut notes), Ok(Keycode::ModLayer));
+        assert_eq!(notes.len(), 1);
+        assert!(parse_keycode("MO(2)", &mut notes).is_err());
+        assert!(parse_keycode("RGB_TOG", &mut notes).is_err());
+        assert_eq!(parse_keycode("KC_RGUI", &mut notes), Err(RIGHT_GUI_UNSUPPORTED.to_string()));
+        assert_eq!(parse_keycode("0xE7", &mut notes), Err(RIGHT_GUI_UNSUPPORTED.to_string()));
+        assert_eq!(
+            parse_keycode("RGUI(KC_L)", &mut notes),
+            Ok(Keycode::Modded([false, false, false, false, false, false, false, true], 0x0F))
+        );
+        assert_eq!(parse_keycode("0x0104", &mut notes), numeric_keycode(0x104));
+    }
+
+    #[test]
+    fn imports_qmk_keymap_onto_board() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let board = &general_setting.avail_boards[0];
+        let keymap = r#"
+            const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {
+                /* Base */
+                [0] = LAYOUT(KC_GRV, LCTL(KC_C), MO(1), KC_VOLU, RGB_TOG),
+                [1] = LAYOUT(_______, KC_NO, _______, LCTL(KC_C), KC_B),
+            };
+        "#;
+        let layers = parse_qmk_keymap(keymap).unwrap();
+        assert_eq!(layers[0], vec![vec!["KC_GRV", "LCTL(KC_C)", "MO(1)", "KC_VOLU", "RGB_TOG"]]);
+        let imported = build_config(&layers, board, &general_setting);
+        let config = &imported.config;
+        let address: Vec<u8> = board.map_address.iter().flatten().flatten().copied().take(5).collect();
+
+        assert_eq!(config.layer0[&address[0]], Some(0x35));
+        let trigger = config.layer0[&address[1]].unwrap();
+        assert_eq!(config.macro_key_map[&trigger].key_id, 0x06);
+        assert_eq!(config.layer1[&address[3]], Some(trigger));
+        assert_eq!(config.layer0[&address[2]], Some(MOD_KEY_ID));
+        assert_eq!(config.layer1[&address[2]], Some(MOD_KEY_ID));
+        assert_eq!(config.media_key_map[&config.layer0[&address[3]].unwrap()], 0xE9);
+        assert_eq!(config.layer0[&address[4]], general_setting.initial_id_map[&address[4]]);
+        assert_eq!(config.layer1[&address[0]], Some(0x35));
+        assert_eq!(config.layer1[&address[1]], None);
+        assert!(imported.notes.iter().any(|n| n.contains("RGB_TOG")));
+    }
+
+    #[test]
+    fn matches_keys_by_row_and_place() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let board = &general_setting.avail_boards[0];
+        let rows: Vec<Vec<u8>> = board
+            .map_address
+            .iter()
+            .map(|row| row.iter().flatten().copied().collect::<Vec<u8>>())
+            .filter(|row| !row.is_empty())
+            .collect();
+        // The first row lacks a key; the others are complete
+        let lines: Vec<String> = rows
+            .iter()
+            .enumerate()
+            .map(|(number, row)| {
+                let keycode = if number == 0 { "KC_A" } else { "KC_B" };
+                let count = if number == 0 { row.len() - 1 } else { row.len() };
+                vec![keycode; count].join(", ")
+            })
+            .collect();
+        let keymap = format!("[0] = LAYOUT(\n{}\n),\n[1] = LAYOUT(\n{}\n)", lines.join(",\n"), lines.join(",\n"));
+        let layers = parse_qmk_keymap(&keymap).unwrap();
+        assert_eq!(layers[0].len(), rows.len());
+        let imported = build_config(&layers, board, &general_setting);
+        let config = &imported.config;
+
+        for address in rows[1..].iter().flatten() {
+            assert_eq!(config.layer0[address], Some(0x05));
+        }
+        let unchanged: Vec<&u8> = rows[0].iter().filter(|address| config.layer0[*address] != Some(0x04)).collect();
+        assert_eq!(unchanged.len(), 1);
+        assert_eq!(config.layer0[unchanged[0]], general_setting.initial_id_map[unchanged[0]]);
+        assert_eq!(imported.notes.len(), 1, "{:?}", imported.notes);
+        assert!(imported.notes[0].starts_with("Row 1:"));
+    }
+}
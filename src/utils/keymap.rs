@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+
+use crate::models::{
+    Config, GeneralSeitting, MacroKey, default_macro_key_map, default_media_key_map, position_label,
+};
+
+// Modifier key IDs in the order of the `MacroKey` fields
+const MODIFIER_IDS: [u8; 8] = [0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7];
+
+/// Readable form of a [`Config`], written as TOML.
+///
+/// Keys are named by the legend printed at that position in `language`, e.g.
+/// `CapsLock` or `1!`, values by usage name. Only differences from the defaults
+/// are listed, and entries missing from the config are listed under `[removed]`,
+/// so that converting back gives the exact same config. Names that are not
+/// unique are written as hex, e.g. `"0xa5"`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Keymap {
+    config_version: u32,
+    board: String,
+    language: String,
+    fn_key: String,
+    trackpoint_speed: u32,
+    middle_click: bool,
+    #[serde(default)]
+    layer0: BTreeMap<String, String>,
+    #[serde(default)]
+    layer1: BTreeMap<String, String>,
+    #[serde(default)]
+    macros: BTreeMap<String, KeymapMacro>,
+    #[serde(default)]
+    media: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Removed::is_empty")]
+    removed: Removed,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct KeymapMacro {
+    key: String,
+    #[serde(default)]
+    modifiers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+struct Removed {
+    #[serde(default)]
+    layer0: Vec<String>,
+    #[serde(default)]
+    layer1: Vec<String>,
+    #[serde(default)]
+    macros: Vec<String>,
+    #[serde(default)]
+    media: Vec<String>,
+}
+
+impl Removed {
+    fn is_empty(&self) -> bool {
+        self.layer0.is_empty() && self.layer1.is_empty() && self.macros.is_empty() && self.media.is_empty()
+    }
+}
+
+/// Lookups between IDs, matrix addresses and their names.
+pub(crate) struct Names<'a> {
+    general_setting: &'a GeneralSeitting,
+    id_by_name: BTreeMap<&'a str, u8>,
+    media_by_name: BTreeMap<&'a str, u16>,
+    address_by_id: BTreeMap<u8, u8>,
+    label_by_address: BTreeMap<u8, String>,
+    address_by_label: BTreeMap<String, u8>,
+}
+
+impl<'a> Names<'a> {
+    pub(crate) fn new(general_setting: &'a GeneralSeitting) -> Names<'a> {
+        Names {
+            general_setting,
+            id_by_name: unique_names(&general_setting.avail_hid_usage_names),
+            media_by_name: unique_names(&general_setting.avail_media_key_usage_names),
+            address_by_id: general_setting
+                .initial_id_map
+                .iter()
+                .filter_map(|(address, id)| id.map(|id| (id, *address)))
+                .collect(),
+            label_by_address: BTreeMap::new(),
+            address_by_label: BTreeMap::new(),
+        }
+    }
+
+    /// Name key positions by their legend in the logical layout `language`
+    /// instead of by the usage name of the key originally there.
+    pub(crate) fn with_language(mut self, language: &str) -> Names<'a> {
+        let logical_layout = self.general_setting.avail_logical_layouts.iter().find(|l| l.layout_name == language);
+        let labels: BTreeMap<u8, String> = self
+            .general_setting
+            .initial_id_map
+            .iter()
+            .map(|(address, id)| {
+                let label: String = position_label(logical_layout, *id).split_whitespace().collect();
+                (*address, label)
+            })
+            .collect();
+        self.address_by_label = unique_names(&labels).into_iter().map(|(label, address)| (label.to_string(), address)).collect();
+        self.label_by_address = self.address_by_label.iter().map(|(label, address)| (*address, label.clone())).collect();
+        self
+    }
+
+    pub(crate) fn id_name(&self, id: u8) -> String {
+        match self.general_setting.avail_hid_usage_names.get(&id) {
+            Some(name) if self.id_by_name.get(name.as_str()) == Some(&id) => name.clone(),
+            _ => format!("{:#04x}", id),
+        }
+    }
+
+    fn parse_id(&self, name: &str) -> Result<u8, String> {
+        if let Some(id) = self.id_by_name.get(name) {
+            return Ok(*id);
+        }
+        parse_hex(name).ok_or_else(|| format!("Unknown key name '{}'", name))
+    }
+
+    pub(crate) fn value_name(&self, id: Option<u8>) -> String {
+        id.map_or(String::new(), |id| self.id_name(id))
+    }
+
+    fn parse_value(&self, name: &str) -> Result<Option<u8>, String> {
+        if name.is_empty() {
+            return Ok(None);
+        }
+        self.parse_id(name).map(Some)
+    }
+
+    /// Named by its legend, see [`Names::with_language`], or else after the key
+    /// originally at `address`
+    pub(crate) fn address_name(&self, address: u8) -> String {
+        if let Some(label) = self.label_by_address.get(&address) {
+            return label.clone();
+        }
+        match self.general_setting.initial_id_map.get(&address) {
+            Some(Some(id)) if self.address_by_id.get(id) == Some(&address) => {
+                let name = self.id_name(*id);
+                if name.starts_with("0x") { format!("{:#04x}", address) } else { name }
+            }
+            _ => format!("{:#04x}", address),
+        }
+    }
+
+    fn parse_address(&self, name: &str) -> Result<u8, String> {
+        if let Some(address) = self.address_by_label.get(name) {
+            return Ok(*address);
+        }
+        if let Some(address) = self.id_by_name.get(name).and_then(|id| self.address_by_id.get(id)) {
+            return Ok(*address);
+        }
+        parse_hex(name).ok_or_else(|| format!("Unknown key position '{}'", name))
+    }
+
+    pub(crate) fn media_name(&self, usage: u16) -> String {
+        match self.general_setting.avail_media_key_usage_names.get(&usage) {
+            Some(name) if self.media_by_name.get(name.as_str()) == Some(&usage) => name.clone(),
+            _ => format!("{:#06x}", usage),
+        }
+    }
+
+    /// Modifiers and key joined with '+', e.g. "LeftControl+C"
+    pub(crate) fn macro_name(&self, macro_key: &MacroKey) -> String {
+        modifiers(macro_key)
+            .iter()
+            .zip(MODIFIER_IDS)
+            .filter(|(on, _)| **on)
+            .map(|(_, id)| self.id_name(id))
+            .chain(std::iter::once(self.id_name(macro_key.key_id)))
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    fn parse_media(&self, name: &str) -> Result<u16, String> {
+        if let Some(usage) = self.media_by_name.get(name) {
+            return Ok(*usage);
+        }
+        name.strip_prefix("0x")
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("Unknown media usage '{}'", name))
+    }
+}
+
+/// Names that map to exactly one key.
+fn unique_names<K: Copy>(names: &BTreeMap<K, String>) -> BTreeMap<&str, K> {
+    let mut seen = BTreeSet::new();
+    let mut duplicated = BTreeSet::new();
+    for name in names.values() {
+        if !seen.insert(name.as_str()) {
+            duplicated.insert(name.as_str());
+        }
+    }
+    names
+        .iter()
+        .filter(|(_, name)| !name.is_empty() && !duplicated.contains(name.as_str()))
+        .map(|(key, name)| (name.as_str(), *key))
+        .collect()
+}
+
+fn parse_hex(name: &str) -> Option<u8> {
+    name.strip_prefix("0x").and_then(|hex| u8::from_str_radix(hex, 16).ok())
+}
+
+fn modifiers(macro_key: &MacroKey) -> [bool; 8] {
+    [
+        macro_key.left_ctrl,
+        macro_key.left_shift,
+        macro_key.left_alt,
+        macro_key.left_gui,
+        macro_key.right_ctrl,
+        macro_key.right_shift,
+        macro_key.right_alt,
+        macro_key.right_gui,
+    ]
+}
+
+pub(crate) fn macro_key_from(key_id: u8, flags: [bool; 8]) -> MacroKey {
+    MacroKey {
+        key_id,
+        left_ctrl: flags[0],
+        left_shift: flags[1],
+        left_alt: flags[2],
+        left_gui: flags[3],
+        right_ctrl: flags[4],
+        right_shift: flags[5],
+        right_alt: flags[6],
+        right_gui: flags[7],
+    }
+}
+
+/// Entries of `map` that differ from `defaults`, and keys of `defaults` missing from `map`.
+fn diff_from_defaults<'m, K: Ord + Copy, V: PartialEq>(
+    map: &'m BTreeMap<K, V>,
+    defaults: &BTreeMap<K, V>,
+) -> (Vec<(K, &'m V)>, Vec<K>) {
+    let changed = map
+        .iter()
+        .filter(|(k, v)| defaults.get(k) != Some(v))
+        .map(|(k, v)| (*k, v))
+        .collect();
+    let removed = defaults.keys().filter(|k| !map.contains_key(k)).copied().collect();
+    (changed, removed)
+}
+
+pub fn config_to_keymap(config: &Config, general_setting: &GeneralSeitting) -> Result<String, String> {
+    let names = Names::new(general_setting).with_language(&config.logical_layout_name);
+    let mut removed = Removed::default();
+
+    let mut layers = Vec::new();
+    for (layer, removed_keys) in [(&config.layer0, &mut removed.layer0), (&config.layer1, &mut removed.layer1)] {
+        let (changed, missing) = diff_from_defaults(layer, &general_setting.initial_id_map);
+        *removed_keys = missing.into_iter().map(|a| names.address_name(a)).collect();
+        layers.push(
+            changed
+                .into_iter()
+                .map(|(address, id)| (names.address_name(address), names.value_name(*id)))
+                .collect::<BTreeMap<_, _>>(),
+        );
+    }
+    let layer1 = layers.pop().unwrap_or_default();
+    let layer0 = layers.pop().unwrap_or_default();
+
+    let (changed, missing) = diff_from_defaults(&config.macro_key_map, &default_macro_key_map());
+    removed.macros = missing.into_iter().map(|t| names.id_name(t)).collect();
+    let macros = changed
+        .into_iter()
+        .map(|(trigger, macro_key)| {
+            let modifiers = modifiers(macro_key)
+                .iter()
+                .zip(MODIFIER_IDS)
+                .filter(|(on, _)| **on)
+                .map(|(_, id)| names.id_name(id))
+                .collect();
+            (names.id_name(trigger), KeymapMacro { key: names.id_name(macro_key.key_id), modifiers })
+        })
+        .collect();
+
+    let (changed, missing) = diff_from_defaults(&config.media_key_map, &default_media_key_map());
+    removed.media = missing.into_iter().map(|t| names.id_name(t)).collect();
+    let media = changed
+        .into_iter()
+        .map(|(trigger, usage)| (names.id_name(trigger), names.media_name(*usage)))
+        .collect();
+
+    let keymap = Keymap {
+        config_version: config.config_version,
+        board: config.physical_layout_name.clone(),
+        language: config.logical_layout_name.clone(),
+        fn_key: names.id_name(config.fn_id),
+        trackpoint_speed: config.tp_sensitivity,
+        middle_click: config.enable_middle_click,
+        layer0,
+        layer1,
+        macros,
+        media,
+        removed,
+    };
+    toml::to_string(&keymap).map_err(|e| format!("Failed to write keymap: {}", e))
+}
+
+pub fn keymap_to_config(text: &str, general_setting: &GeneralSeitting) -> Result<Config, String> {
+    let keymap: Keymap = toml::from_str(text).map_err(|e| format!("Invalid keymap: {}", e))?;
+    let names = Names::new(general_setting).with_language(&keymap.language);
+
+    let mut layers = Vec::new();
+    for (entries, removed) in [(&keymap.layer0, &keymap.removed.layer0), (&keymap.layer1, &keymap.removed.layer1)] {
+        let mut layer = general_setting.initial_id_map.clone();
+        for name in removed {
+            layer.remove(&names.parse_address(name)?);
+        }
+        for (position, value) in entries {
+            layer.insert(names.parse_address(position)?, names.parse_value(value)?);
+        }
+        layers.push(layer);
+    }
+    let layer1 = layers.pop().unwrap_or_default();
+    let layer0 = layers.pop().unwrap_or_default();
+
+    let mut macro_key_map = default_macro_key_map();
+    for name in &keymap.removed.macros {
+        macro_key_map.remove(&names.parse_id(name)?);
+    }
+    for (trigger, entry) in &keymap.macros {
+        let mut flags = [false; 8];
+        for modifier in &entry.modifiers {
+            let id = names.parse_id(modifier)?;
+            let index = MODIFIER_IDS
+                .iter()
+                .position(|m| *m == id)
+                .ok_or_else(|| format!("'{}' is not a modifier key", modifier))?;
+            flags[index] = true;
+        }
+        macro_key_map.insert(names.parse_id(trigger)?, macro_key_from(names.parse_id(&entry.key)?, flags));
+    }
+
+    let mut media_key_map = default_media_key_map();
+    for name in &keymap.removed.media {
+        media_key_map.remove(&names.parse_id(name)?);
+    }
+    for (trigger, usage) in &keymap.media {
+        media_key_map.insert(names.parse_id(trigger)?, names.parse_media(usage)?);
+    }
+
+    Ok(Config {
+        config_version: keymap.config_version,
+        physical_layout_name: keymap.board,
+        logical_layout_name: keymap.language,
+        layer0,
+        layer1,
+        fn_id: names.parse_id(&keymap.fn_key)?,
+        tp_sensitivity: keymap.trackpoint_speed,
+        macro_key_map,
+        media_key_map,
+        enable_middle_click: keymap.middle_click,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples_round_trip() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        for entry in std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/examples")).unwrap() {
+            let path = entry.unwrap().path();
+            let config: Config = serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+            let text = config_to_keymap(&config, &general_setting).unwrap();
+            let back = keymap_to_config(&text, &general_setting).unwrap();
+            assert_eq!(back, config, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn names_positions_by_legend() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let mut config: Config = serde_json::from_reader(std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/__default__.json")).unwrap()).unwrap();
+        config.logical_layout_name = "US_English".into();
+        let address = |id| *general_setting.initial_id_map.iter().find(|(_, i)| **i == Some(id)).unwrap().0;
+        config.layer0.insert(address(0x39), Some(0xE0));
+        config.layer0.insert(address(0x1E), Some(0x1F));
+
+        let text = config_to_keymap(&config, &general_setting).unwrap();
+        assert!(text.contains("CapsLock = \"LeftControl\""), "{}", text);
+        assert!(text.contains("\"1!\" = \"2 and @\""), "{}", text);
+        assert_eq!(keymap_to_config(&text, &general_setting).unwrap(), config);
+
+        // A misspelled table is an error, not an empty one
+        let misspelled = text.replace("[layer0]", "[layer_0]");
+        assert!(keymap_to_config(&misspelled, &general_setting).unwrap_err().contains("layer_0"));
+    }
+}
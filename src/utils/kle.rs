@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde_json::{Map, Value, json};
+
+use crate::models::{Board, GeneralSeitting, KeyGeometry, KeyShape, LogicalLayout, rows_from_keys};
+
+// Keys are drawn `width` px wide with a 4 px gap, so 1u is 52 px including the gap
+const KEY_GAP_PX: f64 = 4.0;
+const UNIT_PX: f64 = 52.0;
+// Rows are 40 px high with a 4 px gap
+const ROW_PX: f64 = 44.0;
+
+// Legend positions in a KLE label string
+const LEGEND_SHIFTED: usize = 0;
+const LEGEND_DEFAULT: usize = 1;
+const LEGEND_FRONT: usize = 4;
+
+// The metadata notes carry the matrix address of every key, in key order, so that
+// an exported layout can be imported again without guessing addresses from legends
+const ADDRESS_NOTE_PREFIX: &str = "KU-1255 matrix addresses:";
+
+/// Board created from a KLE layout, with everything that could not be expressed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportedBoard {
+    pub board: Board,
+    pub notes: Vec<String>,
+}
+
+fn px_to_units(width: u16) -> f64 {
+    ((width as f64 + KEY_GAP_PX) / UNIT_PX * 100.0).round() / 100.0
+}
+
+fn units_to_px(units: f64) -> u16 {
+    (units * UNIT_PX - KEY_GAP_PX).round().max(0.0) as u16
+}
+
+fn height_to_rows(height: u16) -> f64 {
+    ((height as f64 + KEY_GAP_PX) / ROW_PX * 100.0).round() / 100.0
+}
+
+fn rows_to_height(rows: f64) -> u16 {
+    (rows * ROW_PX - KEY_GAP_PX).round().max(0.0) as u16
+}
+
+fn round_units(units: f64) -> f64 {
+    (units * 100.0).round() / 100.0
+}
+
+/// Printed label of a key ID, falling back to its usage name.
+fn key_label(logical_layout: &LogicalLayout, id: Option<u8>) -> (String, String) {
+    let Some(label) = id.and_then(|id| logical_layout.map_key_label.get(&id)) else {
+        return (String::new(), String::new());
+    };
+    if label.default.is_empty() {
+        (String::new(), label.usage_name.clone())
+    } else {
+        (label.shifted.clone(), label.default.clone())
+    }
+}
+
+/// keyboard-layout-editor.com JSON of `board`, labelled with the Main layer and,
+/// where it differs, the 2nd layer as a front legend. Keys are placed from the
+/// board geometry; an ISO Enter becomes a two-part KLE key.
+pub fn board_to_kle(
+    board: &Board,
+    logical_layout: &LogicalLayout,
+    layer0: &BTreeMap<u8, Option<u8>>,
+    layer1: &BTreeMap<u8, Option<u8>>,
+) -> Value {
+    let mut keys = board.key_geometry();
+    keys.sort_by_key(|key| (key.y, key.x));
+    let addresses: Vec<String> = keys.iter().map(|key| format!("{:02X}", key.address)).collect();
+    let mut rows = vec![json!({
+        "name": board.board_label,
+        "notes": format!("{} {}", ADDRESS_NOTE_PREFIX, addresses.join(" ")),
+    })];
+    // KLE starts every row one unit below the previous one
+    let mut next_row_y = 0.0;
+    for row_keys in keys.chunk_by(|a, b| a.y == b.y) {
+        let row_y = round_units(row_keys[0].y as f64 / ROW_PX);
+        let mut y_offset = round_units(row_y - next_row_y);
+        next_row_y = row_y + 1.0;
+        let mut row = Vec::new();
+        let mut cursor = 0.0;
+        for key in row_keys {
+            let mut props = Map::new();
+            if y_offset != 0.0 {
+                props.insert("y".into(), json!(y_offset));
+                y_offset = 0.0;
+            }
+            // KLE draws the lower part of an ISO Enter as the key and its upper row as the second rectangle
+            let notch = if key.shape == KeyShape::IsoEnter { key.notch } else { 0 };
+            let x = round_units((key.x + notch) as f64 / UNIT_PX);
+            let units = px_to_units(key.w - notch);
+            let rows_high = height_to_rows(key.h);
+            let x_offset = round_units(x - cursor);
+            if x_offset != 0.0 {
+                props.insert("x".into(), json!(x_offset));
+            }
+            if units != 1.0 {
+                props.insert("w".into(), json!(units));
+            }
+            if rows_high != 1.0 {
+                props.insert("h".into(), json!(rows_high));
+            }
+            if key.shape == KeyShape::IsoEnter {
+                props.insert("x2".into(), json!(-round_units(notch as f64 / UNIT_PX)));
+                props.insert("w2".into(), json!(px_to_units(key.w)));
+                props.insert("h2".into(), json!(1.0));
+            }
+            cursor = round_units(x + units);
+            if !props.is_empty() {
+                row.push(Value::Object(props));
+            }
+
+            let main = layer0.get(&key.address).copied().flatten();
+            let second = layer1.get(&key.address).copied().flatten();
+            let mut legends = vec![String::new(); LEGEND_FRONT + 1];
+            (legends[LEGEND_SHIFTED], legends[LEGEND_DEFAULT]) = key_label(logical_layout, main);
+            if second != main {
+                legends[LEGEND_FRONT] = key_label(logical_layout, second).1;
+            }
+            while legends.last().is_some_and(String::is_empty) {
+                legends.pop();
+            }
+            row.push(json!(legends.join("\n")));
+        }
+        rows.push(Value::Array(row));
+    }
+    Value::Array(rows)
+}
+
+/// Addresses of the original keys, by their lowercase labels and usage names.
+fn addresses_by_label(logical_layout: &LogicalLayout, general_setting: &GeneralSeitting) -> BTreeMap<String, Vec<u8>> {
+    let mut by_label: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for (address, id) in &general_setting.initial_id_map {
+        let Some(id) = id else { continue };
+        let usage_name = general_setting.avail_hid_usage_names.get(id).cloned().unwrap_or_default();
+        let mut labels = vec![usage_name];
+        if let Some(label) = logical_layout.map_key_label.get(id) {
+            labels.push(label.default.clone());
+            labels.push(label.shifted.clone());
+        }
+        for label in labels.into_iter().filter(|l| !l.is_empty()) {
+            by_label.entry(label.to_lowercase()).or_default().push(*address);
+        }
+    }
+    by_label
+}
+
+/// Create a board from keyboard-layout-editor.com JSON.
+///
+/// Layouts exported by [`board_to_kle`] keep their matrix addresses. Otherwise each
+/// key is matched to an address by its legends, using the labels of `logical_layout`
+/// and the usage names; a legend like `0x60` gives the address directly.
+/// Keys keep their position and size; a two-part key is kept as an ISO Enter
+/// when it has that shape. Rotation and other two-part keys are dropped.
+pub fn kle_to_board(
+    kle: &Value,
+    board_name: &str,
+    logical_layout: &LogicalLayout,
+    general_setting: &GeneralSeitting,
+) -> Result<ImportedBoard, String> {
+    let items = kle.as_array().ok_or("KLE JSON must be an array of rows")?;
+    let by_label = addresses_by_label(logical_layout, general_setting);
+    let mut used = BTreeSet::new();
+    let mut notes = Vec::new();
+    let mut board_label = board_name.to_string();
+    let mut keys = Vec::new();
+
+    let key_count = items.iter().filter_map(Value::as_array).flatten().filter(|k| k.is_string()).count();
+    let mut noted_addresses: Option<std::vec::IntoIter<u8>> = None;
+
+    // KLE cursor in units; every row starts one unit below the previous one
+    let mut y = 0.0;
+    let mut row_number = 0;
+    for item in items {
+        let row = match item {
+            Value::Object(meta) => {
+                if let Some(name) = meta.get("name").and_then(Value::as_str) {
+                    // ',' and '#' end a value in the .cfg format
+                    board_label = name.replace([',', '#'], " ");
+                }
+                let noted = meta
+                    .get("notes")
+                    .and_then(Value::as_str)
+                    .and_then(|notes| notes.split_once(ADDRESS_NOTE_PREFIX))
+                    .map(|(_, list)| {
+                        list.split_whitespace().map(|a| u8::from_str_radix(a, 16).ok()).collect::<Option<Vec<u8>>>()
+                    });
+                match noted {
+                    Some(Some(list)) if list.len() == key_count => noted_addresses = Some(list.into_iter()),
+                    Some(_) => notes.push(
+                        "The matrix addresses in the notes do not match the keys; addresses are guessed from the legends"
+                            .to_string(),
+                    ),
+                    None => {}
+                }
+                continue;
+            }
+            Value::Array(row) => row,
+            _ => return Err("KLE rows must be arrays".to_string()),
+        };
+        row_number += 1;
+        let mut x = 0.0;
+        let (mut w, mut h) = (1.0, 1.0);
+        let mut second: Option<(f64, f64, f64, f64)> = None;
+        for key in row {
+            match key {
+                Value::Object(props) => {
+                    let number = |name: &str| props.get(name).and_then(Value::as_f64);
+                    x += number("x").unwrap_or(0.0);
+                    y += number("y").unwrap_or(0.0);
+                    w = number("w").unwrap_or(w);
+                    h = number("h").unwrap_or(h);
+                    if ["w2", "h2", "x2", "y2"].iter().any(|name| props.contains_key(*name)) {
+                        second = Some((
+                            number("x2").unwrap_or(0.0),
+                            number("y2").unwrap_or(0.0),
+                            number("w2").unwrap_or(w),
+                            number("h2").unwrap_or(h),
+                        ));
+                    }
+                    for unsupported in ["r", "rx", "ry"] {
+                        if number(unsupported).is_some_and(|v| v != 0.0) {
+                            notes.push(format!(
+                                "Row {}: '{}' is not supported; keys are laid out unrotated",
+                                row_number, unsupported
+                            ));
+                        }
+                    }
+                }
+                Value::String(legend) => {
+                    let noted = noted_addresses.as_mut().and_then(Iterator::next);
+                    let address = noted.or_else(|| legend
+                        .split('\n')
+                        .map(|l| l.trim())
+                        .filter(|l| !l.is_empty())
+                        .find_map(|l| {
+                            if let Some(address) = l.strip_prefix("0x").and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                                return (!used.contains(&address)).then_some(address);
+                            }
+                            by_label.get(&l.to_lowercase())?.iter().find(|a| !used.contains(*a)).copied()
+                        }));
+
+                    let mut key = KeyGeometry {
+                        x: (x * UNIT_PX).round().max(0.0) as u16,
+                        y: (y * ROW_PX).round().max(0.0) as u16,
+                        w: units_to_px(w),
+                        h: rows_to_height(h),
+                        shape: KeyShape::Rect,
+                        notch: 0,
+                        label: String::new(),
+                        address: address.unwrap_or_default(),
+                        id: address.and_then(|a| general_setting.initial_id_map.get(&a).copied().flatten()),
+                    };
+                    if x < 0.0 || y < 0.0 {
+                        notes.push(format!(
+                            "Row {}: key '{}' lies left of or above the layout and is moved to its edge",
+                            row_number,
+                            legend.replace('\n', " ")
+                        ));
+                    }
+                    match second.take() {
+                        // An ISO Enter: a one row high upper part sticking out to the left
+                        Some((x2, y2, w2, h2))
+                            if x2 < 0.0 && y2 == 0.0 && h2 == 1.0 && h > 1.0 && round_units(x2 + w2) == w =>
+                        {
+                            let notch = (-x2 * UNIT_PX).round() as u16;
+                            key.x = key.x.saturating_sub(notch);
+                            key.w += notch;
+                            key.shape = KeyShape::IsoEnter;
+                            key.notch = notch;
+                        }
+                        Some(_) => notes.push(format!(
+                            "Row {}: key '{}' has a second rectangle that is not an ISO Enter; only the first is kept",
+                            row_number,
+                            legend.replace('\n', " ")
+                        )),
+                        None => {}
+                    }
+                    match address {
+                        Some(address) => {
+                            used.insert(address);
+                            keys.push(key);
+                        }
+                        None => notes.push(format!(
+                            "Row {}: no matrix address for key '{}'; it is left empty",
+                            row_number,
+                            legend.replace('\n', " ")
+                        )),
+                    }
+                    x += w;
+                    (w, h) = (1.0, 1.0);
+                }
+                _ => return Err(format!("Row {}: unexpected item {}", row_number, key)),
+            }
+        }
+        y += 1.0;
+    }
+    if used.is_empty() {
+        return Err("No key of the KLE layout could be matched to a matrix address".to_string());
+    }
+
+    let initial_id_map = keys.iter().map(|key| (key.address, key.id)).collect();
+    let rows = rows_from_keys(keys);
+    let board = Board {
+        board_name: board_name.to_string(),
+        board_label,
+        default_logical_layout_name: logical_layout.layout_name.clone(),
+        map_widths: rows.map_widths,
+        map_address: rows.map_address,
+        initial_id_map,
+        keys: rows.keys,
+    };
+    Ok(ImportedBoard { board, notes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_round_trip() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for board in &general_setting.avail_boards {
+            let logical_layout = general_setting
+                .avail_logical_layouts
+                .iter()
+                .find(|l| l.layout_name == board.default_logical_layout_name)
+                .unwrap();
+            let kle = board_to_kle(board, logical_layout, &board.initial_id_map, &board.initial_id_map);
+            let imported = kle_to_board(&kle, &board.board_name, logical_layout, &general_setting).unwrap();
+            // Neighbouring gaps are merged, so only the keys are compared
+            assert_eq!(keys(&imported.board), keys(board), "{}", board.board_name);
+            assert_eq!(outlines(&imported.board), outlines(board), "{}", board.board_name);
+            assert_eq!(imported.board.board_label, board.board_label);
+            assert!(imported.notes.is_empty(), "{:?}", imported.notes);
+
+            // Only the structured format keeps an ISO Enter
+            let path = dir.path().join(format!("{}.toml", board.board_name));
+            GeneralSeitting::save_board_geometry(&imported.board, &path, &general_setting.avail_logical_layouts).unwrap();
+            let saved = GeneralSeitting::load_board(&path).unwrap();
+            assert_eq!(keys(&saved), keys(&imported.board));
+            assert_eq!(outlines(&saved), outlines(&imported.board));
+            assert_eq!(saved.initial_id_map, imported.board.initial_id_map);
+        }
+    }
+
+    #[test]
+    fn matches_keys_by_legend() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let board = general_setting.avail_boards.iter().find(|b| b.board_name == "0B47190").unwrap();
+        let logical_layout = general_setting
+            .avail_logical_layouts
+            .iter()
+            .find(|l| l.layout_name == board.default_logical_layout_name)
+            .unwrap();
+        let mut kle = board_to_kle(board, logical_layout, &board.initial_id_map, &board.initial_id_map);
+        kle[0].as_object_mut().unwrap().remove("notes");
+        let imported = kle_to_board(&kle, "copy", logical_layout, &general_setting).unwrap();
+        assert_eq!(keys(&imported.board), keys(board));
+    }
+
+    fn outlines(board: &Board) -> Vec<(u8, u16, u16, u16, u16, KeyShape, u16)> {
+        let mut outlines: Vec<_> =
+            board.key_geometry().iter().map(|k| (k.address, k.x, k.y, k.w, k.h, k.shape, k.notch)).collect();
+        outlines.sort_by_key(|&(_, x, y, ..)| (y, x));
+        outlines
+    }
+
+    fn keys(board: &Board) -> Vec<Vec<(u8, u16)>> {
+        board
+            .map_address
+            .iter()
+            .zip(&board.map_widths)
+            .map(|(addresses, widths)| {
+                addresses.iter().zip(widths).filter_map(|(a, w)| a.map(|a| (a, *w))).collect()
+            })
+            .collect()
+    }
+}
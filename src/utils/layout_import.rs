@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::models::{KeyLabel, LogicalLayout};
+
+/// Extensions accepted by [`import_logical_layout`]; XKB symbols files usually have none.
+pub const LAYOUT_IMPORT_EXTENSIONS: [&str; 2] = ["klc", "xkb"];
+
+/// Logical layout read from an XKB symbols or KLC file, with notes on what
+/// could not be converted.
+pub struct ImportedLayout {
+    pub layout: LogicalLayout,
+    pub notes: Vec<String>,
+}
+
+/// Label of one level and whether it is a dead key
+type Level = Option<(String, bool)>;
+
+/// Levels in the order default, shifted, AltGr, AltGr + Shift, by key ID
+type Levels = BTreeMap<u8, [Level; 4]>;
+
+/// Key IDs of the XKB key names. `BKSL` is the key above Enter on ANSI
+/// boards (0x31) and left of Enter on ISO boards (0x32).
+const XKB_KEYS: [(&str, &[u8]); 51] = [
+    ("TLDE", &[0x35]),
+    ("AE01", &[0x1E]), ("AE02", &[0x1F]), ("AE03", &[0x20]), ("AE04", &[0x21]), ("AE05", &[0x22]),
+    ("AE06", &[0x23]), ("AE07", &[0x24]), ("AE08", &[0x25]), ("AE09", &[0x26]), ("AE10", &[0x27]),
+    ("AE11", &[0x2D]), ("AE12", &[0x2E]), ("AE13", &[0x89]),
+    ("AD01", &[0x14]), ("AD02", &[0x1A]), ("AD03", &[0x08]), ("AD04", &[0x15]), ("AD05", &[0x17]),
+    ("AD06", &[0x1C]), ("AD07", &[0x18]), ("AD08", &[0x0C]), ("AD09", &[0x12]), ("AD10", &[0x13]),
+    ("AD11", &[0x2F]), ("AD12", &[0x30]),
+    ("AC01", &[0x04]), ("AC02", &[0x16]), ("AC03", &[0x07]), ("AC04", &[0x09]), ("AC05", &[0x0A]),
+    ("AC06", &[0x0B]), ("AC07", &[0x0D]), ("AC08", &[0x0E]), ("AC09", &[0x0F]), ("AC10", &[0x33]),
+    ("AC11", &[0x34]), ("BKSL", &[0x31, 0x32]), ("LSGT", &[0x64]),
+    ("AB01", &[0x1D]), ("AB02", &[0x1B]), ("AB03", &[0x06]), ("AB04", &[0x19]), ("AB05", &[0x05]),
+    ("AB06", &[0x11]), ("AB07", &[0x10]), ("AB08", &[0x36]), ("AB09", &[0x37]), ("AB10", &[0x38]),
+    ("AB11", &[0x87]), ("KPDL", &[0x63]),
+];
+
+/// Key IDs of the PC/AT (set 1) scancodes used in KLC files.
+const SCANCODE_KEYS: [(u8, &[u8]); 51] = [
+    (0x29, &[0x35]),
+    (0x02, &[0x1E]), (0x03, &[0x1F]), (0x04, &[0x20]), (0x05, &[0x21]), (0x06, &[0x22]),
+    (0x07, &[0x23]), (0x08, &[0x24]), (0x09, &[0x25]), (0x0A, &[0x26]), (0x0B, &[0x27]),
+    (0x0C, &[0x2D]), (0x0D, &[0x2E]), (0x7D, &[0x89]),
+    (0x10, &[0x14]), (0x11, &[0x1A]), (0x12, &[0x08]), (0x13, &[0x15]), (0x14, &[0x17]),
+    (0x15, &[0x1C]), (0x16, &[0x18]), (0x17, &[0x0C]), (0x18, &[0x12]), (0x19, &[0x13]),
+    (0x1A, &[0x2F]), (0x1B, &[0x30]),
+    (0x1E, &[0x04]), (0x1F, &[0x16]), (0x20, &[0x07]), (0x21, &[0x09]), (0x22, &[0x0A]),
+    (0x23, &[0x0B]), (0x24, &[0x0D]), (0x25, &[0x0E]), (0x26, &[0x0F]), (0x27, &[0x33]),
+    (0x28, &[0x34]), (0x2B, &[0x31, 0x32]), (0x56, &[0x64]),
+    (0x2C, &[0x1D]), (0x2D, &[0x1B]), (0x2E, &[0x06]), (0x2F, &[0x19]), (0x30, &[0x05]),
+    (0x31, &[0x11]), (0x32, &[0x10]), (0x33, &[0x36]), (0x34, &[0x37]), (0x35, &[0x38]),
+    (0x73, &[0x87]), (0x53, &[0x63]),
+];
+
+/// XKB keysym names of printable ASCII characters other than letters and digits.
+const ASCII_KEYSYMS: [(&str, char); 32] = [
+    ("exclam", '!'), ("quotedbl", '"'), ("numbersign", '#'), ("dollar", '$'), ("percent", '%'),
+    ("ampersand", '&'), ("apostrophe", '\''), ("parenleft", '('), ("parenright", ')'), ("asterisk", '*'),
+    ("plus", '+'), ("comma", ','), ("minus", '-'), ("period", '.'), ("slash", '/'), ("colon", ':'),
+    ("semicolon", ';'), ("less", '<'), ("equal", '='), ("greater", '>'), ("question", '?'), ("at", '@'),
+    ("bracketleft", '['), ("backslash", '\\'), ("bracketright", ']'), ("asciicircum", '^'),
+    ("underscore", '_'), ("grave", '`'), ("braceleft", '{'), ("bar", '|'), ("braceright", '}'),
+    ("asciitilde", '~'),
+];
+
+/// XKB keysym names of U+00A0 to U+00BF.
+const LATIN1_SYMBOL_KEYSYMS: [&str; 32] = [
+    "nobreakspace", "exclamdown", "cent", "sterling", "currency", "yen", "brokenbar", "section",
+    "diaeresis", "copyright", "ordfeminine", "guillemotleft", "notsign", "hyphen", "registered", "macron",
+    "degree", "plusminus", "twosuperior", "threesuperior", "acute", "mu", "paragraph", "periodcentered",
+    "cedilla", "onesuperior", "masculine", "guillemotright", "onequarter", "onehalf", "threequarters",
+    "questiondown",
+];
+
+/// XKB keysym names of U+00C0 to U+00DE; U+00E0 to U+00FE are the same
+/// names in lower case, except `division` for `multiply`.
+const LATIN1_LETTER_KEYSYMS: [&str; 31] = [
+    "Agrave", "Aacute", "Acircumflex", "Atilde", "Adiaeresis", "Aring", "AE", "Ccedilla", "Egrave",
+    "Eacute", "Ecircumflex", "Ediaeresis", "Igrave", "Iacute", "Icircumflex", "Idiaeresis", "ETH",
+    "Ntilde", "Ograve", "Oacute", "Ocircumflex", "Otilde", "Odiaeresis", "multiply", "Oslash", "Ugrave",
+    "Uacute", "Ucircumflex", "Udiaeresis", "Yacute", "THORN",
+];
+
+/// Spacing accent shown for each XKB dead key.
+const DEAD_KEYSYMS: [(&str, char); 13] = [
+    ("dead_grave", '`'), ("dead_acute", '´'), ("dead_circumflex", '^'), ("dead_tilde", '~'),
+    ("dead_macron", '¯'), ("dead_breve", '˘'), ("dead_abovedot", '˙'), ("dead_diaeresis", '¨'),
+    ("dead_abovering", '˚'), ("dead_doubleacute", '˝'), ("dead_caron", 'ˇ'), ("dead_cedilla", '¸'),
+    ("dead_ogonek", '˛'),
+];
+
+/// Text of a layout file: KLC files are usually UTF-16 with a byte order mark.
+fn decode_text(bytes: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| from([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).to_string(),
+        _ => String::from_utf8_lossy(bytes).to_string(),
+    }
+}
+
+/// Printed label of an XKB keysym, or `None` for `NoSymbol` and spaces.
+fn keysym_label(keysym: &str) -> Result<Level, String> {
+    if matches!(keysym, "NoSymbol" | "VoidSymbol" | "space" | "nobreakspace") {
+        return Ok(None);
+    }
+    if keysym.starts_with("dead_") {
+        return DEAD_KEYSYMS
+            .iter()
+            .find(|(name, _)| *name == keysym)
+            .map(|(_, c)| Some((c.to_string(), true)))
+            .ok_or_else(|| format!("unknown dead key {}", keysym));
+    }
+    let mut chars = keysym.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Some((c.to_string(), false)));
+    }
+    // Unicode keysyms such as U20AC; names such as Udiaeresis are Latin-1 below
+    let code = if let Some(hex) = keysym.strip_prefix('U').filter(|h| h.len() >= 4 && h.chars().all(|c| c.is_ascii_hexdigit())) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = keysym.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok().map(|v| if v >= 0x0100_0000 { v - 0x0100_0000 } else { v })
+    } else if keysym == "EuroSign" {
+        Some(0x20AC)
+    } else if let Some((_, c)) = ASCII_KEYSYMS.iter().find(|(name, _)| *name == keysym) {
+        Some(*c as u32)
+    } else if let Some(i) = LATIN1_SYMBOL_KEYSYMS.iter().position(|name| *name == keysym) {
+        Some(0xA0 + i as u32)
+    } else if let Some(i) = LATIN1_LETTER_KEYSYMS.iter().position(|name| *name == keysym) {
+        Some(0xC0 + i as u32)
+    } else if let Some(i) = LATIN1_LETTER_KEYSYMS.iter().position(|name| name.to_lowercase() == keysym && *name != "multiply") {
+        Some(0xE0 + i as u32)
+    } else {
+        match keysym {
+            "division" => Some(0xF7),
+            "ssharp" => Some(0xDF),
+            "ydiaeresis" => Some(0xFF),
+            _ => None,
+        }
+    };
+    match code.and_then(char::from_u32) {
+        Some(c) if c.is_whitespace() || c.is_control() => Ok(None),
+        Some(c) => Ok(Some((c.to_string(), false))),
+        None => Err(format!("unknown keysym {}", keysym)),
+    }
+}
+
+/// Body of the `xkb_symbols` block named `variant`, or of the default or first
+/// block, with its name.
+fn xkb_block<'a>(text: &'a str, variant: Option<&str>) -> Result<(String, &'a str), String> {
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("xkb_symbols") {
+        let is_default = rest[..start].split_whitespace().any(|word| word == "default");
+        let after = &rest[start + "xkb_symbols".len()..];
+        let name = after.split('"').nth(1).unwrap_or("").to_string();
+        let open = after.find('{').ok_or("xkb_symbols block without '{'")?;
+        let body_start = open + 1;
+        let mut depth = 1;
+        let mut end = None;
+        for (i, c) in after[body_start..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(body_start + i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| format!("xkb_symbols \"{}\" is not closed", name))?;
+        blocks.push((name, is_default, &after[body_start..end]));
+        rest = &after[end..];
+    }
+    let block = match variant {
+        Some(variant) => blocks.iter().find(|(name, _, _)| name == variant).ok_or_else(|| {
+            let names: Vec<&str> = blocks.iter().map(|(name, _, _)| name.as_str()).collect();
+            format!("No variant '{}'; the file has {}", variant, names.join(", "))
+        })?,
+        None => blocks
+            .iter()
+            .find(|(_, is_default, _)| *is_default)
+            .or(blocks.first())
+            .ok_or("No xkb_symbols block found")?,
+    };
+    Ok((block.0.clone(), block.2))
+}
+
+/// Levels of the keys of an XKB symbols file. Included files are not followed.
+fn parse_xkb(text: &str, variant: Option<&str>, notes: &mut Vec<String>) -> Result<(String, Levels), String> {
+    let text: String = text
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (name, body) = xkb_block(&text, variant)?;
+    let mut levels = Levels::new();
+    for statement in body.split(';') {
+        let statement = statement.trim();
+        if let Some(include) = statement.strip_prefix("include") {
+            notes.push(format!(
+                "include {} is not followed; keys defined there keep the base language",
+                include.trim()
+            ));
+            continue;
+        }
+        let Some(key) = statement.strip_prefix("key").map(str::trim_start) else { continue };
+        let Some(key_name) = key.strip_prefix('<').and_then(|k| k.split('>').next()) else { continue };
+        let Some((_, ids)) = XKB_KEYS.iter().find(|(name, _)| *name == key_name) else {
+            continue;
+        };
+        // Drop the group indices of `symbols[Group1]` and `type[Group1]` to find the first keysym list
+        let mut definition = key.to_string();
+        for group in 1..=4 {
+            definition = definition.replace(&format!("[Group{}]", group), "");
+        }
+        let Some(list) = definition.split('[').nth(1).and_then(|l| l.split(']').next()) else {
+            continue;
+        };
+        let mut key_levels: [Level; 4] = Default::default();
+        for (level, keysym) in key_levels.iter_mut().zip(list.split(',').map(str::trim)) {
+            match keysym_label(keysym) {
+                Ok(label) => *level = label,
+                Err(e) => notes.push(format!("<{}>: {}", key_name, e)),
+            }
+        }
+        for id in ids.iter() {
+            levels.insert(*id, key_levels.clone());
+        }
+    }
+    Ok((name, levels))
+}
+
+/// Label of a KLC cell: a character, 4 hex digits, or `-1` for none; a
+/// trailing `@` marks a dead key.
+fn klc_cell_label(cell: &str) -> Level {
+    let (cell, dead) = match cell.strip_suffix('@') {
+        Some(cell) => (cell, true),
+        None => (cell, false),
+    };
+    let mut chars = cell.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ if cell.len() == 4 => u32::from_str_radix(cell, 16).ok().and_then(char::from_u32)?,
+        _ => return None,
+    };
+    (!c.is_whitespace() && !c.is_control()).then(|| (c.to_string(), dead))
+}
+
+/// Levels of the keys of a Microsoft Keyboard Layout Creator file.
+fn parse_klc(text: &str, notes: &mut Vec<String>) -> Result<(String, Levels), String> {
+    let mut name = String::new();
+    let mut section = "";
+    let mut shift_states: Vec<u8> = Vec::new();
+    let mut levels = Levels::new();
+    for line in text.lines() {
+        let line = line.split("//").next().unwrap_or("");
+        let line = line.split(';').next().unwrap_or("");
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some(first) = tokens.first() else { continue };
+        // Section keywords such as SHIFTSTATE, LAYOUT or DEADKEY start a line
+        if first.len() > 2 && first.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+            section = first;
+            if *first == "KBD" {
+                name = tokens.get(1).copied().unwrap_or("").to_string();
+            }
+            continue;
+        }
+        match section {
+            "SHIFTSTATE" => shift_states.push(first.parse().map_err(|_| format!("Invalid shift state '{}'", first))?),
+            "LAYOUT" => {
+                let Ok(scancode) = u8::from_str_radix(first, 16) else { continue };
+                let Some((_, ids)) = SCANCODE_KEYS.iter().find(|(sc, _)| *sc == scancode) else { continue };
+                let mut key_levels: [Level; 4] = Default::default();
+                for (state, cell) in shift_states.iter().zip(tokens.iter().skip(3)) {
+                    let level = match state {
+                        0 => 0,
+                        1 => 1,
+                        6 => 2,
+                        7 => 3,
+                        _ => continue,
+                    };
+                    if *cell == "%%" {
+                        notes.push(format!("Scancode {:02X}: ligatures are not supported", scancode));
+                        continue;
+                    }
+                    key_levels[level] = klc_cell_label(cell);
+                }
+                for id in ids.iter() {
+                    levels.insert(*id, key_levels.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    if shift_states.is_empty() {
+        return Err("No SHIFTSTATE section found".to_string());
+    }
+    Ok((name, levels))
+}
+
+/// Convert an XKB symbols file or a Microsoft KLC file to a logical layout.
+///
+/// Keys the file does not define, such as Enter or the function keys, keep
+/// the labels of `base`. `variant` selects an `xkb_symbols` block; by default
+/// the one marked `default` or the first is used.
+pub fn import_logical_layout(
+    bytes: &[u8],
+    file_name: &str,
+    variant: Option<&str>,
+    base: &LogicalLayout,
+) -> Result<ImportedLayout, String> {
+    let text = decode_text(bytes);
+    let mut notes = Vec::new();
+    let stem = Path::new(file_name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let is_klc = file_name.to_lowercase().ends_with(".klc") || text.lines().any(|l| l.trim() == "SHIFTSTATE");
+    let (source_name, levels) = if is_klc {
+        parse_klc(&text, &mut notes)?
+    } else {
+        parse_xkb(&text, variant, &mut notes)?
+    };
+    if levels.values().all(|key| key[0].is_none()) {
+        return Err(format!("No key labels found in {}", file_name));
+    }
+
+    let layout_name = if is_klc || source_name.is_empty() || source_name == "basic" {
+        stem
+    } else {
+        format!("{}_{}", stem, source_name.replace(['(', ')', ' '], ""))
+    };
+    let mut map_key_label = base.map_key_label.clone();
+    for (id, key_levels) in levels {
+        if key_levels[0].is_none() {
+            continue;
+        }
+        let label = map_key_label.entry(id).or_insert_with(KeyLabel::new);
+        let cells = [&mut label.default, &mut label.shifted, &mut label.altgr, &mut label.altgr_shifted];
+        for ((cell, dead), level) in cells.into_iter().zip(label.dead.iter_mut()).zip(key_levels) {
+            (*cell, *dead) = level.unwrap_or_default();
+        }
+    }
+    Ok(ImportedLayout {
+        layout: LogicalLayout {
+            layout_label: layout_name.replace('_', " / "),
+            layout_name,
+            map_key_label,
+        },
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{GeneralSeitting, resources};
+
+    fn labels(layout: &LogicalLayout, id: u8) -> (&str, &str, &str, &str, [bool; 4]) {
+        let l = &layout.map_key_label[&id];
+        (&l.default, &l.shifted, &l.altgr, &l.altgr_shifted, l.dead)
+    }
+
+    #[test]
+    fn imports_xkb_and_klc_layouts() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let base = general_setting.avail_logical_layouts.iter().find(|l| l.layout_name == "US_English").unwrap();
+
+        let xkb = r#"
+            default partial alphanumeric_keys
+            xkb_symbols "basic" {
+                include "latin(type4)"
+                name[Group1]="German";
+                key <AE02> { [ 2, quotedbl, twosuperior, oneeighth ] }; // comment
+                key <AD01> { [ q, Q, at, Greek_OMEGA ] };
+                key <AD06> { [ z, Z, leftarrow, yen ] };
+                key <TLDE> { type[Group1]="FOUR_LEVEL", symbols[Group1]= [ dead_circumflex, degree ] };
+                key <AC10> { [ odiaeresis, Odiaeresis, dead_doubleacute ] };
+                key <AE04> { [ 4, dollar, U20AC ] };
+                key <AD11> { [ udiaeresis, Udiaeresis, Ugrave, Ucircumflex ] };
+            };
+            xkb_symbols "nodeadkeys" {
+                key <TLDE> { [ asciicircum, degree ] };
+            };
+        "#;
+        let imported = import_logical_layout(xkb.as_bytes(), "de", None, base).unwrap();
+        let layout = &imported.layout;
+        assert_eq!(layout.layout_name, "de");
+        assert_eq!(labels(layout, 0x1F), ("2", "\"", "²", "", [false; 4]));
+        assert_eq!(labels(layout, 0x14), ("q", "Q", "@", "", [false; 4]));
+        assert_eq!(labels(layout, 0x35), ("^", "°", "", "", [true, false, false, false]));
+        assert_eq!(labels(layout, 0x33), ("ö", "Ö", "˝", "", [false, false, true, false]));
+        assert_eq!(labels(layout, 0x21).2, "€");
+        assert_eq!(labels(layout, 0x2F), ("ü", "Ü", "Ù", "Û", [false; 4]));
+        assert_eq!(layout.map_key_label[&0x28].default, base.map_key_label[&0x28].default);
+        assert!(imported.notes.iter().any(|n| n.contains("latin(type4)")));
+        assert!(imported.notes.iter().any(|n| n.contains("oneeighth")));
+
+        let variant = import_logical_layout(xkb.as_bytes(), "de", Some("nodeadkeys"), base).unwrap();
+        assert_eq!(variant.layout.layout_name, "de_nodeadkeys");
+        assert_eq!(labels(&variant.layout, 0x35).4, [false; 4]);
+        assert!(import_logical_layout(xkb.as_bytes(), "de", Some("nope"), base).is_err());
+
+        let klc = "KBD\tfrcustom\t\"French - Custom\"\r\n\r\nSHIFTSTATE\r\n\r\n0\t//Column 4\r\n1\t//Column 5 : Shft\r\n2\t//Column 6 :       Ctrl\r\n6\t//Column 7 :       Ctrl Alt\r\n7\t//Column 8 : Shft  Ctrl Alt\r\n\r\nLAYOUT\t\t;an extra '@' at the end is a dead key\r\n\r\n//SC\tVK_\t\tCap\t0\t1\t2\t6\t7\r\n02\t1\t\t0\t0026\t1\t-1\t-1\t-1\t// AMPERSAND, DIGIT ONE\r\n10\tA\t\t1\ta\tA\t-1\t-1\t-1\r\n1a\tOEM_6\t\t0\t005e@\t00a8@\t001b\t-1\t-1\r\n03\t2\t\t0\t00e9\t2\t-1\t007e@\t-1\r\n\r\nDEADKEY\t005e\r\n\r\n0061\t00e2\t// a -> â\r\n\r\nENDKBD\r\n";
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(klc.encode_utf16().flat_map(u16::to_le_bytes));
+        let imported = import_logical_layout(&bytes, "fr-custom.klc", None, base).unwrap();
+        let layout = &imported.layout;
+        assert_eq!(layout.layout_name, "fr-custom");
+        assert_eq!(labels(layout, 0x1E), ("&", "1", "", "", [false; 4]));
+        assert_eq!(labels(layout, 0x14), ("a", "A", "", "", [false; 4]));
+        assert_eq!(labels(layout, 0x2F), ("^", "¨", "", "", [true, true, false, false]));
+        assert_eq!(labels(layout, 0x1F), ("é", "2", "~", "", [false, false, true, false]));
+
+        // The written CSV reads back the same
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fr-custom.csv");
+        GeneralSeitting::save_logical_layout(layout, &path).unwrap();
+        let loaded = GeneralSeitting::load_logical_layout(&path, &resources().find("settings/general_setting.csv")).unwrap();
+        for id in [0x1E, 0x14, 0x2F, 0x1F, 0x28] {
+            assert_eq!(labels(&loaded, id), labels(layout, id));
+        }
+    }
+}
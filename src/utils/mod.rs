@@ -1,11 +1,45 @@
 mod config;
 pub use config::*;
+mod keymap;
+mod overlay;
+pub use overlay::OverlayBase;
+mod config_diff;
+pub use config_diff::{ConfigDiff, DIFF_SECTIONS, MergeResult, diff_configs, merge_configs};
+mod import;
+pub use import::{IMPORT_EXTENSIONS, import_keymap};
+mod kle;
+pub use kle::{board_to_kle, kle_to_board};
+mod layout_import;
+pub use layout_import::{LAYOUT_IMPORT_EXTENSIONS, import_logical_layout};
+mod board_wizard;
+pub use board_wizard::{BoardRecorder, WIZARD_KEY_UNITS, WizardMode, diagnostic_settings, unit_width};
+mod cheat_sheet;
+pub use cheat_sheet::{cheat_sheet, write_cheat_sheet};
+mod os_remap;
+pub use os_remap::{OS_REMAP_FORMATS, OsRemapFormat, export_os_remap};
 
 mod firmware;
 pub use firmware::*;
 
+mod flasher;
+mod dry_run;
+#[cfg(test)]
+mod emulator;
+mod recovery;
+pub use recovery::DeviceState;
+mod usb;
+pub use usb::UsbDeviceInfo;
+mod batch;
+pub use batch::{BatchStatus, DeviceOutcome};
+mod history;
+pub use history::{HistoryEntry, history_image_identity, load_history};
+mod fingerprint;
+pub use fingerprint::firmware_identity;
 mod template;
 mod diff;
 mod format;
 mod commands;
-mod installer;
\ No newline at end of file
+mod installer;
+mod decompile;
+mod board_match;
+pub use board_match::BoardMatch;
\ No newline at end of file
@@ -0,0 +1,478 @@
+use std::fmt::Write as _;
+use serde_json::{Value, json};
+
+use crate::models::{Board, Config, GeneralSeitting, MacroKey, default_tp_sensitivity};
+use crate::utils::keymap::Names;
+
+// Vendor and product ID of the keyboard as seen by the OS, for Karabiner device conditions
+const KEYBOARD_DEVICE_ID: (u16, u16) = (0x17ef, 0x6047);
+
+/// HID usage ID with its xkb key name, US keysyms, Karabiner key_code and AutoHotkey key.
+/// Printable keys use scan codes in AutoHotkey so that they do not depend on the Windows layout.
+/// An empty name means the key has no equivalent in that format.
+const OS_KEYS: &[(u8, &str, &str, &str, &str)] = &[
+    (0x04, "AC01", "a, A", "a", "sc01E"),
+    (0x05, "AB05", "b, B", "b", "sc030"),
+    (0x06, "AB03", "c, C", "c", "sc02E"),
+    (0x07, "AC03", "d, D", "d", "sc020"),
+    (0x08, "AD03", "e, E", "e", "sc012"),
+    (0x09, "AC04", "f, F", "f", "sc021"),
+    (0x0A, "AC05", "g, G", "g", "sc022"),
+    (0x0B, "AC06", "h, H", "h", "sc023"),
+    (0x0C, "AD08", "i, I", "i", "sc017"),
+    (0x0D, "AC07", "j, J", "j", "sc024"),
+    (0x0E, "AC08", "k, K", "k", "sc025"),
+    (0x0F, "AC09", "l, L", "l", "sc026"),
+    (0x10, "AB07", "m, M", "m", "sc032"),
+    (0x11, "AB06", "n, N", "n", "sc031"),
+    (0x12, "AD09", "o, O", "o", "sc018"),
+    (0x13, "AD10", "p, P", "p", "sc019"),
+    (0x14, "AD01", "q, Q", "q", "sc010"),
+    (0x15, "AD04", "r, R", "r", "sc013"),
+    (0x16, "AC02", "s, S", "s", "sc01F"),
+    (0x17, "AD05", "t, T", "t", "sc014"),
+    (0x18, "AD07", "u, U", "u", "sc016"),
+    (0x19, "AB04", "v, V", "v", "sc02F"),
+    (0x1A, "AD02", "w, W", "w", "sc011"),
+    (0x1B, "AB02", "x, X", "x", "sc02D"),
+    (0x1C, "AD06", "y, Y", "y", "sc015"),
+    (0x1D, "AB01", "z, Z", "z", "sc02C"),
+    (0x1E, "AE01", "1, exclam", "1", "sc002"),
+    (0x1F, "AE02", "2, at", "2", "sc003"),
+    (0x20, "AE03", "3, numbersign", "3", "sc004"),
+    (0x21, "AE04", "4, dollar", "4", "sc005"),
+    (0x22, "AE05", "5, percent", "5", "sc006"),
+    (0x23, "AE06", "6, asciicircum", "6", "sc007"),
+    (0x24, "AE07", "7, ampersand", "7", "sc008"),
+    (0x25, "AE08", "8, asterisk", "8", "sc009"),
+    (0x26, "AE09", "9, parenleft", "9", "sc00A"),
+    (0x27, "AE10", "0, parenright", "0", "sc00B"),
+    (0x28, "RTRN", "Return", "return_or_enter", "Enter"),
+    (0x29, "ESC", "Escape", "escape", "Escape"),
+    (0x2A, "BKSP", "BackSpace", "delete_or_backspace", "Backspace"),
+    (0x2B, "TAB", "Tab, ISO_Left_Tab", "tab", "Tab"),
+    (0x2C, "SPCE", "space", "spacebar", "Space"),
+    (0x2D, "AE11", "minus, underscore", "hyphen", "sc00C"),
+    (0x2E, "AE12", "equal, plus", "equal_sign", "sc00D"),
+    (0x2F, "AD11", "bracketleft, braceleft", "open_bracket", "sc01A"),
+    (0x30, "AD12", "bracketright, braceright", "close_bracket", "sc01B"),
+    (0x31, "BKSL", "backslash, bar", "backslash", "sc02B"),
+    (0x32, "AC12", "numbersign, asciitilde", "non_us_pound", "sc02B"),
+    (0x33, "AC10", "semicolon, colon", "semicolon", "sc027"),
+    (0x34, "AC11", "apostrophe, quotedbl", "quote", "sc028"),
+    (0x35, "TLDE", "grave, asciitilde", "grave_accent_and_tilde", "sc029"),
+    (0x36, "AB08", "comma, less", "comma", "sc033"),
+    (0x37, "AB09", "period, greater", "period", "sc034"),
+    (0x38, "AB10", "slash, question", "slash", "sc035"),
+    (0x39, "CAPS", "Caps_Lock", "caps_lock", "CapsLock"),
+    (0x3A, "FK01", "F1", "f1", "F1"),
+    (0x3B, "FK02", "F2", "f2", "F2"),
+    (0x3C, "FK03", "F3", "f3", "F3"),
+    (0x3D, "FK04", "F4", "f4", "F4"),
+    (0x3E, "FK05", "F5", "f5", "F5"),
+    (0x3F, "FK06", "F6", "f6", "F6"),
+    (0x40, "FK07", "F7", "f7", "F7"),
+    (0x41, "FK08", "F8", "f8", "F8"),
+    (0x42, "FK09", "F9", "f9", "F9"),
+    (0x43, "FK10", "F10", "f10", "F10"),
+    (0x44, "FK11", "F11", "f11", "F11"),
+    (0x45, "FK12", "F12", "f12", "F12"),
+    (0x46, "PRSC", "Print", "print_screen", "PrintScreen"),
+    (0x47, "SCLK", "Scroll_Lock", "scroll_lock", "ScrollLock"),
+    (0x48, "PAUS", "Pause", "pause", "Pause"),
+    (0x49, "INS", "Insert", "insert", "Insert"),
+    (0x4A, "HOME", "Home", "home", "Home"),
+    (0x4B, "PGUP", "Prior", "page_up", "PgUp"),
+    (0x4C, "DELE", "Delete", "delete_forward", "Delete"),
+    (0x4D, "END", "End", "end", "End"),
+    (0x4E, "PGDN", "Next", "page_down", "PgDn"),
+    (0x4F, "RGHT", "Right", "right_arrow", "Right"),
+    (0x50, "LEFT", "Left", "left_arrow", "Left"),
+    (0x51, "DOWN", "Down", "down_arrow", "Down"),
+    (0x52, "UP", "Up", "up_arrow", "Up"),
+    (0x53, "NMLK", "Num_Lock", "keypad_num_lock", "NumLock"),
+    (0x54, "KPDV", "KP_Divide", "keypad_slash", "NumpadDiv"),
+    (0x55, "KPMU", "KP_Multiply", "keypad_asterisk", "NumpadMult"),
+    (0x56, "KPSU", "KP_Subtract", "keypad_hyphen", "NumpadSub"),
+    (0x57, "KPAD", "KP_Add", "keypad_plus", "NumpadAdd"),
+    (0x58, "KPEN", "KP_Enter", "keypad_enter", "NumpadEnter"),
+    (0x59, "KP1", "KP_End, KP_1", "keypad_1", "Numpad1"),
+    (0x5A, "KP2", "KP_Down, KP_2", "keypad_2", "Numpad2"),
+    (0x5B, "KP3", "KP_Next, KP_3", "keypad_3", "Numpad3"),
+    (0x5C, "KP4", "KP_Left, KP_4", "keypad_4", "Numpad4"),
+    (0x5D, "KP5", "KP_Begin, KP_5", "keypad_5", "Numpad5"),
+    (0x5E, "KP6", "KP_Right, KP_6", "keypad_6", "Numpad6"),
+    (0x5F, "KP7", "KP_Home, KP_7", "keypad_7", "Numpad7"),
+    (0x60, "KP8", "KP_Up, KP_8", "keypad_8", "Numpad8"),
+    (0x61, "KP9", "KP_Prior, KP_9", "keypad_9", "Numpad9"),
+    (0x62, "KP0", "KP_Insert, KP_0", "keypad_0", "Numpad0"),
+    (0x63, "KPDL", "KP_Delete, KP_Decimal", "keypad_period", "NumpadDot"),
+    (0x64, "LSGT", "less, greater", "non_us_backslash", "sc056"),
+    (0x65, "COMP", "Menu", "application", "AppsKey"),
+    (0x66, "POWR", "XF86PowerOff", "power", ""),
+    (0x67, "KPEQ", "KP_Equal", "keypad_equal_sign", ""),
+    (0x68, "FK13", "F13", "f13", "F13"),
+    (0x69, "FK14", "F14", "f14", "F14"),
+    (0x6A, "FK15", "F15", "f15", "F15"),
+    (0x6B, "FK16", "F16", "f16", "F16"),
+    (0x6C, "FK17", "F17", "f17", "F17"),
+    (0x6D, "FK18", "F18", "f18", "F18"),
+    (0x6E, "FK19", "F19", "f19", "F19"),
+    (0x6F, "FK20", "F20", "f20", "F20"),
+    (0x70, "FK21", "F21", "f21", "F21"),
+    (0x71, "FK22", "F22", "f22", "F22"),
+    (0x72, "FK23", "F23", "f23", "F23"),
+    (0x73, "FK24", "F24", "f24", "F24"),
+    (0x87, "AB11", "backslash, underscore", "international1", "sc073"),
+    (0x88, "HKTG", "Hiragana_Katakana", "international2", "sc070"),
+    (0x89, "AE13", "backslash, bar", "international3", "sc07D"),
+    (0x8A, "HENK", "Henkan_Mode", "international4", "sc079"),
+    (0x8B, "MUHE", "Muhenkan", "international5", "sc07B"),
+    (0x90, "HNGL", "Hangul", "lang1", "sc072"),
+    (0x91, "HJCV", "Hangul_Hanja", "lang2", "sc071"),
+    (0xE0, "LCTL", "Control_L", "left_control", "LCtrl"),
+    (0xE1, "LFSH", "Shift_L", "left_shift", "LShift"),
+    (0xE2, "LALT", "Alt_L", "left_option", "LAlt"),
+    (0xE3, "LWIN", "Super_L", "left_command", "LWin"),
+    (0xE4, "RCTL", "Control_R", "right_control", "RCtrl"),
+    (0xE5, "RTSH", "Shift_R", "right_shift", "RShift"),
+    (0xE6, "RALT", "Alt_R", "right_option", "RAlt"),
+    (0xE7, "RWIN", "Super_R", "right_command", "RWin"),
+];
+
+/// Consumer page usage with its xkb keysym, Karabiner consumer_key_code and AutoHotkey key.
+const OS_MEDIA_KEYS: &[(u16, &str, &str, &str)] = &[
+    (0x006F, "XF86MonBrightnessUp", "display_brightness_increment", ""),
+    (0x0070, "XF86MonBrightnessDown", "display_brightness_decrement", ""),
+    (0x00B3, "XF86AudioForward", "fast_forward", ""),
+    (0x00B4, "XF86AudioRewind", "rewind", ""),
+    (0x00B5, "XF86AudioNext", "scan_next_track", "Media_Next"),
+    (0x00B6, "XF86AudioPrev", "scan_previous_track", "Media_Prev"),
+    (0x00B7, "XF86AudioStop", "", "Media_Stop"),
+    (0x00B8, "XF86Eject", "eject", ""),
+    (0x00CD, "XF86AudioPlay", "play_or_pause", "Media_Play_Pause"),
+    (0x00E2, "XF86AudioMute", "mute", "Volume_Mute"),
+    (0x00E9, "XF86AudioRaiseVolume", "volume_increment", "Volume_Up"),
+    (0x00EA, "XF86AudioLowerVolume", "volume_decrement", "Volume_Down"),
+    (0x018A, "XF86Mail", "", "Launch_Mail"),
+    (0x0192, "XF86Calculator", "", "Launch_App2"),
+    (0x0194, "XF86MyComputer", "", "Launch_App1"),
+    (0x0221, "XF86Search", "", "Browser_Search"),
+    (0x0223, "XF86HomePage", "", "Browser_Home"),
+    (0x0224, "XF86Back", "", "Browser_Back"),
+    (0x0225, "XF86Forward", "", "Browser_Forward"),
+    (0x0227, "XF86Reload", "", "Browser_Refresh"),
+    (0x022A, "XF86Favorites", "", "Browser_Favorites"),
+];
+
+// Column of each format in OS_KEYS and OS_MEDIA_KEYS
+const XKB: usize = 0;
+const KARABINER: usize = 1;
+const AUTOHOTKEY: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OsRemapFormat {
+    Xkb,
+    Karabiner,
+    AutoHotkey,
+}
+
+pub const OS_REMAP_FORMATS: [OsRemapFormat; 3] = [OsRemapFormat::Xkb, OsRemapFormat::Karabiner, OsRemapFormat::AutoHotkey];
+
+impl OsRemapFormat {
+    pub fn label(&self) -> &'static str {
+        match self {
+            OsRemapFormat::Xkb => "xkb symbols (Linux)",
+            OsRemapFormat::Karabiner => "Karabiner-Elements (macOS)",
+            OsRemapFormat::AutoHotkey => "AutoHotkey v2 (Windows)",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OsRemapFormat::Xkb => "xkb",
+            OsRemapFormat::Karabiner => "json",
+            OsRemapFormat::AutoHotkey => "ahk",
+        }
+    }
+
+    /// Format named by a file extension or "xkb", "karabiner" or "ahk"
+    pub fn from_name(name: &str) -> Option<OsRemapFormat> {
+        match name {
+            "xkb" => Some(OsRemapFormat::Xkb),
+            "karabiner" | "json" => Some(OsRemapFormat::Karabiner),
+            "ahk" | "autohotkey" => Some(OsRemapFormat::AutoHotkey),
+            _ => None,
+        }
+    }
+
+    fn column(&self) -> usize {
+        match self {
+            OsRemapFormat::Xkb => XKB,
+            OsRemapFormat::Karabiner => KARABINER,
+            OsRemapFormat::AutoHotkey => AUTOHOTKEY,
+        }
+    }
+}
+
+/// Remapping file and the parts of the config it cannot express.
+#[derive(Clone, PartialEq, Debug)]
+pub struct OsRemap {
+    pub text: String,
+    pub unsupported: Vec<String>,
+}
+
+/// What a remapped key sends instead of its original key.
+enum Target {
+    Key(u8),
+    Disabled,
+    Macro(MacroKey),
+    Media(u16),
+}
+
+struct Remap {
+    from: u8,
+    to: Target,
+    comment: String,
+}
+
+fn os_key(id: u8, column: usize) -> Option<&'static str> {
+    OS_KEYS.iter().find(|k| k.0 == id).map(|k| [k.1, k.3, k.4][column]).filter(|name| !name.is_empty())
+}
+
+fn os_media_key(usage: u16, column: usize) -> Option<&'static str> {
+    OS_MEDIA_KEYS.iter().find(|k| k.0 == usage).map(|k| [k.1, k.2, k.3][column]).filter(|name| !name.is_empty())
+}
+
+fn keysyms(id: u8) -> &'static str {
+    OS_KEYS.iter().find(|k| k.0 == id).map_or("", |k| k.2)
+}
+
+/// Main layer keys that differ from the board's original keys, and what cannot be exported at all.
+fn collect_remaps(config: &Config, board: &Board, names: &Names) -> (Vec<Remap>, Vec<String>) {
+    let mut remaps = Vec::new();
+    let mut unsupported = Vec::new();
+    for (address, original) in &board.initial_id_map {
+        let Some(original) = original else { continue };
+        let Some(current) = config.layer0.get(address) else { continue };
+        if current == &Some(*original) {
+            continue;
+        }
+        let comment = format!("{} -> {}", names.id_name(*original), names.value_name(*current));
+        let to = match current {
+            None => Target::Disabled,
+            Some(id) if *id == config.fn_id => {
+                unsupported.push(format!("{}: the Fn key only exists in the firmware", comment));
+                continue;
+            }
+            Some(id) => match (config.macro_key_map.get(id), config.media_key_map.get(id)) {
+                (Some(macro_key), _) => Target::Macro(macro_key.clone()),
+                (_, Some(usage)) => Target::Media(*usage),
+                _ => Target::Key(*id),
+            },
+        };
+        remaps.push(Remap { from: *original, to, comment });
+    }
+    if config.layer1 != config.layer0 {
+        unsupported.push("The 2nd layer (Mod layer) is not exported; the OS does not see the Fn key".to_string());
+    }
+    if config.tp_sensitivity != default_tp_sensitivity() {
+        unsupported.push(format!("TrackPoint sensitivity {} can only be set in the firmware", config.tp_sensitivity));
+    }
+    if config.enable_middle_click {
+        unsupported.push("TrackPoint middle click can only be set in the firmware".to_string());
+    }
+    (remaps, unsupported)
+}
+
+/// OS-level remapping of the Main layer of `config` on `board`, including macro and media keys.
+pub fn export_os_remap(
+    format: OsRemapFormat,
+    config: &Config,
+    board: &Board,
+    general_setting: &GeneralSeitting,
+) -> OsRemap {
+    let names = Names::new(general_setting);
+    let (remaps, mut unsupported) = collect_remaps(config, board, &names);
+    let column = format.column();
+    let mut supported = Vec::new();
+    for remap in remaps {
+        let missing = match (&remap.to, os_key(remap.from, column)) {
+            (_, None) => Some("the original key has no OS key code"),
+            (Target::Key(id), _) if os_key(*id, column).is_none() => Some("no equivalent key"),
+            (Target::Media(usage), _) if os_media_key(*usage, column).is_none() => Some("no equivalent media key"),
+            (Target::Macro(macro_key), _) if os_key(macro_key.key_id, column).is_none() => Some("no equivalent key"),
+            (Target::Macro(_), _) if format == OsRemapFormat::Xkb => Some("xkb cannot send key combinations"),
+            _ => None,
+        };
+        match missing {
+            Some(reason) => unsupported.push(format!("{}: {}", remap.comment, reason)),
+            None => supported.push(remap),
+        }
+    }
+    let title = format!("KU-1255 {} ({})", board.board_label, config.logical_layout_name);
+    let text = match format {
+        OsRemapFormat::Xkb => xkb(&title, &supported),
+        OsRemapFormat::Karabiner => karabiner(&title, &supported),
+        OsRemapFormat::AutoHotkey => autohotkey(&title, &supported),
+    };
+    if format == OsRemapFormat::Xkb && config.logical_layout_name != "US_English" {
+        unsupported.push("xkb symbols are those of the US layout".to_string());
+    }
+    OsRemap { text, unsupported }
+}
+
+fn modifier_ids(macro_key: &MacroKey) -> Vec<u8> {
+    [
+        macro_key.left_ctrl,
+        macro_key.left_shift,
+        macro_key.left_alt,
+        macro_key.left_gui,
+        macro_key.right_ctrl,
+        macro_key.right_shift,
+        macro_key.right_alt,
+        macro_key.right_gui,
+    ]
+    .iter()
+    .zip(0xE0..=0xE7)
+    .filter_map(|(on, id)| on.then_some(id))
+    .collect()
+}
+
+fn xkb(title: &str, remaps: &[Remap]) -> String {
+    let mut text = format!(
+        "// {}\n\
+         // Copy to ~/.config/xkb/symbols/ku1255 and add \"ku1255\" to the layout, e.g.\n\
+         // setxkbmap -layout us -symbols \"pc+us+ku1255+inet(evdev)\"\n\
+         default partial modifier_keys alphanumeric_keys\n\
+         xkb_symbols \"remap\" {{\n",
+        title
+    );
+    let modifier_maps = [("Control", [0xE0, 0xE4]), ("Shift", [0xE1, 0xE5]), ("Mod1", [0xE2, 0xE6]), ("Mod4", [0xE3, 0xE7])];
+    for remap in remaps {
+        let key = os_key(remap.from, XKB).unwrap_or_default();
+        let symbols = match &remap.to {
+            Target::Key(id) => keysyms(*id).to_string(),
+            Target::Media(usage) => os_media_key(*usage, XKB).unwrap_or_default().to_string(),
+            Target::Disabled | Target::Macro(_) => "VoidSymbol".to_string(),
+        };
+        let _ = writeln!(text, "    // {}", remap.comment);
+        let _ = writeln!(text, "    key <{}> {{ [ {} ] }};", key, symbols);
+        let modifier = match remap.to {
+            Target::Key(id) => modifier_maps.iter().find(|(_, ids)| ids.contains(&id)),
+            _ => None,
+        };
+        if let Some((modifier, _)) = modifier {
+            let _ = writeln!(text, "    modifier_map {} {{ <{}> }};", modifier, key);
+        }
+    }
+    text.push_str("};\n");
+    text
+}
+
+fn karabiner(title: &str, remaps: &[Remap]) -> String {
+    let (vendor_id, product_id) = KEYBOARD_DEVICE_ID;
+    let manipulators: Vec<Value> = remaps
+        .iter()
+        .map(|remap| {
+            let to = match &remap.to {
+                Target::Key(id) => json!({ "key_code": os_key(*id, KARABINER) }),
+                Target::Disabled => json!({ "key_code": "vk_none" }),
+                Target::Media(usage) => json!({ "consumer_key_code": os_media_key(*usage, KARABINER) }),
+                Target::Macro(macro_key) => json!({
+                    "key_code": os_key(macro_key.key_id, KARABINER),
+                    "modifiers": modifier_ids(macro_key).iter().filter_map(|id| os_key(*id, KARABINER)).collect::<Vec<_>>(),
+                }),
+            };
+            json!({
+                "description": remap.comment,
+                "type": "basic",
+                "from": { "key_code": os_key(remap.from, KARABINER), "modifiers": { "optional": ["any"] } },
+                "to": [to],
+                "conditions": [{
+                    "type": "device_if",
+                    "identifiers": [{ "vendor_id": vendor_id, "product_id": product_id }],
+                }],
+            })
+        })
+        .collect();
+    let rules = json!({
+        "title": title,
+        "rules": [{ "description": title, "manipulators": manipulators }],
+    });
+    serde_json::to_string_pretty(&rules).unwrap_or_default() + "\n"
+}
+
+fn autohotkey(title: &str, remaps: &[Remap]) -> String {
+    let mut text = format!(
+        "; {}\n\
+         ; Applies to every keyboard attached to this computer\n\
+         #Requires AutoHotkey v2.0\n\n",
+        title
+    );
+    for remap in remaps {
+        let key = os_key(remap.from, AUTOHOTKEY).unwrap_or_default();
+        let action = match &remap.to {
+            Target::Key(id) => os_key(*id, AUTOHOTKEY).unwrap_or_default().to_string(),
+            Target::Media(usage) => os_media_key(*usage, AUTOHOTKEY).unwrap_or_default().to_string(),
+            Target::Disabled => "return".to_string(),
+            Target::Macro(macro_key) => {
+                let modifiers: Vec<&str> =
+                    modifier_ids(macro_key).iter().filter_map(|id| os_key(*id, AUTOHOTKEY)).collect();
+                let mut keys = String::new();
+                for modifier in &modifiers {
+                    let _ = write!(keys, "{{{} down}}", modifier);
+                }
+                let _ = write!(keys, "{{{}}}", os_key(macro_key.key_id, AUTOHOTKEY).unwrap_or_default());
+                for modifier in modifiers.iter().rev() {
+                    let _ = write!(keys, "{{{} up}}", modifier);
+                }
+                format!("Send \"{}\"", keys)
+            }
+        };
+        let _ = writeln!(text, "{}::{}  ; {}", key, action, remap.comment);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::keymap::macro_key_from;
+
+    #[test]
+    fn exports_remaps_and_reports_the_rest() {
+        let general_setting = GeneralSeitting::load_from_files().unwrap();
+        let board = general_setting.avail_boards.iter().find(|b| b.board_name == "0B47190").unwrap();
+        let address = |id: u8| *board.initial_id_map.iter().find(|(_, i)| **i == Some(id)).unwrap().0;
+        let mut config: Config = serde_json::from_reader(std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json")).unwrap()).unwrap();
+        config.physical_layout_name = board.board_name.clone();
+        config.logical_layout_name = "US_English".to_string();
+        config.layer0 = board.initial_id_map.clone();
+        config.layer0.insert(address(0x39), Some(0xE0));
+        config.layer0.insert(address(0xE4), Some(0xE8));
+        config.macro_key_map.insert(0xE8, macro_key_from(0x06, [true, false, false, false, false, false, false, false]));
+        config.layer1 = config.layer0.clone();
+        config.tp_sensitivity = default_tp_sensitivity();
+        config.enable_middle_click = true;
+
+        let xkb = export_os_remap(OsRemapFormat::Xkb, &config, board, &general_setting);
+        assert!(xkb.text.contains("key <CAPS> { [ Control_L ] };"), "{}", xkb.text);
+        assert!(xkb.text.contains("modifier_map Control { <CAPS> };"));
+        assert_eq!(xkb.unsupported.len(), 2, "{:?}", xkb.unsupported);
+
+        let ahk = export_os_remap(OsRemapFormat::AutoHotkey, &config, board, &general_setting);
+        assert!(ahk.text.contains("CapsLock::LCtrl"), "{}", ahk.text);
+        assert!(ahk.text.contains("RCtrl::Send \"{LCtrl down}{sc02E}{LCtrl up}\""), "{}", ahk.text);
+        assert_eq!(ahk.unsupported.len(), 1);
+
+        let karabiner = export_os_remap(OsRemapFormat::Karabiner, &config, board, &general_setting);
+        let rules: Value = serde_json::from_str(&karabiner.text).unwrap();
+        let manipulators = rules["rules"][0]["manipulators"].as_array().unwrap();
+        assert_eq!(manipulators.len(), 2);
+        let caps = manipulators.iter().find(|m| m["from"]["key_code"] == "caps_lock").unwrap();
+        assert_eq!(caps["to"][0]["key_code"], "left_control");
+        let macro_key = manipulators.iter().find(|m| m["from"]["key_code"] == "right_control").unwrap();
+        assert_eq!(macro_key["to"][0]["modifiers"], json!(["left_control"]));
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value, json, from_reader};
+
+use crate::models::Config;
+use crate::utils::config::{ConfigError, CONFIG_VERSION, check_config_version, migrate_config};
+
+/// Field naming the base config of an overlay, relative to the overlay file.
+pub const EXTEND_KEY: &str = "extend";
+
+// Fields merged key by key instead of being replaced as a whole
+const MERGED_FIELDS: [&str; 4] = ["layer0", "layer1", "macro_key_map", "media_key_map"];
+
+/// Base of an overlay config, kept so that saving writes only the overrides.
+#[derive(Clone, PartialEq, Debug)]
+pub struct OverlayBase {
+    /// `extend` as written in the overlay file
+    pub extend: String,
+    /// Canonical path of the base file
+    pub base_path: PathBuf,
+    /// Fully resolved base config
+    pub base: Config,
+}
+
+/// Config file resolved through its `extend` chain.
+pub struct ResolvedConfig {
+    /// Complete config at [`CONFIG_VERSION`]
+    pub value: Value,
+    /// Version found in the file itself
+    pub original_version: u32,
+    pub overlay: Option<OverlayBase>,
+}
+
+/// Read a JSON config, following `extend` to its base configs.
+/// Plain configs are migrated; overlays are applied on top of their migrated base.
+pub fn resolve_config_file(filepath: &Path) -> Result<ResolvedConfig, ConfigError> {
+    resolve(filepath, &mut Vec::new())
+}
+
+fn resolve(filepath: &Path, visited: &mut Vec<PathBuf>) -> Result<ResolvedConfig, ConfigError> {
+    let canonical = filepath.canonicalize()?;
+    if visited.contains(&canonical) {
+        return Err(ConfigError::Overlay(format!("{} extends itself", filepath.display())));
+    }
+    visited.push(canonical);
+
+    let mut value: Value = from_reader(BufReader::new(File::open(filepath)?))?;
+    let overlay = value.as_object_mut().ok_or(ConfigError::NotAnObject)?;
+    let Some(extend) = overlay.remove(EXTEND_KEY) else {
+        let original_version = migrate_config(&mut value)?;
+        return Ok(ResolvedConfig { value, original_version, overlay: None });
+    };
+    let extend = extend
+        .as_str()
+        .ok_or_else(|| ConfigError::Overlay(format!("'{}' must be a file path", EXTEND_KEY)))?
+        .to_string();
+    let original_version = check_config_version(overlay)?;
+
+    let base_path = filepath.parent().unwrap_or(Path::new(".")).join(&extend);
+    let mut resolved = resolve(&base_path, visited)
+        .map_err(|e| ConfigError::Overlay(format!("Failed to load base {}: {}", base_path.display(), e)))?;
+    let base: Config = serde_json::from_value(resolved.value.clone())?;
+    // A misspelt field would otherwise be dropped silently and the base value used
+    let fields = serde_json::to_value(&base)?;
+    if let Some(unknown) = overlay.keys().find(|field| fields.get(*field).is_none()) {
+        return Err(ConfigError::Overlay(format!(
+            "{}: unknown field '{}'",
+            filepath.display(),
+            unknown
+        )));
+    }
+    apply_overlay(&mut resolved.value, overlay);
+    Ok(ResolvedConfig {
+        value: resolved.value,
+        original_version,
+        overlay: Some(OverlayBase { extend, base_path: base_path.canonicalize()?, base }),
+    })
+}
+
+fn apply_overlay(base: &mut Value, overlay: &Map<String, Value>) {
+    let Some(base) = base.as_object_mut() else {
+        return;
+    };
+    for (field, value) in overlay {
+        if field == "config_version" {
+            continue;
+        }
+        match (base.get_mut(field), value) {
+            (Some(Value::Object(base_map)), Value::Object(overrides)) if MERGED_FIELDS.contains(&field.as_str()) => {
+                for (k, v) in overrides {
+                    base_map.insert(k.clone(), v.clone());
+                }
+            }
+            _ => {
+                base.insert(field.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl OverlayBase {
+    /// `extend` for an overlay saved at `filepath`: kept as written when it still
+    /// points to the same base, otherwise the absolute base path.
+    pub fn extend_from(&self, filepath: &Path) -> String {
+        let dir = filepath.parent().unwrap_or(Path::new("."));
+        match dir.join(&self.extend).canonicalize() {
+            Ok(path) if path == self.base_path => self.extend.clone(),
+            _ => self.base_path.display().to_string(),
+        }
+    }
+}
+
+/// Overlay of `config` saved at `filepath` on top of `overlay.base`, containing only the overrides.
+/// Entries missing from `config` but present in the base cannot be expressed and are kept.
+pub fn overlay_value(filepath: &Path, config: &Config, overlay: &OverlayBase) -> Result<Value, ConfigError> {
+    let full = serde_json::to_value(config)?;
+    let base = serde_json::to_value(&overlay.base)?;
+    let mut out = Map::new();
+    out.insert("config_version".into(), json!(CONFIG_VERSION));
+    out.insert(EXTEND_KEY.into(), json!(overlay.extend_from(filepath)));
+    if let (Value::Object(full), Value::Object(base)) = (full, base) {
+        for (field, value) in full {
+            if field == "config_version" || base.get(&field) == Some(&value) {
+                continue;
+            }
+            match (&value, base.get(&field)) {
+                (Value::Object(map), Some(Value::Object(base_map))) if MERGED_FIELDS.contains(&field.as_str()) => {
+                    let changed: Map<String, Value> = map
+                        .iter()
+                        .filter(|(k, v)| base_map.get(*k) != Some(*v))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect();
+                    out.insert(field, Value::Object(changed));
+                }
+                _ => {
+                    out.insert(field, value);
+                }
+            }
+        }
+    }
+    Ok(Value::Object(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let base_path = dir.join("base.json");
+        std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/Dvorak.json"), &base_path).unwrap();
+        let base: Config = serde_json::from_reader(File::open(&base_path).unwrap()).unwrap();
+
+        let overlay_path = dir.join("mine.json");
+        std::fs::write(
+            &overlay_path,
+            json!({ "extend": "base.json", "tp_sensitivity": 5, "layer1": { "16": 4 } }).to_string(),
+        )
+        .unwrap();
+        let resolved = resolve_config_file(&overlay_path).unwrap();
+        let config: Config = serde_json::from_value(resolved.value).unwrap();
+        assert_eq!(config.tp_sensitivity, 5);
+        assert_eq!(config.layer1.get(&16), Some(&Some(4)));
+        assert_eq!(config.layer0, base.layer0);
+
+        let overlay = resolved.overlay.unwrap();
+        let value = overlay_value(&overlay_path, &config, &overlay).unwrap();
+        assert_eq!(value[EXTEND_KEY], json!("base.json"));
+        assert_eq!(value["layer1"], json!({ "16": 4 }));
+        assert!(value.get("layer0").is_none());
+
+        std::fs::write(&overlay_path, json!({ "extend": "base.json", "tp_sensitivty": 5 }).to_string()).unwrap();
+        match resolve_config_file(&overlay_path) {
+            Err(ConfigError::Overlay(msg)) => assert!(msg.contains("'tp_sensitivty'"), "{}", msg),
+            other => panic!("unknown field accepted: {:?}", other.map(|r| r.value)),
+        }
+
+        std::fs::write(&base_path, json!({ "extend": "mine.json" }).to_string()).unwrap();
+        assert!(matches!(resolve_config_file(&overlay_path), Err(ConfigError::Overlay(_))));
+    }
+}
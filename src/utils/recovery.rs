@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::utils::flasher::{
+    FlashError, FlashImage, Flasher, Transport, ALL_ERASED_EXPECTED_CHECKSUM, DEFAULT_DEVICE_IDS,
+};
+use crate::utils::installer::extract_fw_from_installer_to_vec;
+use crate::utils::history::{HistoryEntry, record_flash};
+use crate::utils::usb::{UsbTransport, find_single_device};
+
+/// State of a keyboard as seen through the flasher protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceState {
+    /// Application firmware is running; nothing to recover.
+    Keyboard,
+    /// Stuck in the bootloader. `checksum` covers words 0x0000..=0x27ff and
+    /// is only known once the flash is unlocked.
+    Flasher { checksum: Option<u16>, application: ApplicationState },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApplicationState {
+    /// Flash is locked; detection leaves it so, recovery unlocks it
+    Locked,
+    /// Application area erased, canary gone
+    Erased,
+    /// Checksum matches the stock image
+    Stock,
+    /// Partially programmed or some other image
+    Unknown,
+}
+
+impl ApplicationState {
+    /// Whether the canary word at 0x27ff is programmed. The bootloader has no
+    /// read command, so it follows from the checksum: it is the last word
+    /// written, present in a complete stock image and absent once erased.
+    pub fn canary(&self) -> Option<bool> {
+        match self {
+            ApplicationState::Erased => Some(false),
+            ApplicationState::Stock => Some(true),
+            ApplicationState::Locked | ApplicationState::Unknown => None,
+        }
+    }
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceState::Keyboard => write!(f, "Keyboard is running its application firmware. No recovery needed."),
+            DeviceState::Flasher { checksum, application } => {
+                let detail = match application {
+                    ApplicationState::Locked => "flash is locked, its contents are checked when recovering",
+                    ApplicationState::Erased => "application area is erased",
+                    ApplicationState::Stock => "application area holds the stock firmware",
+                    ApplicationState::Unknown => "application area is incomplete or unknown",
+                };
+                write!(f, "Keyboard is in flasher mode: {}", detail)?;
+                match application.canary() {
+                    Some(true) => write!(f, ", canary present")?,
+                    Some(false) => write!(f, ", canary missing")?,
+                    None => {}
+                }
+                match checksum {
+                    Some(checksum) => write!(f, " (checksum {:#06x}).", checksum),
+                    None => write!(f, "."),
+                }
+            }
+        }
+    }
+}
+
+fn classify(checksum: u16, stock: &FlashImage) -> DeviceState {
+    let application = if checksum == ALL_ERASED_EXPECTED_CHECKSUM {
+        ApplicationState::Erased
+    } else if checksum == stock.expected_checksum() {
+        ApplicationState::Stock
+    } else {
+        ApplicationState::Unknown
+    };
+    DeviceState::Flasher { checksum: Some(checksum), application }
+}
+
+/// Probe without side effects: only a keyboard already in flasher mode
+/// answers, and a locked flash is reported as such rather than unlocked.
+pub fn detect_state<T: Transport>(flasher: &mut Flasher<T>, stock: &FlashImage) -> Result<DeviceState, FlashError> {
+    let unlocked = match flasher.get_flash_unlock_state() {
+        Ok(state) => state,
+        Err(FlashError::UnexpectedResponse(_)) => return Ok(DeviceState::Keyboard),
+        Err(e) => return Err(e),
+    };
+    if !unlocked {
+        return Ok(DeviceState::Flasher { checksum: None, application: ApplicationState::Locked });
+    }
+    Ok(classify(flasher.get_checksum()?, stock))
+}
+
+/// Re-flash the stock image onto a keyboard stuck in flasher mode.
+/// `Flasher::flash` verifies the checksum and reboots the keyboard on success.
+/// Returns the state found before flashing.
+pub fn recover<T: Transport>(flasher: &mut Flasher<T>, stock: &FlashImage) -> Result<DeviceState, FlashError> {
+    let mut state = detect_state(flasher, stock)?;
+    match state {
+        DeviceState::Keyboard => return Ok(state),
+        DeviceState::Flasher { application: ApplicationState::Locked, .. } => {
+            flasher.unlock_flash()?;
+            state = classify(flasher.get_checksum()?, stock);
+        }
+        DeviceState::Flasher { .. } => {}
+    }
+    flasher.flash(stock)?;
+    Ok(state)
+}
+
+/// Stock image from the official installer.
+pub fn stock_image_from_installer(installer: &[u8]) -> Result<FlashImage, String> {
+    let stock = extract_fw_from_installer_to_vec(installer)?;
+    FlashImage::from_bytes(&stock).map_err(|e| e.to_string())
+}
+
+/// State of the single connected keyboard and its identity in the flash history.
+pub fn detect_usb_device_state(installer: &[u8]) -> Result<(DeviceState, String), String> {
+    let stock = stock_image_from_installer(installer)?;
+    let device = find_single_device(&DEFAULT_DEVICE_IDS).map_err(|e| e.to_string())?;
+    let transport = UsbTransport::open(&device).map_err(|e| e.to_string())?;
+    let state = detect_state(&mut Flasher::new(transport), &stock).map_err(|e| e.to_string())?;
+    Ok((state, device.identity()))
+}
+
+pub fn recover_usb_device(installer: &[u8]) -> Result<String, String> {
+    let stock_bytes = extract_fw_from_installer_to_vec(installer)?;
+    let stock = FlashImage::from_bytes(&stock_bytes).map_err(|e| e.to_string())?;
+    let device = find_single_device(&DEFAULT_DEVICE_IDS).map_err(|e| e.to_string())?;
+    let transport = UsbTransport::open(&device).map_err(|e| e.to_string())?;
+    let result = recover(&mut Flasher::new(transport), &stock).map_err(|e| format!("Recovery failed: {}", e));
+    if !matches!(result, Ok(DeviceState::Keyboard)) {
+        let flashed = result.as_ref().map(|_| ()).map_err(|e| e.clone());
+        let entry = HistoryEntry::new(&device.identity(), None, installer, &stock_bytes, &flashed);
+        if let Err(e) = record_flash(&entry, &stock_bytes) {
+            eprintln!("{}", e);
+        }
+    }
+    match result? {
+        DeviceState::Keyboard => Ok(DeviceState::Keyboard.to_string()),
+        _ => Ok("Stock firmware restored and verified. The keyboard is rebooting.".into()),
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+use rusb::{Context, DeviceHandle, Direction, Recipient, RequestType, UsbContext, request_type};
+
+use crate::utils::flasher::{FlashError, Transport};
+
+const USB_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl From<rusb::Error> for FlashError {
+    fn from(e: rusb::Error) -> FlashError {
+        match e {
+            rusb::Error::Timeout => FlashError::Timeout,
+            rusb::Error::NoDevice => FlashError::Disconnected,
+            rusb::Error::Access => FlashError::Usb("permission denied opening device".into()),
+            other => FlashError::Usb(other.to_string()),
+        }
+    }
+}
+
+/// Keyboard found on the bus, opened by bus number and device address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsbDeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus: u8,
+    pub address: u8,
+    /// `None` when the device has none or cannot be opened
+    pub serial: Option<String>,
+}
+
+impl UsbDeviceInfo {
+    /// Name of the keyboard in the flash history. Unlike the bus address it
+    /// survives replugging.
+    pub fn identity(&self) -> String {
+        match &self.serial {
+            Some(serial) => format!("{:04x}:{:04x} {}", self.vendor_id, self.product_id, serial),
+            None => format!("{:04x}:{:04x}", self.vendor_id, self.product_id),
+        }
+    }
+}
+
+impl std::fmt::Display for UsbDeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x} at {:02}:{:03}", self.vendor_id, self.product_id, self.bus, self.address)
+    }
+}
+
+/// List connected devices matching any of the vendor:product pairs.
+pub fn find_devices(ids: &[(u16, u16)]) -> Result<Vec<UsbDeviceInfo>, FlashError> {
+    let context = Context::new()?;
+    let mut found = Vec::new();
+    for device in context.devices()?.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        if ids.contains(&(desc.vendor_id(), desc.product_id())) {
+            found.push(UsbDeviceInfo {
+                vendor_id: desc.vendor_id(),
+                product_id: desc.product_id(),
+                bus: device.bus_number(),
+                address: device.address(),
+                serial: device
+                    .open()
+                    .and_then(|handle| handle.read_serial_number_string_ascii(&desc))
+                    .ok()
+                    .filter(|serial| !serial.trim().is_empty()),
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// The only connected device matching `ids`.
+pub fn find_single_device(ids: &[(u16, u16)]) -> Result<UsbDeviceInfo, FlashError> {
+    let mut found = find_devices(ids)?;
+    if found.len() != 1 {
+        return Err(FlashError::Usb(format!("{} device(s) found", found.len())));
+    }
+    Ok(found.remove(0))
+}
+
+/// [`Transport`] over a real USB device handle.
+pub struct UsbTransport {
+    handle: DeviceHandle<Context>,
+}
+
+impl UsbTransport {
+    pub fn open(info: &UsbDeviceInfo) -> Result<UsbTransport, FlashError> {
+        let context = Context::new()?;
+        let device = context
+            .devices()?
+            .iter()
+            .find(|d| d.bus_number() == info.bus && d.address() == info.address)
+            .ok_or(FlashError::Disconnected)?;
+        let handle = device.open()?;
+
+        match handle.active_configuration() {
+            Ok(config) if config > 0 => {
+                let interfaces = device.active_config_descriptor()?.num_interfaces();
+                for iface in 0..interfaces {
+                    // Not supported on every platform
+                    let _ = handle.set_auto_detach_kernel_driver(true);
+                    handle.claim_interface(iface)?;
+                }
+            }
+            _ => {
+                handle.set_active_configuration(1)?;
+                handle.claim_interface(0)?;
+            }
+        }
+        println!("Using device {}", info);
+        Ok(UsbTransport { handle })
+    }
+}
+
+impl Transport for UsbTransport {
+    fn control_write(&mut self, request: u8, value: u16, index: u16, data: &[u8]) -> Result<(), FlashError> {
+        let rt = request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle.write_control(rt, request, value, index, data, USB_TIMEOUT)?;
+        Ok(())
+    }
+
+    fn control_read(&mut self, request: u8, value: u16, index: u16, length: usize) -> Result<Vec<u8>, FlashError> {
+        let rt = request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let mut buf = vec![0u8; length];
+        let n = self.handle.read_control(rt, request, value, index, &mut buf, USB_TIMEOUT)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+}